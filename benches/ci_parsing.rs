@@ -0,0 +1,131 @@
+//! Benchmarks for turning CI configs into tarpaulin commands, using the fixtures in
+//! `tests/fixtures` along with a generated workflow far bigger than most projects have.
+//!
+//! Baseline on a single core x86_64 linux VM, so expect some noise:
+//!
+//! | Benchmark                 | Time    |
+//! |---------------------------|---------|
+//! | github/load_workflow      | 58 µs   |
+//! | github/read_workflow      | 40 µs   |
+//! | github/get_command        | 121 µs  |
+//! | github/get_command_large  | 1.6 ms  |
+//! | gitlab/get_command        | 234 µs  |
+//! | travis/get_command        | 105 µs  |
+//!
+//! Run with `cargo bench --bench ci_parsing`, criterion compares against the previous run.
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::fixture;
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use tater::ci::{github, gitlab, travis, ProviderInput};
+use tater::{Context, CrateSpec};
+
+/// Jobs in the generated workflow, each is 23 lines so it's over 900 lines long
+const LARGE_WORKFLOW_JOBS: usize = 40;
+
+/// A workflow with lots of matrix jobs that build and lint, with the tests in the last one
+fn large_workflow() -> PathBuf {
+    let mut yaml =
+        "name: CI\non: [push, pull_request]\nenv:\n  CARGO_TERM_COLOR: always\njobs:\n".to_string();
+    for i in 0..LARGE_WORKFLOW_JOBS {
+        write!(
+            yaml,
+            "  build-{i}:
+    name: Build {i} ${{{{ matrix.rust }}}} on ${{{{ matrix.os }}}}
+    strategy:
+      matrix:
+        os: [ubuntu-latest, macos-latest, windows-latest]
+        rust: [stable, beta, nightly]
+    runs-on: ${{{{ matrix.os }}}}
+    steps:
+      - uses: actions/checkout@v3
+      - uses: dtolnay/rust-toolchain@master
+        with:
+          toolchain: ${{{{ matrix.rust }}}}
+      - uses: Swatinem/rust-cache@v2
+      - name: Build
+        run: cargo build --workspace --features feature-{i}
+      - name: Clippy
+        run: cargo clippy --workspace --features feature-{i} -- -D warnings
+      - name: Docs
+        run: |
+          cargo doc --no-deps \\
+            --features feature-{i}
+      - name: Format
+        run: cargo fmt --all -- --check
+",
+            i = i
+        )
+        .unwrap();
+    }
+    yaml.push_str(
+        "  test:
+    runs-on: ubuntu-latest
+    steps:
+      - uses: actions/checkout@v3
+      - name: Test
+        run: cargo test --workspace --all-features -- --test-threads 1
+",
+    );
+    let root = std::env::temp_dir().join(format!("tater-bench-large-{}", std::process::id()));
+    let workflows = root.join(".github/workflows");
+    fs::create_dir_all(&workflows).unwrap();
+    fs::write(workflows.join("ci.yml"), yaml).unwrap();
+    root
+}
+
+fn ci_parsing(c: &mut Criterion) {
+    let context = Context::default();
+    let spec: CrateSpec =
+        serde_json::from_str(r#"{ "repository_url": "https://github.com/xd009642/tater" }"#)
+            .unwrap();
+    let input = |root| ProviderInput {
+        root,
+        jobs: None,
+        context: &context,
+        spec: &spec,
+    };
+
+    let github_root = fixture("hyper");
+    let github = input(&github_root);
+    let workflow_path = github_root.join(".github/workflows/ci.yml");
+    c.bench_function("github/load_workflow", |b| {
+        b.iter(|| github::load_workflow(&workflow_path).unwrap())
+    });
+    let workflow = github::load_workflow(&workflow_path).unwrap();
+    c.bench_function("github/read_workflow", |b| {
+        b.iter(|| {
+            let mut cmd = github.command();
+            github::read_workflow(&github, &workflow, &mut cmd).unwrap();
+            cmd
+        })
+    });
+    c.bench_function("github/get_command", |b| {
+        b.iter(|| github::get_command(&github).unwrap())
+    });
+    let large_root = large_workflow();
+    let large = input(&large_root);
+    c.bench_function("github/get_command_large", |b| {
+        b.iter(|| github::get_command(&large).unwrap())
+    });
+
+    let gitlab_root = fixture("vcpkg");
+    let gitlab = input(&gitlab_root);
+    c.bench_function("gitlab/get_command", |b| {
+        b.iter(|| gitlab::get_command(&gitlab).unwrap())
+    });
+    let travis_root = fixture("clap");
+    let travis = input(&travis_root);
+    c.bench_function("travis/get_command", |b| {
+        b.iter(|| travis::get_command(&travis).unwrap())
+    });
+
+    let _ = fs::remove_dir_all(&large_root);
+}
+
+criterion_group!(benches, ci_parsing);
+criterion_main!(benches);
@@ -0,0 +1,314 @@
+use crate::ci;
+use crate::report::*;
+use crate::runner::*;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{create_dir_all, remove_dir_all, File};
+use std::path::Path;
+use structopt::StructOpt;
+use tracing::{error, info, warn};
+
+/// Sparse crates.io index entry for cargo-tarpaulin
+const INDEX_URL: &str = "https://index.crates.io/ca/rg/cargo-tarpaulin";
+
+/// Directory in the output directory for bisect results, kept out of `results` so it's never taken
+/// for a run
+pub const BISECT_DIR: &str = "bisect";
+
+#[derive(Debug, Clone, PartialEq, StructOpt)]
+pub struct BisectArgs {
+    /// Name of the crate in the repos file to bisect
+    #[structopt(long = "crate")]
+    pub krate: String,
+    /// A tarpaulin version the crate passes with
+    #[structopt(long = "good")]
+    pub good: String,
+    /// A tarpaulin version the crate fails with
+    #[structopt(long = "bad")]
+    pub bad: String,
+}
+
+/// The outcome of testing one tarpaulin version
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BisectStep {
+    pub version: String,
+    pub status: Status,
+}
+
+/// Written to `bisect/<crate>/bisect.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BisectReport {
+    pub good: String,
+    pub bad: String,
+    /// The first version the crate fails with
+    pub first_bad: Option<String>,
+    pub steps: Vec<BisectStep>,
+}
+
+#[derive(Deserialize)]
+struct IndexEntry {
+    vers: String,
+    #[serde(default)]
+    yanked: bool,
+}
+
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.split('.').map(|x| x.parse().ok());
+    match (parts.next()?, parts.next()?, parts.next()?, parts.next()) {
+        (Some(major), Some(minor), Some(patch), None) => Some((major, minor, patch)),
+        _ => None,
+    }
+}
+
+/// Gets the released versions from the index between `good` and `bad` inclusive in version
+/// order, yanked and pre-release versions are left out
+pub fn versions_between(index: &str, good: &str, bad: &str) -> Result<Vec<String>, String> {
+    let good_ver = parse_version(good).ok_or(format!("Invalid version {}", good))?;
+    let bad_ver = parse_version(bad).ok_or(format!("Invalid version {}", bad))?;
+    if good_ver >= bad_ver {
+        return Err(format!(
+            "The good version {} has to be older than the bad version {}",
+            good, bad
+        ));
+    }
+    let mut versions = index
+        .lines()
+        .filter_map(|line| serde_json::from_str::<IndexEntry>(line).ok())
+        .filter(|x| !x.yanked)
+        .filter_map(|x| parse_version(&x.vers).map(|v| (v, x.vers)))
+        .filter(|(v, _)| *v >= good_ver && *v <= bad_ver)
+        .collect::<Vec<_>>();
+    versions.sort();
+    let versions = versions.into_iter().map(|(_, x)| x).collect::<Vec<_>>();
+    if versions.first().map(String::as_str) != Some(good) {
+        Err(format!("Version {} not found in the index", good))
+    } else if versions.last().map(String::as_str) != Some(bad) {
+        Err(format!("Version {} not found in the index", bad))
+    } else {
+        Ok(versions)
+    }
+}
+
+/// Binary searches for the first bad version, assuming the first version is good and the last is
+/// bad. Returns the index of the first bad version
+pub fn first_bad(versions: &[String], mut is_bad: impl FnMut(&str) -> bool) -> usize {
+    let mut good = 0;
+    let mut bad = versions.len() - 1;
+    while bad - good > 1 {
+        let mid = good + (bad - good) / 2;
+        if is_bad(&versions[mid]) {
+            bad = mid;
+        } else {
+            good = mid;
+        }
+    }
+    bad
+}
+
+#[cfg(feature = "remote")]
+fn fetch_index() -> Result<String, String> {
+    ureq::get(INDEX_URL)
+        .call()
+        .map_err(|e| e.to_string())
+        .and_then(|x| x.into_string().map_err(|e| e.to_string()))
+        .map_err(|e| format!("Failed to fetch {}: {}", INDEX_URL, e))
+}
+
+#[cfg(not(feature = "remote"))]
+fn fetch_index() -> Result<String, String> {
+    Err(format!(
+        "can't fetch {} as tater was built without the `remote` feature",
+        INDEX_URL
+    ))
+}
+
+/// Installs a tarpaulin version into its own directory, reusing a previous install. A failed
+/// install is removed so it's tried again next time
+fn install_tarpaulin(context: &Context, root: &Path, version: &str) -> Result<(), String> {
+    let binary = root
+        .join("bin")
+        .join(format!("cargo-tarpaulin{}", env::consts::EXE_SUFFIX));
+    if binary.is_file() {
+        info!("cargo-tarpaulin {} already installed", version);
+        return Ok(());
+    }
+    info!("Installing cargo-tarpaulin {}", version);
+    let res = new_command("cargo")
+        .args(ci::tarpaulin_install_args(context))
+        .args(["--locked", "--version"])
+        .arg(format!("={}", version))
+        .arg("--root")
+        .arg(root)
+        .output()
+        .map_err(|e| format!("Failed to run cargo install: {}", e))
+        .and_then(|output| {
+            if output.status.success() && binary.is_file() {
+                Ok(())
+            } else {
+                Err(format!(
+                    "Failed to install cargo-tarpaulin {}: {}",
+                    version,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ))
+            }
+        });
+    if res.is_err() {
+        let _ = remove_dir_all(root);
+    }
+    res
+}
+
+/// Runs the crate with a version of tarpaulin, the version is installed to a temporary
+/// directory which is put first in the `PATH` so cargo picks it over any installed tarpaulin
+fn run_version(
+    context: &Context,
+    spec: &CrateSpec,
+    jobs: Option<usize>,
+    output: &Path,
+    version: &str,
+) -> Status {
+    let name = spec.name().unwrap_or("unnamed_project");
+    let root = env::temp_dir().join(format!("tater-tarpaulin-{}", version));
+    if let Err(e) = install_tarpaulin(context, &root, version) {
+        error!("{}", e);
+        return Status::Fail;
+    }
+    let existing = env::var_os("PATH").unwrap_or_default();
+    let path =
+        env::join_paths(std::iter::once(root.join("bin")).chain(env::split_paths(&existing)))
+            .unwrap_or(existing);
+    let mut spec = spec.clone();
+    spec.env
+        .insert("PATH".to_string(), path.to_string_lossy().to_string());
+
+    let proj_res = output.join(BISECT_DIR).join(name).join(version);
+    let index = context
+        .crates
+        .iter()
+        .position(|x| x.name() == Some(name))
+        .unwrap_or_default();
+    let outcome = run_test(
+        index,
+        context,
+        &spec,
+        jobs,
+        &output.join("projects"),
+        &proj_res,
+    );
+    let result = ProjectResult::new(context, &spec, &outcome);
+    write_result(&proj_res.join(RESULT_FILE), &result);
+    info!("{} with tarpaulin {}: {}", name, version, result.status);
+    result.status
+}
+
+/// Finds the first tarpaulin version a crate fails with, the report and logs for each version
+/// tested are written to `bisect/<crate>/`
+pub fn bisect(
+    context: &Context,
+    args: &BisectArgs,
+    jobs: Option<usize>,
+    output: &Path,
+) -> Result<BisectReport, String> {
+    if context.engine == Engine::Docker {
+        return Err("Bisecting isn't supported with the docker engine".to_string());
+    }
+    let spec = context
+        .crates
+        .iter()
+        .find(|x| x.name() == Some(args.krate.as_str()))
+        .ok_or(format!("{} isn't in the repos file", args.krate))?;
+    let versions = versions_between(&fetch_index()?, &args.good, &args.bad)?;
+    info!(
+        "Bisecting {} over {} tarpaulin versions",
+        args.krate,
+        versions.len()
+    );
+    let _ = create_dir_all(output.join("projects"));
+    // Every version needs to build the project from scratch, and the installed version has to be
+    // the one ran
+    let context = &Context {
+        keep_target: false,
+        shared_target_dir: None,
+        sccache: false,
+        tarpaulin_path: None,
+        ..context.clone()
+    };
+
+    let mut steps = vec![];
+    let mut run = |version: &str| {
+        let status = run_version(context, spec, jobs, output, version);
+        steps.push(BisectStep {
+            version: version.to_string(),
+            status,
+        });
+        status
+    };
+    // Otherwise the search would blame whichever end is wrong
+    let good_status = run(&args.good);
+    if good_status != Status::Pass {
+        return Err(format!(
+            "{} doesn't pass with the good version {} ({})",
+            args.krate, args.good, good_status
+        ));
+    }
+    let bad_status = run(&args.bad);
+    if bad_status == Status::Pass {
+        return Err(format!(
+            "{} passes with the bad version {}",
+            args.krate, args.bad
+        ));
+    }
+    let first = first_bad(&versions, |version| run(version) != Status::Pass);
+    let report = BisectReport {
+        good: args.good.clone(),
+        bad: args.bad.clone(),
+        first_bad: versions.get(first).cloned(),
+        steps,
+    };
+    let path = output
+        .join(BISECT_DIR)
+        .join(&args.krate)
+        .join("bisect.json");
+    let _ = create_dir_all(path.parent().unwrap());
+    match File::create(&path) {
+        Ok(f) => {
+            if let Err(e) = serde_json::to_writer_pretty(f, &report) {
+                warn!("Failed to write {}: {}", path.display(), e);
+            }
+        }
+        Err(e) => warn!("Failed to create {}: {}", path.display(), e),
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bisects_versions() {
+        let index = r#"{"name":"cargo-tarpaulin","vers":"0.24.0","yanked":false}
+{"name":"cargo-tarpaulin","vers":"0.25.0","yanked":false}
+{"name":"cargo-tarpaulin","vers":"0.26.0-alpha","yanked":false}
+{"name":"cargo-tarpaulin","vers":"0.25.1","yanked":true}
+{"name":"cargo-tarpaulin","vers":"0.25.2","yanked":false}
+{"name":"cargo-tarpaulin","vers":"0.26.0","yanked":false}
+{"name":"cargo-tarpaulin","vers":"0.26.1","yanked":false}
+{"name":"cargo-tarpaulin","vers":"0.27.2","yanked":false}"#;
+
+        let versions = versions_between(index, "0.25.0", "0.27.2").unwrap();
+        assert_eq!(versions, ["0.25.0", "0.25.2", "0.26.0", "0.26.1", "0.27.2"]);
+        assert!(versions_between(index, "0.25.1", "0.27.2").is_err());
+        assert!(versions_between(index, "0.26.0", "0.26.0").is_err());
+        assert!(versions_between(index, "0.27.2", "0.25.0").is_err());
+
+        let mut tested = vec![];
+        let first = first_bad(&versions, |v| {
+            tested.push(v.to_string());
+            parse_version(v).unwrap() >= (0, 26, 1)
+        });
+        assert_eq!(versions[first], "0.26.1");
+        assert!(!tested.contains(&"0.25.0".to_string()));
+        assert!(!tested.contains(&"0.27.2".to_string()));
+    }
+}
@@ -0,0 +1,154 @@
+use std::env;
+use std::fs::{read_dir, remove_dir_all, remove_file, symlink_metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+pub const GIGABYTE: u64 = 1024 * 1024 * 1024;
+/// Directories in the cargo home counted towards the size of the cache
+const CACHE_DIRS: [&str; 2] = ["registry", "git"];
+/// Directories in the cargo home entries are pruned from, cargo extracts or downloads them again
+/// when they're needed
+const PRUNABLE_DIRS: [&str; 2] = ["registry/cache", "registry/src"];
+
+/// The cargo home the projects are built with, `CARGO_HOME` if it's set otherwise `~/.cargo`
+pub fn cargo_home() -> Option<PathBuf> {
+    env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")))
+}
+
+/// Total size of the files in a directory, symlinks aren't followed
+fn size(path: &Path) -> u64 {
+    match symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => read_dir(path)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|x| size(&x.path()))
+            .sum(),
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    }
+}
+
+/// Size of the registry and git caches in the cargo home
+pub fn cache_size(cargo_home: &Path) -> u64 {
+    CACHE_DIRS.iter().map(|x| size(&cargo_home.join(x))).sum()
+}
+
+/// Downloaded crates and extracted sources in the registry, oldest first. Each registry index has
+/// its own directory under `registry/cache` and `registry/src`
+fn prunable_entries(cargo_home: &Path) -> Vec<(PathBuf, SystemTime)> {
+    let mut entries = PRUNABLE_DIRS
+        .iter()
+        .flat_map(|x| read_dir(cargo_home.join(x)).into_iter().flatten().flatten())
+        .flat_map(|index| read_dir(index.path()).into_iter().flatten().flatten())
+        .filter_map(|x| {
+            let modified = x.metadata().and_then(|x| x.modified()).ok()?;
+            Some((x.path(), modified))
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|(_, modified)| *modified);
+    entries
+}
+
+pub fn format_gb(bytes: u64) -> String {
+    format!("{:.2}GB", bytes as f64 / GIGABYTE as f64)
+}
+
+/// Deletes the oldest registry entries until the cache is under `limit` bytes. This must only be
+/// called between projects, deleting sources while cargo is using them would break the build.
+/// Returns the size before and after if anything was pruned
+pub fn prune(cargo_home: &Path, limit: u64) -> io::Result<Option<(u64, u64)>> {
+    let before = cache_size(cargo_home);
+    if before <= limit {
+        return Ok(None);
+    }
+    let mut current = before;
+    for (entry, _) in prunable_entries(cargo_home) {
+        if current <= limit {
+            break;
+        }
+        let entry_size = size(&entry);
+        if entry.is_dir() {
+            remove_dir_all(&entry)?;
+        } else {
+            remove_file(&entry)?;
+        }
+        current = current.saturating_sub(entry_size);
+    }
+    Ok(Some((before, current)))
+}
+
+/// Prunes the cargo cache, logging what happened
+pub fn prune_cache(cargo_home: &Path, limit: u64) {
+    match prune(cargo_home, limit) {
+        Ok(Some((before, after))) => {
+            info!(
+                "Pruned cargo cache in {} from {} to {}",
+                cargo_home.display(),
+                format_gb(before),
+                format_gb(after)
+            );
+            if after > limit {
+                warn!(
+                    "Cargo cache is still over {} after pruning the registry",
+                    format_gb(limit)
+                );
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Failed to prune cargo cache: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_project;
+    use std::fs::{create_dir_all, write, File};
+    use std::time::Duration;
+
+    #[test]
+    fn oldest_pruned_first() {
+        let home = test_project("cargo-home", &[]);
+        let index = "index.crates.io-6f17d22bba15001f";
+        let cache = home.join("registry/cache").join(index);
+        let src = home.join("registry/src").join(index);
+        create_dir_all(&cache).unwrap();
+        create_dir_all(src.join("old-0.1.0/src")).unwrap();
+        create_dir_all(home.join("git/db")).unwrap();
+
+        let now = SystemTime::now();
+        let entry = |path: PathBuf, age: u64| {
+            let file = if path.is_dir() {
+                path.join("src/lib.rs")
+            } else {
+                path.clone()
+            };
+            write(&file, vec![0; 1000]).unwrap();
+            File::open(&path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(age))
+                .unwrap();
+            path
+        };
+        let old_src = entry(src.join("old-0.1.0"), 300);
+        let old_crate = entry(cache.join("old-0.1.0.crate"), 200);
+        let new_crate = entry(cache.join("new-0.2.0.crate"), 100);
+        write(home.join("git/db/pack"), vec![0; 1000]).unwrap();
+        assert_eq!(cache_size(&home), 4000);
+
+        assert_eq!(prune(&home, 5000).unwrap(), None);
+        assert_eq!(prune(&home, 2500).unwrap(), Some((4000, 2000)));
+        assert!(!old_src.exists());
+        assert!(!old_crate.exists());
+        assert!(new_crate.exists());
+
+        // Git checkouts aren't pruned so this can't get under the limit
+        assert_eq!(prune(&home, 500).unwrap(), Some((2000, 1000)));
+        assert!(!new_crate.exists());
+    }
+}
@@ -1,4 +1,5 @@
 #![allow(dead_code)]
+use crate::ci::types::SingleOrMultiString;
 use crate::ci::*;
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -7,7 +8,7 @@ use std::collections::HashMap;
 use std::fs;
 use std::io;
 use std::path::Path;
-use std::process::{Child, Command};
+use std::process::Command;
 use tracing::{debug, info, instrument, warn};
 
 /// The overall github actions workflow, look [here](https://docs.github.com/en/actions/learn-github-actions/workflow-syntax-for-github-actions) for
@@ -18,6 +19,7 @@ pub struct Workflow {
     jobs: HashMap<String, Job>,
     #[serde(default)]
     defaults: Defaults,
+    #[serde(default)]
     env: HashMap<String, serde_yaml::Value>,
 }
 
@@ -38,7 +40,7 @@ pub struct Job {
     #[serde(default)]
     name: String,
     #[serde(rename = "runs-on")]
-    runs_on: String,
+    runs_on: SingleOrMultiString,
     steps: Vec<Step>,
     #[serde(default)]
     strategy: Strategy,
@@ -68,6 +70,59 @@ pub struct Step {
     with: HashMap<String, serde_yaml::Value>,
     #[serde(default)]
     run: String,
+    /// Directory the step's script runs in, replaces the workflow's default
+    #[serde(default, rename = "working-directory")]
+    working_directory: Option<String>,
+    /// Expression deciding whether the step runs, empty if it always runs
+    #[serde(default, rename = "if")]
+    condition: String,
+}
+
+impl Step {
+    fn runs(&self, matrix: &HashMap<String, serde_yaml::Value>) -> bool {
+        self.condition.trim().is_empty() || evaluate_condition(&self.condition, matrix)
+    }
+
+    /// Directory the step runs in from the CI config, the step's own or the workflow's default
+    fn working_directory<'a>(&'a self, defaults: &'a Defaults) -> Option<&'a str> {
+        self.working_directory
+            .as_deref()
+            .or_else(|| defaults.working_directory())
+    }
+}
+
+/// Evaluates a step's `if:` for the matrix values tater picked. Only comparing `matrix.os` or
+/// `matrix.rust` to a string with `==` or `!=` is understood, anything else is assumed not to run
+/// so a step meant for another platform or event isn't used
+fn evaluate_condition(condition: &str, matrix: &HashMap<String, serde_yaml::Value>) -> bool {
+    let condition = condition.trim();
+    let condition = condition
+        .strip_prefix("${{")
+        .and_then(|x| x.strip_suffix("}}"))
+        .unwrap_or(condition);
+    let (lhs, rhs, equal) = match condition.split_once("!=") {
+        Some((lhs, rhs)) => (lhs.trim(), rhs.trim(), false),
+        None => match condition.split_once("==") {
+            Some((lhs, rhs)) => (lhs.trim(), rhs.trim(), true),
+            None => return false,
+        },
+    };
+    let literal = |x: &str| {
+        ['\'', '"']
+            .iter()
+            .find_map(|q| x.strip_prefix(*q).and_then(|x| x.strip_suffix(*q)))
+            .map(String::from)
+    };
+    let variable = |x: &str| match x.strip_prefix("matrix.") {
+        Some(key @ ("os" | "rust")) => matrix.get(key).map(value_string),
+        _ => None,
+    };
+    let (value, expected) = match (variable(lhs), literal(rhs), variable(rhs), literal(lhs)) {
+        (Some(value), Some(expected), ..) | (.., Some(value), Some(expected)) => (value, expected),
+        _ => return false,
+    };
+    // Github ignores case when comparing strings
+    value.eq_ignore_ascii_case(&expected) == equal
 }
 
 #[derive(Debug, PartialEq)]
@@ -81,7 +136,8 @@ fn value_string(val: &serde_yaml::Value) -> String {
     match val {
         Value::String(s) => s.clone(),
         Value::Number(n) => n.to_string(),
-        _ => todo!(),
+        Value::Bool(b) => b.to_string(),
+        _ => String::new(),
     }
 }
 
@@ -89,13 +145,59 @@ impl MatrixValue {
     fn data_str(&self) -> String {
         self.data
             .iter()
-            .map(|x| value_string(x))
+            .map(value_string)
             .collect::<Vec<_>>()
             .join(",")
     }
 }
 
+/// Runner labels for each OS
+const OS_LABELS: &[&str] = &["windows", "macos", "ubuntu", "linux"];
+
+/// Runner labels for the OS tater is running on
+fn host_labels() -> &'static [&'static str] {
+    if cfg!(windows) {
+        &["windows"]
+    } else if cfg!(target_os = "macos") {
+        &["macos"]
+    } else {
+        &["ubuntu", "linux"]
+    }
+}
+
 impl Job {
+    /// Whether the job runs on the OS tater is running on. Jobs picking the OS with an expression
+    /// like `${{ matrix.os }}` or only using custom labels could run anywhere
+    fn runs_on_host(&self) -> bool {
+        let labels = match &self.runs_on {
+            SingleOrMultiString::Single(label) => vec![label.to_lowercase()],
+            SingleOrMultiString::Multi(labels) => labels.iter().map(|x| x.to_lowercase()).collect(),
+        };
+        let is_os = |x: &String, os: &[&str]| os.iter().any(|os| x.starts_with(os));
+        labels
+            .iter()
+            .any(|x| x.contains("${{") || is_os(x, host_labels()))
+            || !labels.iter().any(|x| is_os(x, OS_LABELS))
+    }
+
+    /// The matrix values tater runs the job with, the first of each unless there's an OS
+    /// matching the one tater is running on
+    fn chosen_matrix(&self) -> HashMap<String, serde_yaml::Value> {
+        self.strategy
+            .matrix
+            .elements
+            .iter()
+            .filter_map(|(key, values)| {
+                let host = values.iter().find(|x| {
+                    let x = value_string(x).to_lowercase();
+                    key == "os" && host_labels().iter().any(|os| x.starts_with(os))
+                });
+                let value = host.or_else(|| values.first())?;
+                Some((key.clone(), value.clone()))
+            })
+            .collect()
+    }
+
     fn get_possible_matrix_values(&self, var: &str) -> Option<Vec<MatrixValue>> {
         // If it's not directly in the matrix elements then it will be defined by the include table
         // this is gonna be a bit wild
@@ -144,55 +246,85 @@ impl Job {
     }
 }
 
-fn find_job(file: &Path, name: &str) -> bool {
-    file.file_name()
-        .unwrap()
-        .to_string_lossy()
-        .to_lowercase()
-        .contains(name)
+/// Points for words in the workflow's file name, only the best match counts
+const NAME_HINTS: &[(&str, u32)] = &[("coverage", 40), ("test", 30), ("ci", 20), ("rust", 10)];
+
+/// How likely a workflow is to be the one running the tests, higher is more likely. Running
+/// tarpaulin is the strongest sign followed by running the tests, then the file name. The number of
+/// steps breaks ties as bigger workflows tend to be the main CI
+pub fn score_workflow(path: &Path, workflow: &Workflow) -> u32 {
+    let name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let name_score = NAME_HINTS
+        .iter()
+        .filter(|(hint, _)| name.contains(hint))
+        .map(|(_, score)| *score)
+        .max()
+        .unwrap_or(0);
+    let steps = || workflow.jobs.values().flat_map(|x| x.steps.iter());
+    let tarpaulin = steps()
+        .any(|x| x.uses.starts_with("actions-rs/tarpaulin") || x.run.contains("cargo tarpaulin"));
+    let tests = steps().any(|x| {
+        (x.uses.starts_with("actions-rs/cargo")
+            && x.with.get("command").and_then(|x| x.as_str()) == Some("test"))
+            || TEST_INVOCATION.is_match(&x.run)
+    });
+    let step_count = steps().count().min(10) as u32;
+    let mut score = name_score + step_count;
+    if tarpaulin {
+        score += 100;
+    }
+    if tests {
+        score += 50;
+    }
+    score
+}
+
+/// Parses a workflow file
+pub fn load_workflow(path: &Path) -> io::Result<Workflow> {
+    let workflow = fs::File::open(path)?;
+    serde_yaml::from_reader(workflow)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
 }
 
-pub fn get_command(
-    root: impl AsRef<Path>,
-    jobs: Option<&usize>,
-    context: &Context,
-    spec: &CrateSpec,
-) -> io::Result<Child> {
-    let workflows = root.as_ref().join(".github/workflows");
-    let workflows: Vec<_> = fs::read_dir(&workflows)?
+pub fn get_command(input: &ProviderInput) -> io::Result<Command> {
+    let workflows = input.root.join(".github/workflows");
+    let mut paths: Vec<_> = fs::read_dir(&workflows)?
         .filter_map(|x| x.ok())
         .map(|x| x.path())
         .filter(|x| x.is_file())
         .collect();
-
-    // First we look for one called coverage, then test, then ci. After that we go over all of them for
-    // the first one containing `cargo test` or `cargo tarpaulin` usage
-    let mut cmd = Command::new("cargo");
-    init_command(root.as_ref(), jobs, context, spec, &mut cmd);
-
-    if let Some(coverage) = workflows.iter().find(|x| find_job(x, "coverage")) {
-        read_workflow(root.as_ref(), coverage, &mut cmd)
-    } else if let Some(coverage) = workflows.iter().find(|x| find_job(x, "test")) {
-        read_workflow(root.as_ref(), coverage, &mut cmd)
-    } else if let Some(coverage) = workflows.iter().find(|x| find_job(x, "ci")) {
-        read_workflow(root.as_ref(), coverage, &mut cmd)
-    } else if let Some(coverage) = workflows.iter().find(|x| find_job(x, "rust")) {
-        read_workflow(root.as_ref(), coverage, &mut cmd)
-    } else {
-        // Dumb search
-        for coverage in &workflows {
-            if let Ok(c) = read_workflow(root.as_ref(), coverage, &mut cmd) {
-                return Ok(c);
+    // Sorted first so workflows with the same score are always tried in the same order
+    paths.sort();
+    let mut workflows = paths
+        .into_iter()
+        .filter_map(|path| match load_workflow(&path) {
+            Ok(workflow) => Some((score_workflow(&path, &workflow), path, workflow)),
+            Err(e) => {
+                warn!("Unable to read {}: {}", path.display(), e);
+                None
             }
+        })
+        .collect::<Vec<_>>();
+    workflows.sort_by_key(|(score, _, _)| std::cmp::Reverse(*score));
+
+    // The best scoring workflow is used, if no command can be made from it the next best is tried
+    let mut cmd = input.command();
+    for (score, path, workflow) in &workflows {
+        debug!("Processing workflow: {} (score {})", path.display(), score);
+        if read_workflow(input, workflow, &mut cmd).is_ok() {
+            return Ok(cmd);
         }
-        Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Didn't find valid github action",
-        ))
     }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "Didn't find valid github action",
+    ))
 }
 
-fn handle_tarpaulin_workflow(step: &Step, cmd: &mut Command) -> io::Result<Child> {
+fn handle_tarpaulin_workflow(step: &Step, context: &Context, cmd: &mut Command) {
     // Extract tarpaulin args and merge https://github.com/actions-rs/tarpaulin
     for (arg, val) in step
         .with
@@ -211,58 +343,85 @@ fn handle_tarpaulin_workflow(step: &Step, cmd: &mut Command) -> io::Result<Child
             }
             "out-type" => {
                 cmd.arg("--out");
+                cmd.args(val.split_whitespace());
             }
-            "args" | "version" => {
-                process_arg_string(cmd, &val);
-            }
+            "args" | "version" => match process_arg_string(val, context) {
+                Ok(args) => {
+                    cmd.args(args);
+                }
+                Err(e) => warn!("Unable to use args '{}': {}", val, e),
+            },
             e => warn!("Unexpected with field: {}", e),
         }
     }
-    info!("Spawning: {:?}", cmd);
-    return cmd.spawn();
 }
 
-#[instrument(skip(root, cmd))]
-fn read_workflow(root: &Path, workflow: &Path, cmd: &mut Command) -> io::Result<Child> {
-    debug!("Processing workflow: {}", workflow.display());
-    let workflow = fs::File::open(workflow)?;
-    let workflow: Workflow = serde_yaml::from_reader(workflow)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
-
-    for (_name, job) in &workflow.jobs {
-        if let Some(step) = job
+/// Adds the args and environment of the workflow's tarpaulin or test step to the command, an error
+/// if the workflow doesn't run the tests
+#[instrument(skip(input, workflow, cmd))]
+pub fn read_workflow(
+    input: &ProviderInput,
+    workflow: &Workflow,
+    cmd: &mut Command,
+) -> io::Result<()> {
+    // Jobs for other OSes are still used, but only if there's nothing for this one
+    let mut jobs = workflow.jobs.values().collect::<Vec<_>>();
+    jobs.sort_by_key(|x| !x.runs_on_host());
+    for job in jobs {
+        let matrix = job.chosen_matrix();
+        let steps = job
             .steps
+            .iter()
+            .filter(|x| x.runs(&matrix))
+            .collect::<Vec<_>>();
+        if let Some(step) = steps
             .iter()
             .find(|x| x.uses.starts_with("actions-rs/tarpaulin"))
         {
             info!("Found tarpaulin workflow");
-            return handle_tarpaulin_workflow(step, cmd);
-        } else if let Some(step) = job
-            .steps
+            cmd.current_dir(working_dir(
+                input.root,
+                input.spec,
+                step.working_directory(&workflow.defaults),
+                "",
+                "",
+            ));
+            handle_tarpaulin_workflow(step, input.context, cmd);
+            return Ok(());
+        } else if let Some(step) = steps
             .iter()
             .find(|x| x.uses.starts_with("actions-rs/cargo"))
         {
             // Convert grcov args to tarpaulin https://github.com/actions-rs/grcov
             if step.with.get("command").and_then(|x| x.as_str()) == Some("test") {
                 info!("Found test command");
-                if let Some(dir) = workflow.defaults.working_directory() {
-                    info!("Working dir to {}", root.join(dir).display());
-                    cmd.current_dir(root.join(dir));
-                }
+                cmd.current_dir(working_dir(
+                    input.root,
+                    input.spec,
+                    step.working_directory(&workflow.defaults),
+                    "",
+                    "",
+                ));
                 if let Some(s) = step.with.get("args") {
                     if s.is_string() {
                         let run = replace_variables(s.as_str().unwrap(), job);
-                        process_arg_string(cmd, run.as_str());
+                        match process_arg_string(run.as_str(), input.context) {
+                            Ok(args) => {
+                                cmd.args(args);
+                            }
+                            Err(e) => warn!("Unable to use args '{}': {}", run, e),
+                        }
                     }
                 }
-                info!("Spawning: {:?}", cmd);
-                return cmd.spawn();
+                return Ok(());
             }
         } else {
-            for step in &job.steps {
+            for step in &steps {
                 let run = replace_variables(&step.run, job);
-                if try_to_populate_command(&run, cmd) {
-                    return cmd.spawn();
+                // Each step has its own shell so nothing carries over from earlier steps
+                let ci_dir = step.working_directory(&workflow.defaults);
+                if populate_command(input, ci_dir, "", &run, cmd) {
+                    return Ok(());
                 }
             }
         }
@@ -280,7 +439,7 @@ fn replace_variables(run: &str, job: &Job) -> String {
     }
     if let Some(vars) = GHA_VARIABLE.captures(run) {
         let mut var_map = HashMap::<&str, String>::new();
-        for cap in vars.iter().filter_map(|x| x) {
+        for cap in vars.iter().flatten() {
             let res = job.get_possible_matrix_values(cap.as_str());
             match res {
                 Some(values) if !values.is_empty() => {
@@ -292,7 +451,7 @@ fn replace_variables(run: &str, job: &Job) -> String {
         if !var_map.is_empty() {
             let mut x = run.to_string();
             for (k, v) in var_map.iter() {
-                x = x.replace(k, &v);
+                x = x.replace(k, v);
             }
             x.replace("${{", "").replace("}}", "")
         } else {
@@ -303,84 +462,261 @@ fn replace_variables(run: &str, job: &Job) -> String {
     }
 }
 
-fn process_arg_string(cmd: &mut Command, args: &str) {
+/// Gets the arguments to pass to tarpaulin from an argument string in the CI config
+fn process_arg_string(args: &str, context: &Context) -> Result<Vec<String>, ParseError> {
     info!("Applying args: '{}'", args);
-    let mut skip_next = false;
-    for arg in args.split_whitespace() {
-        if skip_next {
-            skip_next = false;
-            continue;
-        }
-        if arg == "--color" || arg == "--coveralls" {
-            skip_next = true;
-            continue;
-        }
-        cmd.arg(arg);
-    }
+    Ok(strip_args(split_args(args)?, context))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ci::test::*;
 
     #[test]
-    fn openmls_yaml() {
-        let x = r#"
-name: Tests & Checks
-
-on:
-  push:
-    branches:
-      - main
-      - konrad/treesync
-  pull_request:
-    branches:
-      - main
-      - konrad/treesync
-  workflow_dispatch:
+    fn jobs_are_passed() {
+        let workflow = r#"
+jobs:
+  test:
+    runs-on: ubuntu-latest
+    steps:
+      - run: cargo test --all-features
+"#;
+        let root = test_project("github-jobs", &[(".github/workflows/ci.yml", workflow)]);
+        let cmd = get_command(&ProviderInput {
+            root: &root,
+            jobs: Some(4),
+            context: &Context::default(),
+            spec: &test_spec(),
+        })
+        .unwrap();
+        let args = args(&cmd);
+        assert!(args.windows(2).any(|x| x == ["--jobs", "4"]));
+        assert!(args.contains(&"--all-features".to_string()));
+    }
 
-env:
-  CARGO_TERM_COLOR: always
+    #[test]
+    fn host_jobs_preferred() {
+        let workflow = r#"
+jobs:
+  windows:
+    runs-on: windows-latest
+    steps:
+      - run: cargo test --features windows
+  linux:
+    runs-on: [self-hosted, Linux]
+    steps:
+      - run: cargo test --features linux
+  mac:
+    runs-on: macos-12
+    steps:
+      - run: cargo test --features mac
+"#;
+        let root = test_project("github-host-os", &[(".github/workflows/ci.yml", workflow)]);
+        let cmd = get_command(&ProviderInput {
+            root: &root,
+            jobs: None,
+            context: &Context::default(),
+            spec: &test_spec(),
+        })
+        .unwrap();
+        let expected = if cfg!(windows) {
+            "windows"
+        } else if cfg!(target_os = "macos") {
+            "mac"
+        } else {
+            "linux"
+        };
+        assert!(args(&cmd).windows(2).any(|x| x == ["--features", expected]));
 
-defaults:
-  run:
-    working-directory: openmls
+        let job = |runs_on: &str| Job {
+            name: String::new(),
+            runs_on: serde_yaml::from_str(runs_on).unwrap(),
+            steps: vec![],
+            strategy: Strategy::default(),
+        };
+        assert!(job("${{ matrix.os }}").runs_on_host());
+        assert!(job("[self-hosted, gpu]").runs_on_host());
+        assert_eq!(job("windows-2019").runs_on_host(), cfg!(windows));
+        assert_eq!(
+            job("ubuntu-20.04").runs_on_host(),
+            cfg!(target_os = "linux")
+        );
+    }
 
+    #[test]
+    fn step_conditions() {
+        let matrix = vec![
+            ("os".to_string(), "ubuntu-latest".into()),
+            ("rust".to_string(), "stable".into()),
+        ]
+        .into_iter()
+        .collect::<HashMap<String, serde_yaml::Value>>();
+        assert!(evaluate_condition("matrix.os == 'ubuntu-latest'", &matrix));
+        assert!(evaluate_condition(
+            "${{ matrix.rust == \"Stable\" }}",
+            &matrix
+        ));
+        assert!(evaluate_condition("'ubuntu-latest' == matrix.os", &matrix));
+        assert!(!evaluate_condition(
+            "matrix.os == 'windows-latest'",
+            &matrix
+        ));
+        assert!(evaluate_condition("matrix.os != 'windows-latest'", &matrix));
+        assert!(!evaluate_condition("matrix.os != 'Ubuntu-Latest'", &matrix));
+        assert!(!evaluate_condition("matrix.target == 'wasm'", &matrix));
+        assert!(!evaluate_condition("github.event_name == 'push'", &matrix));
+        assert!(!evaluate_condition("always()", &matrix));
+
+        let workflow = r#"
 jobs:
-  tests:
+  test:
     strategy:
-      fail-fast: false
       matrix:
-        os:
-          - macos-latest
-          - ubuntu-latest
-          - windows-latest
+        os: [windows-latest, macos-latest, ubuntu-latest]
     runs-on: ${{ matrix.os }}
     steps:
-      - uses: actions/checkout@v2
-        with:
-          ref: ${{ github.event.pull_request.head.sha }}
-      - name: Tests debug build
-        run: |
-          cargo test --verbose
-      - name: Tests release build
-        run: |
-          cargo test --verbose --release
-        # Test 32 bit builds on windows
-      - name: Install rust target
-        if: matrix.os == 'windows-latest'
-        uses: actions-rs/toolchain@v1
-        with:
-          toolchain: stable
-          profile: minimal
-          override: true
-          target: i686-pc-windows-msvc
-      - name: Tests 32bit windows release build
-        if: matrix.os == 'windows-latest'
-        run: |
-          cargo test --verbose --target i686-pc-windows-msvc
-          cargo test --verbose --release --target i686-pc-windows-msvc
-            "#;
+      - if: matrix.os == 'windows-latest'
+        run: cargo test --features windows
+      - if: matrix.os == 'macos-latest'
+        run: cargo test --features mac
+      - if: matrix.os == 'ubuntu-latest'
+        run: cargo test --features linux
+"#;
+        let root = test_project(
+            "github-conditions",
+            &[(".github/workflows/ci.yml", workflow)],
+        );
+        let cmd = get_command(&ProviderInput {
+            root: &root,
+            jobs: None,
+            context: &Context::default(),
+            spec: &test_spec(),
+        })
+        .unwrap();
+        let expected = if cfg!(windows) {
+            "windows"
+        } else if cfg!(target_os = "macos") {
+            "mac"
+        } else {
+            "linux"
+        };
+        assert!(args(&cmd).windows(2).any(|x| x == ["--features", expected]));
+    }
+
+    #[test]
+    fn tarpaulin_action_inputs() {
+        let step: Step = serde_yaml::from_str(
+            "uses: actions-rs/tarpaulin@v0.1
+with:
+  args: '--all-features --ignore-tests'
+  timeout: '120'
+",
+        )
+        .unwrap();
+        let mut cmd = Command::new("cargo");
+        handle_tarpaulin_workflow(&step, &Context::default(), &mut cmd);
+        // The inputs are a map so they can come in any order
+        let mut args = args(&cmd);
+        args.sort();
+        assert_eq!(
+            args,
+            ["--all-features", "--ignore-tests", "--timeout", "120"]
+        );
+    }
+
+    #[test]
+    fn workflows_scored() {
+        let workflow = |steps: &str| -> Workflow {
+            serde_yaml::from_str(&format!(
+                "jobs:\n  main:\n    runs-on: ubuntu-latest\n    steps:\n{}",
+                steps
+            ))
+            .unwrap()
+        };
+        let lint = workflow("      - run: cargo fmt --check\n      - run: cargo clippy\n");
+        let build = workflow("      - run: cargo build\n      - run: cargo test --features foo\n");
+        let tarpaulin = workflow("      - uses: actions-rs/tarpaulin@v0.1\n");
+        let score = |name: &str, workflow: &Workflow| score_workflow(Path::new(name), workflow);
+        // Running the tests beats a promising name
+        assert!(score("build.yml", &build) > score("ci.yml", &lint));
+        assert!(score("release.yml", &tarpaulin) > score("coverage.yml", &build));
+        assert!(score("coverage.yml", &lint) > score("ci.yml", &lint));
+        assert!(score("test.yml", &build) > score("build.yml", &build));
+
+        let root = test_project(
+            "github-scored",
+            &[
+                (".github/workflows/ci.yml", "jobs:\n  lint:\n    runs-on: ubuntu-latest\n    steps:\n      - run: cargo clippy\n"),
+                (".github/workflows/build.yml", "jobs:\n  build:\n    runs-on: ubuntu-latest\n    steps:\n      - run: cargo test --features scored\n"),
+            ],
+        );
+        let spec = test_spec();
+        let cmd = get_command(&ProviderInput {
+            root: &root,
+            jobs: None,
+            context: &Context::default(),
+            spec: &spec,
+        })
+        .unwrap();
+        assert!(args(&cmd).windows(2).any(|x| x == ["--features", "scored"]));
+    }
+
+    #[test]
+    fn quoted_args() {
+        let context = Context::default();
+        assert_eq!(
+            process_arg_string(r#"--features "foo bar""#, &context),
+            Ok(vec!["--features".to_string(), "foo bar".to_string()])
+        );
+        assert_eq!(
+            process_arg_string("--features 'foo bar'", &context),
+            Ok(vec!["--features".to_string(), "foo bar".to_string()])
+        );
+        assert_eq!(
+            process_arg_string(r#"--features "foo "'bar' --color never  --lib"#, &context),
+            Ok(vec![
+                "--features".to_string(),
+                "foo bar".to_string(),
+                "--lib".to_string()
+            ])
+        );
+        assert_eq!(
+            process_arg_string(r#"-- --skip "say \"hi\"" \"x\" '\n' """#, &context),
+            Ok(vec![
+                "--".to_string(),
+                "--skip".to_string(),
+                "say \"hi\"".to_string(),
+                "\"x\"".to_string(),
+                "\\n".to_string(),
+                "".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn bad_quotes() {
+        let context = Context::default();
+        assert_eq!(
+            process_arg_string(r#"--features "foo bar"#, &context),
+            Err(ParseError::UnclosedQuote('"'))
+        );
+        assert_eq!(
+            process_arg_string("--features 'foo bar", &context),
+            Err(ParseError::UnclosedQuote('\''))
+        );
+        assert_eq!(
+            process_arg_string("--lib \\", &context),
+            Err(ParseError::TrailingEscape)
+        );
+    }
+
+    const OPENMLS_WORKFLOW: &str =
+        include_str!("../../tests/fixtures/openmls/.github/workflows/ci.yml");
+
+    #[test]
+    fn openmls_yaml() {
+        let x = OPENMLS_WORKFLOW;
 
         let result: Workflow = serde_yaml::from_str(x).unwrap();
         assert_eq!(result.defaults.working_directory(), Some("openmls"));
@@ -388,6 +724,24 @@ jobs:
         assert_eq!(result.jobs.get("tests").unwrap().steps.len(), 5);
     }
 
+    #[test]
+    fn default_working_directory() {
+        let root = test_project(
+            "github-working-directory",
+            &[(".github/workflows/tests.yml", OPENMLS_WORKFLOW)],
+        );
+        let spec = test_spec();
+        let cmd = get_command(&ProviderInput {
+            root: &root,
+            jobs: None,
+            context: &Context::default(),
+            spec: &spec,
+        })
+        .unwrap();
+        assert!(args(&cmd).iter().any(|x| x == "--verbose"));
+        assert_eq!(cmd.get_current_dir(), Some(root.join("openmls").as_path()));
+    }
+
     #[test]
     fn hyper_yaml() {
         let x = r#"
@@ -4,8 +4,7 @@ use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
 use std::io;
-use std::path::Path;
-use std::process::{Child, Command};
+use std::process::Command;
 use tracing::info;
 
 #[derive(Debug, Deserialize)]
@@ -29,26 +28,24 @@ pub struct Stage {
     script: Vec<String>,
 }
 
-pub fn get_command(
-    root: impl AsRef<Path>,
-    jobs: Option<&usize>,
-    context: &Context,
-    spec: &CrateSpec,
-) -> io::Result<Child> {
-    let workflow = root.as_ref().join(".gitlab-ci.yml");
+pub fn get_command(input: &ProviderInput) -> io::Result<Command> {
+    let workflow = input.root.join(".gitlab-ci.yml");
     if workflow.exists() {
         let workflow = fs::File::open(workflow)?;
         let workflow: Pipeline = serde_yaml::from_reader(workflow)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
 
-        let mut cmd = Command::new("cargo");
-        init_command(root.as_ref(), jobs, context, spec, &mut cmd);
+        let mut cmd = input.command();
         for (k, stage) in &workflow.stages {
             info!("Scanning stage: {:?}", k);
+            // The lines of a job's script share a shell
+            let mut earlier = String::new();
             for line in &stage.script {
-                if try_to_populate_command(line.as_str(), &mut cmd) {
-                    return cmd.spawn();
+                if populate_command(input, None, &earlier, line, &mut cmd) {
+                    return Ok(cmd);
                 }
+                earlier.push_str(line);
+                earlier.push('\n');
             }
         }
 
@@ -67,6 +64,27 @@ pub fn get_command(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ci::test::*;
+
+    #[test]
+    fn jobs_are_passed() {
+        let config = r#"
+test:cargo:
+  script:
+    - cargo test --features foo
+"#;
+        let root = test_project("gitlab-jobs", &[(".gitlab-ci.yml", config)]);
+        let cmd = get_command(&ProviderInput {
+            root: &root,
+            jobs: Some(4),
+            context: &Context::default(),
+            spec: &test_spec(),
+        })
+        .unwrap();
+        let args = args(&cmd);
+        assert!(args.windows(2).any(|x| x == ["--jobs", "4"]));
+        assert!(args.windows(2).any(|x| x == ["--features", "foo"]));
+    }
 
     #[test]
     fn simple_ci_config() {
@@ -78,6 +96,6 @@ test:cargo:
     - cargo test --features foo
 "#;
 
-        let result: Pipeline = serde_yaml::from_str(config).unwrap();
+        let _result: Pipeline = serde_yaml::from_str(config).unwrap();
     }
 }
@@ -1,16 +1,36 @@
+use crate::docker;
+use crate::metadata;
 use crate::runner::*;
 use lazy_static::lazy_static;
-use regex::{Regex, RegexBuilder};
-use std::io;
-use std::path::Path;
-use std::process::{Child, Command, Stdio};
-use tracing::{debug, info, warn};
+use regex::{Captures, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{create_dir_all, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use thiserror::Error;
+use tracing::{debug, error, info, warn};
 
 pub mod github;
 pub mod gitlab;
 pub mod travis;
 pub mod types;
 
+lazy_static! {
+    /// Shell line continuations, a trailing `\` joins the next line onto the current one
+    pub(crate) static ref FIX_LINES: Regex = RegexBuilder::new(r#"\\\s*\n"#)
+        .multi_line(true)
+        .build()
+        .unwrap();
+    /// `cargo test` or `cross test`, possibly with a toolchain
+    pub(crate) static ref TEST_INVOCATION: Regex =
+        Regex::new(r#"(cargo|cross)\s+(\+\S+\s+)?test"#).unwrap();
+}
+
 pub fn default_args() -> Vec<String> {
     vec![
         "tarpaulin".to_string(),
@@ -20,19 +40,423 @@ pub fn default_args() -> Vec<String> {
     ]
 }
 
-pub fn try_to_populate_command(data: &str, cmd: &mut Command) -> bool {
-    // TODO need to split up commands and handle things like `cd blah && cargo test;
-    // Also, find tarpaulin ran via shell commands
-    if data.contains("cargo test") {
+/// Options tater sets itself so they're always stripped from CI commands along with their value
+const STRIPPED_OPTIONS: &[&str] = &["--color", "--coveralls"];
+
+/// Removes the arguments the context says to strip from arguments found in a CI config, along
+/// with the options tater always strips. Anything after `--` is for the test binaries so is left
+/// alone
+pub fn strip_args(args: impl IntoIterator<Item = String>, context: &Context) -> Vec<String> {
+    let mut res = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            res.push(arg);
+            res.extend(args);
+            break;
+        }
+        let (name, has_value) = match arg.split_once('=') {
+            Some((name, _)) => (name, true),
+            None => (arg.as_str(), false),
+        };
+        if STRIPPED_OPTIONS.contains(&name) || context.strip_options.iter().any(|x| x == name) {
+            if !has_value {
+                args.next();
+            }
+        } else if !context.strip_args.iter().any(|x| x == name) {
+            res.push(arg);
+        }
+    }
+    res
+}
+
+/// Directory in the output tater writes the tarpaulin configs for crates whose CI runs multiple
+/// test commands to, each is named after its crate
+pub const TARPAULIN_CONFIGS_DIR: &str = "tarpaulin-configs";
+
+/// Short flags which have a different name in the tarpaulin config
+const CONFIG_ALIASES: &[(&str, &str)] = &[
+    ("-v", "verbose"),
+    ("-p", "packages"),
+    ("-e", "exclude"),
+    ("-F", "features"),
+    ("-o", "out"),
+    ("-t", "timeout"),
+];
+
+/// `cargo test` flags which don't take a value
+const CARGO_TEST_FLAGS: &[&str] = &[
+    "--lib",
+    "--bins",
+    "--examples",
+    "--tests",
+    "--benches",
+    "--all-targets",
+    "--doc",
+    "--workspace",
+    "--all",
+    "--all-features",
+    "--no-default-features",
+    "--release",
+    "--verbose",
+    "-v",
+    "--quiet",
+    "-q",
+    "--offline",
+    "--frozen",
+    "--locked",
+    "--no-fail-fast",
+    "--no-run",
+];
+
+/// Tarpaulin options taking every value up to the next flag, any other option takes one
+const MULTI_VALUE_OPTIONS: &[&str] = &[
+    "--out",
+    "-o",
+    "--run-types",
+    "--exclude-files",
+    "--include-files",
+];
+
+/// Keys tarpaulin reads as an array even with a single value
+const CONFIG_LIST_KEYS: &[&str] = &[
+    "packages",
+    "exclude",
+    "out",
+    "run-types",
+    "exclude-files",
+    "include-files",
+];
+
+/// The arguments of a command found by `extract_tarpaulin_commands`, without the program,
+/// toolchain, subcommand and any trailing shell separator
+fn command_args(command: &str) -> impl Iterator<Item = String> {
+    let skip = if command_toolchain(command).is_some() {
+        3
+    } else {
+        2
+    };
+    // Quoted args like `--features "a b"` are kept together, unless the quotes don't match up
+    let words = split_args(command)
+        .unwrap_or_else(|_| command.split_whitespace().map(String::from).collect());
+    words
+        .into_iter()
+        .skip(skip)
+        .filter(|x| !matches!(x.as_str(), ";" | "&&"))
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("Unclosed {0} quote")]
+    UnclosedQuote(char),
+    #[error("Trailing escape character")]
+    TrailingEscape,
+}
+
+/// Splits a string into arguments the way a shell would, respecting quotes and escapes
+fn split_args(args: &str) -> Result<Vec<String>, ParseError> {
+    let mut res = vec![];
+    let mut current = String::new();
+    // Tracked separately so empty quoted strings still result in an argument
+    let mut in_arg = false;
+    let mut quote = None;
+    let mut chars = args.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), c) => current.push(c),
+            (Some('"'), '"') => quote = None,
+            (Some('"'), '\\') => match chars.next() {
+                Some(c @ ('"' | '\\' | '$' | '`')) => current.push(c),
+                Some(c) => {
+                    current.push('\\');
+                    current.push(c);
+                }
+                None => return Err(ParseError::UnclosedQuote('"')),
+            },
+            (Some(_), c) => current.push(c),
+            (None, '\\') => match chars.next() {
+                Some(c) => {
+                    current.push(c);
+                    in_arg = true;
+                }
+                None => return Err(ParseError::TrailingEscape),
+            },
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    res.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if let Some(q) = quote {
+        Err(ParseError::UnclosedQuote(q))
+    } else {
+        if in_arg {
+            res.push(current);
+        }
+        Ok(res)
+    }
+}
+
+/// Joins arguments into a command line `command_args` splits back into the same arguments, quoting
+/// any with spaces or quotes in
+fn join_args(args: &[String]) -> String {
+    args.iter()
+        .map(|x| {
+            if !x.is_empty() && !x.contains(|c: char| c.is_whitespace() || "'\"\\".contains(c)) {
+                x.clone()
+            } else {
+                format!("'{}'", x.replace('\'', r#"'\''"#))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The toolchain of a command found by `extract_tarpaulin_commands` if it's ran like
+/// `cargo +nightly test`
+fn command_toolchain(command: &str) -> Option<&str> {
+    command
+        .split_whitespace()
+        .nth(1)
+        .and_then(|x| x.strip_prefix('+'))
+}
+
+/// A TOML basic string, JSON string escapes are all valid in TOML
+fn toml_string(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+/// Writes the arguments of a command ran in `dir` as the keys of a tarpaulin config section.
+/// Options take the value following them, or every value up to the next flag for the few that
+/// take several, and flags are `true`. Repeated options and list keys become an array, features
+/// are one space separated string. Positional arguments filter the tests so they're passed to the
+/// test binaries as `args` along with anything after `--`. The config isn't in the project so the
+/// manifest path is always set
+fn write_config_section(writer: &mut impl Write, args: &[String], dir: &Path) -> io::Result<()> {
+    let split = args.iter().position(|x| x == "--").unwrap_or(args.len());
+    let mut options: Vec<(String, Vec<&str>)> = vec![];
+    let mut test_args = vec![];
+    let mut args_iter = args[..split].iter().peekable();
+    while let Some(arg) = args_iter.next() {
+        if !arg.starts_with('-') {
+            test_args.push(arg.as_str());
+            continue;
+        }
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        let key = match CONFIG_ALIASES.iter().find(|(short, _)| *short == flag) {
+            Some((_, key)) => key.to_string(),
+            None => flag.trim_start_matches('-').to_string(),
+        };
+        let mut values = value.into_iter().collect::<Vec<_>>();
+        let is_flag = CARGO_TEST_FLAGS.contains(&flag) || TARPAULIN_FLAGS.contains(&flag);
+        if value.is_none() && !is_flag {
+            let multi = MULTI_VALUE_OPTIONS.contains(&flag);
+            while let Some(value) = args_iter.next_if(|x| !x.starts_with('-')) {
+                values.push(value.as_str());
+                if !multi {
+                    break;
+                }
+            }
+        }
+        match options.iter_mut().find(|(x, _)| *x == key) {
+            Some((_, existing)) => existing.extend(values),
+            None => options.push((key, values)),
+        }
+    }
+    let manifest = match options.iter().position(|(key, _)| key == "manifest-path") {
+        Some(i) => dir.join(options.remove(i).1.first().copied().unwrap_or_default()),
+        None => dir.join("Cargo.toml"),
+    };
+    writeln!(
+        writer,
+        "manifest-path = {}",
+        toml_string(&manifest.to_string_lossy())
+    )?;
+    for (key, values) in &options {
+        if key == "features" {
+            writeln!(writer, "features = {}", toml_string(&values.join(" ")))?;
+            continue;
+        }
+        match values.as_slice() {
+            [] => writeln!(writer, "{} = true", key)?,
+            [value] if !CONFIG_LIST_KEYS.contains(&key.as_str()) => {
+                writeln!(writer, "{} = {}", key, toml_string(value))?
+            }
+            values => {
+                let values = values.iter().map(|x| toml_string(x)).collect::<Vec<_>>();
+                writeln!(writer, "{} = [{}]", key, values.join(", "))?
+            }
+        }
+    }
+    test_args.extend(args[split..].iter().skip(1).map(String::as_str));
+    if !test_args.is_empty() {
+        let test_args = test_args.iter().map(|x| toml_string(x)).collect::<Vec<_>>();
+        writeln!(writer, "args = [{}]", test_args.join(", "))?;
+    }
+    Ok(())
+}
+
+/// Writes a tarpaulin config with a section for each command ran in `dir` so tarpaulin runs all
+/// of them
+pub fn write_tarpaulin_config(commands: &[String], dir: &Path, path: &Path) -> io::Result<()> {
+    let dir = dir.canonicalize()?;
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    for (i, command) in commands.iter().enumerate() {
+        if i > 0 {
+            writeln!(writer)?;
+        }
+        writeln!(writer, "[packages.command_{}]", i + 1)?;
+        write_config_section(
+            &mut writer,
+            &command_args(command).collect::<Vec<_>>(),
+            &dir,
+        )?;
+    }
+    writer.flush()
+}
+
+/// Directories a CI script `cd`s into before it runs the tests, in order. Every `cd` in `earlier`
+/// counts as it all ran before the script
+fn cd_dirs<'a>(earlier: &'a str, script: &'a str) -> Vec<&'a str> {
+    let end = TEST_INVOCATION
+        .find(script)
+        .map(|x| x.start())
+        .unwrap_or(script.len());
+    [earlier, &script[..end]]
+        .iter()
+        .flat_map(|x| x.split(['\n', ';']))
+        .flat_map(|x| x.split("&&"))
+        .filter_map(|x| x.trim().trim_start_matches('(').strip_prefix("cd "))
+        .map(|x| x.trim().trim_matches(['"', '\'']))
+        .collect()
+}
+
+/// Directory a CI script runs the tests in. An explicit subdir for the crate takes precedence over
+/// the CI config, otherwise it's the config's working directory followed by any `cd` ran earlier
+/// in the same shell or in the script before the tests. A `cd` outside the project or to a
+/// directory the checkout doesn't have, such as one made by an earlier step, is ignored along with
+/// any after it
+pub fn working_dir(
+    root: &Path,
+    spec: &CrateSpec,
+    ci_dir: Option<&str>,
+    earlier: &str,
+    script: &str,
+) -> PathBuf {
+    if spec.subdir.is_some() {
+        return spec.working_dir(root);
+    }
+    let join = |dir: &Path, subdir: &str| {
+        subdir_parts(subdir).fold(dir.to_path_buf(), |dir, x| dir.join(x))
+    };
+    let mut dir = match ci_dir.filter(|x| !subdir_escapes(x)) {
+        Some(ci_dir) => join(root, ci_dir),
+        None => root.to_path_buf(),
+    };
+    for cd in cd_dirs(earlier, script) {
+        let next = join(&dir, cd);
+        if subdir_escapes(cd) || !next.is_dir() {
+            debug!("Ignoring `cd {}` in CI", cd);
+            break;
+        }
+        dir = next;
+    }
+    dir
+}
+
+/// Populates the command from a CI script like `try_to_populate_command`, running it where the
+/// script runs the tests. `earlier` is what ran before it in the same shell, so any `cd` in it
+/// still applies. The command's working directory is left alone if nothing was found
+pub fn populate_command(
+    input: &ProviderInput,
+    ci_dir: Option<&str>,
+    earlier: &str,
+    script: &str,
+    cmd: &mut Command,
+) -> bool {
+    let original_dir = cmd.get_current_dir().map(Path::to_path_buf);
+    let dir = working_dir(input.root, input.spec, ci_dir, earlier, script);
+    if original_dir.as_deref() != Some(&dir) {
+        info!("Working dir to {}", dir.display());
+    }
+    cmd.current_dir(dir);
+    let config = input
+        .context
+        .config_dir
+        .as_ref()
+        .zip(input.spec.name())
+        .map(|(dir, name)| dir.join(name).with_extension("toml"));
+    if try_to_populate_command(script, input.context, config.as_deref(), cmd) {
+        return true;
+    }
+    if let Some(dir) = original_dir {
+        cmd.current_dir(dir);
+    }
+    false
+}
+
+/// Adds the arguments of the tests ran by a CI script to the command, false if it doesn't run
+/// any. If it runs several a tarpaulin config running all of them is written to `config`, without
+/// one only the first is ran
+pub fn try_to_populate_command(
+    data: &str,
+    context: &Context,
+    config: Option<&Path>,
+    cmd: &mut Command,
+) -> bool {
+    // TODO find tarpaulin ran via shell commands
+    if TEST_INVOCATION.is_match(data) {
         debug!("Maybe one: '{}'", data);
-        let commands = extract_tarpaulin_commands(data);
+        let found = extract_tarpaulin_commands(data);
+        // A toolchain in the repos file is what tater was asked to test with so it wins, including
+        // one set in the env
+        if let Some(toolchain) = found.first().and_then(|x| command_toolchain(x)) {
+            let env_toolchain = cmd.get_envs().any(|(key, _)| key == "RUSTUP_TOOLCHAIN");
+            if context.toolchain_name().is_none() && !env_toolchain {
+                info!("CI uses the {} toolchain", toolchain);
+                cmd.env("RUSTUP_TOOLCHAIN", toolchain);
+            }
+        }
+        let commands = found
+            .iter()
+            .map(|x| {
+                let args = strip_args(command_args(x), context);
+                format!("cargo tarpaulin {}", join_args(&args))
+            })
+            .collect::<Vec<_>>();
         info!("Found commands: {:?}", commands);
-        if commands.len() == 1 {
-            cmd.args(commands[0].split_whitespace().skip(2));
-        } else if commands.len() > 1 {
-            // Should generate a tarpaulin.toml for these commands
+        if commands.len() > 1 {
+            let written = match (cmd.get_current_dir(), config) {
+                (Some(dir), Some(path)) => write_tarpaulin_config(&commands, dir, path)
+                    .map_err(|e| warn!("Failed to write {}: {}", path.display(), e))
+                    .is_ok(),
+                _ => false,
+            };
+            if let (true, Some(path)) = (written, config) {
+                cmd.arg("--config").arg(path);
+                return true;
+            }
             warn!("Ignoring commands: {:?}", &commands[1..]);
-            cmd.args(commands[0].split_whitespace().skip(2));
+        }
+        if let Some(command) = commands.first() {
+            cmd.args(command_args(command));
         }
         true
     } else {
@@ -42,72 +466,1363 @@ pub fn try_to_populate_command(data: &str, cmd: &mut Command) -> bool {
 
 pub fn extract_tarpaulin_commands(input: &str) -> Vec<String> {
     lazy_static! {
-        static ref FIX_LINES: Regex = RegexBuilder::new(r#"\\\s*\n"#)
-            .multi_line(true)
-            .build()
-            .unwrap();
-        static ref TEST_CMD: Regex =
-            Regex::new(r#"cargo\s+test\s*([\-a-zA-Z\d\\\s\$\{\}\."~\n])*(;?|\s*~\\\s*\n|&&|$)"#)
-                .unwrap();
+        static ref TEST_CMD: Regex = Regex::new(
+            r#"(cargo|cross)\s+(\+[\w\.\-]+\s+)?test\s*([\-_=a-zA-Z\d\\\s\$\{\}\."~\n])*(;?|\s*~\\\s*\n|&&|$)"#
+        )
+        .unwrap();
+        static ref TEST_SUBCOMMAND: Regex =
+            Regex::new(r#"^(cargo|cross)\s+(\+[\w\.\-]+)?\s*test"#).unwrap();
     }
     let line_break_removed = FIX_LINES.replace_all(input, " ");
     let mut res = vec![];
     for s in line_break_removed.lines() {
         for m in TEST_CMD.find_iter(s) {
-            res.push(m.as_str().replace("cargo test", "cargo tarpaulin"));
+            let command = m.as_str();
+            if command.starts_with("cross") && cross_target(command).is_none() {
+                warn!("`cross test` without a target, running for the host");
+            }
+            // Keeps the toolchain the command was ran with, cross takes the target like cargo so
+            // the `--target` carries over
+            let tarpaulin = TEST_SUBCOMMAND.replace(command, |cap: &Captures| match cap.get(2) {
+                Some(toolchain) => format!("cargo {} tarpaulin", toolchain.as_str()),
+                None => "cargo tarpaulin".to_string(),
+            });
+            res.push(tarpaulin.into_owned());
         }
     }
     res
 }
 
+/// The target a `cross test` command builds for
+fn cross_target(command: &str) -> Option<&str> {
+    let mut args = command.split_whitespace();
+    while let Some(arg) = args.next() {
+        if arg == "--target" {
+            return args.next();
+        } else if let Some(target) = arg.strip_prefix("--target=") {
+            return Some(target);
+        }
+    }
+    None
+}
+
+/// What every CI provider gets to build the tarpaulin command for a project from its CI config
+#[derive(Debug, Clone, Copy)]
+pub struct ProviderInput<'a> {
+    /// Directory the project is cloned to
+    pub root: &'a Path,
+    pub jobs: Option<usize>,
+    pub context: &'a Context,
+    pub spec: &'a CrateSpec,
+}
+
+impl<'a> ProviderInput<'a> {
+    /// The tarpaulin command with the arguments and environment every provider starts from
+    pub fn command(&self) -> Command {
+        let mut cmd = tarpaulin_program(self.context, self.spec);
+        init_command(self.root, self.jobs, self.context, self.spec, &mut cmd);
+        cmd
+    }
+}
+
+/// Where the tarpaulin command for a project came from
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CiProvider {
+    Github,
+    Gitlab,
+    Travis,
+    /// No CI config could be used so it's a plain `cargo tarpaulin`
+    Default,
+}
+
+impl fmt::Display for CiProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Github => "github",
+            Self::Gitlab => "gitlab",
+            Self::Travis => "travis",
+            Self::Default => "default",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Makes the tarpaulin command from a CI provider's config
+type GetCommand = fn(&ProviderInput) -> io::Result<Command>;
+
+/// CI providers in the order they're tried
+const PROVIDERS: &[(CiProvider, GetCommand)] = &[
+    (CiProvider::Github, github::get_command),
+    (CiProvider::Gitlab, gitlab::get_command),
+    (CiProvider::Travis, travis::get_command),
+];
+
+/// The tarpaulin command for a project and how it was found
+#[derive(Debug)]
+pub struct Detection {
+    pub provider: CiProvider,
+    pub command: Command,
+    /// Why each provider tried before the one used couldn't make a command
+    pub failures: Vec<(CiProvider, String)>,
+}
+
+/// The program tarpaulin is ran with, either cargo, cross or the binary set in the context
+pub fn tarpaulin_program(context: &Context, spec: &CrateSpec) -> Command {
+    match context.tarpaulin_path.as_ref() {
+        Some(path) => new_command(path),
+        None if context.cross_for(spec) => new_command("cross"),
+        None => new_command("cargo"),
+    }
+}
+
+/// Gets the version number from the output of `cargo tarpaulin --version`, older versions print
+/// `cargo-tarpaulin version: 0.18.0` and newer ones `cargo-tarpaulin 0.27.0`
+pub fn parse_tarpaulin_version(output: &str) -> Option<String> {
+    output
+        .lines()
+        .next()?
+        .split_whitespace()
+        .last()
+        .filter(|x| x.starts_with(|c: char| c.is_ascii_digit() || c == 'v'))
+        .map(|x| x.trim_start_matches('v').to_string())
+}
+
+/// Arguments to `cargo` to install tarpaulin with the features in the context
+pub fn tarpaulin_install_args(context: &Context) -> Vec<String> {
+    let mut args = vec!["install".to_string(), "cargo-tarpaulin".to_string()];
+    if !context.tarpaulin_features.is_empty() {
+        args.push("--features".to_string());
+        args.push(context.tarpaulin_features.join(","));
+    }
+    args
+}
+
+/// Runs `cargo tarpaulin --version` to find the installed tarpaulin, `None` if it isn't installed
+/// in which case how to install it is logged
+pub fn detect_tarpaulin_version(context: &Context) -> Option<String> {
+    let version = match new_command("cargo")
+        .args(["tarpaulin", "--version"])
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            parse_tarpaulin_version(&String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(output) => {
+            debug!(
+                "cargo tarpaulin --version failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            None
+        }
+        Err(e) => {
+            debug!("Failed to run cargo: {}", e);
+            None
+        }
+    };
+    if version.is_none() {
+        error!(
+            "cargo tarpaulin isn't installed, install it with `cargo {}`",
+            tarpaulin_install_args(context).join(" ")
+        );
+    }
+    version
+}
+
 pub fn init_command(
     root: impl AsRef<Path>,
-    jobs: Option<&usize>,
+    jobs: Option<usize>,
     context: &Context,
     spec: &CrateSpec,
     cmd: &mut Command,
 ) {
+    let mut args = vec![];
+    if let Some(toolchain) = context.toolchain_name() {
+        if context.tarpaulin_path.is_some() {
+            // Only the cargo proxy understands `+toolchain`
+            cmd.env("RUSTUP_TOOLCHAIN", toolchain);
+        } else {
+            args.push(format!("+{}", toolchain));
+        }
+    }
+    // A limit of 0 means don't limit
+    let jobs = jobs.filter(|j| *j > 0);
+    if let Some(j) = jobs {
+        args.extend(["--jobs".to_string(), j.to_string()]);
+    }
+    if context.tarpaulin_path.is_some() {
+        // Ran directly so there's no cargo subcommand
+        args.extend(default_args().into_iter().skip(1));
+    } else {
+        args.extend(default_args());
+    }
+    // The JSON report is collected with the results, any other reports asked for are kept
+    args.extend(["--out".to_string(), "Json".to_string()]);
+    if let Some(target) = context.target_for(spec) {
+        args.extend(["--target".to_string(), target.to_string()]);
+    }
+    if context.fetches() {
+        args.push("--offline".to_string());
+    }
+    if let Some(engine) = spec.tarpaulin_engine {
+        args.extend(["--engine".to_string(), engine.to_string()]);
+    }
     if let Some(j) = jobs {
-        cmd.args(&["--jobs", j.to_string().as_str()]);
+        // For test harnesses that don't take `--test-threads`
+        cmd.env("RUST_TEST_THREADS", j.to_string());
+    }
+    // Same precedence as the env tarpaulin is ran with
+    let vars = env::vars()
+        .chain(spec.env.clone())
+        .chain(context.env.clone())
+        .collect::<HashMap<_, _>>();
+    let expand = |arg: &String| expand_vars(arg, &vars, context.warn_undefined_vars);
+    args.extend(context.args.iter().map(expand));
+    args.extend(spec.args.iter().map(expand));
+    let mut args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    dedup_args(&mut args);
+    if let Some(dir) = context.shared_target_dir() {
+        cmd.env("CARGO_TARGET_DIR", dir);
+    }
+    if context.sccache_for(spec) {
+        cmd.env("RUSTC_WRAPPER", "sccache");
     }
-    cmd.args(&default_args())
-        .env("RUST_LOG", "cargo_tarpaulin=info")
-        .args(&context.args)
-        .args(&spec.args)
+    if context.cross_for(spec) {
+        // Named like the docker engine's containers so stalls are detected the same way
+        let name = spec.name().unwrap_or("unnamed_project");
+        let mut opts = format!("--name {}", docker::container_name(name));
+        if context.no_network {
+            opts.push_str(" --network none");
+        }
+        cmd.env("CROSS_CONTAINER_OPTS", opts);
+    }
+    cmd.env("RUST_LOG", "cargo_tarpaulin=info")
+        .env("RUST_BACKTRACE", "1")
+        .args(args)
         .envs(&spec.env)
         .envs(&context.env)
-        .current_dir(root)
+        .current_dir(spec.working_dir(root.as_ref()))
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
 }
 
-fn default_spawn(
+/// Expands `$VAR` and `${VAR}` in an arg from the variables, undefined variables are left as
+/// they are
+pub fn expand_vars(arg: &str, vars: &HashMap<String, String>, warn_undefined: bool) -> String {
+    lazy_static! {
+        static ref VAR: Regex =
+            Regex::new(r#"\$(?:\{([A-Za-z_][A-Za-z0-9_]*)\}|([A-Za-z_][A-Za-z0-9_]*))"#).unwrap();
+    }
+    VAR.replace_all(arg, |cap: &regex::Captures| {
+        let name = cap.get(1).or_else(|| cap.get(2)).unwrap().as_str();
+        match vars.get(name) {
+            Some(value) => value.clone(),
+            None => {
+                if warn_undefined {
+                    warn!("{} isn't set, leaving it in {}", name, arg);
+                }
+                cap[0].to_string()
+            }
+        }
+    })
+    .into_owned()
+}
+
+/// Tarpaulin options that can be given more than once, each adding to the values before
+const REPEATABLE_OPTIONS: &[&str] = &[
+    "--out",
+    "-o",
+    "--exclude-files",
+    "--include-files",
+    "--exclude",
+    "-e",
+    "--packages",
+    "-p",
+    "--features",
+    "--run-types",
+];
+
+/// Removes repeated arguments so the same setting in the context and crate args isn't passed
+/// twice. A repeated flag keeps its first occurrence and a repeated option keeps its last value,
+/// unless it's repeatable in which case only exact repeats are removed. Anything after `--` goes
+/// to the test binaries so is left alone
+pub fn dedup_args(args: &mut Vec<&str>) {
+    let end = args.iter().position(|x| *x == "--").unwrap_or(args.len());
+    // An option is followed by its value unless the value is given with `=`
+    let mut items = vec![];
+    let mut i = 0;
+    while i < end {
+        let has_value = args[i].starts_with('-')
+            && !args[i].contains('=')
+            && i + 1 < end
+            && !args[i + 1].starts_with('-');
+        let len = if has_value { 2 } else { 1 };
+        items.push(&args[i..i + len]);
+        i += len;
+    }
+    let name = |item: &[&str]| item[0].split('=').next().unwrap_or_default().to_string();
+    let mut keep = vec![true; items.len()];
+    for a in 0..items.len() {
+        let flag = name(items[a]);
+        if !flag.starts_with('-') || !keep[a] {
+            continue;
+        }
+        let is_option = items[a].len() == 2 || items[a][0].contains('=');
+        for b in a + 1..items.len() {
+            if name(items[b]) != flag {
+                continue;
+            }
+            if !is_option || items[a] == items[b] {
+                keep[b] = false;
+            } else if !REPEATABLE_OPTIONS.contains(&flag.as_str()) {
+                keep[a] = false;
+                break;
+            }
+        }
+    }
+    let mut deduped = items
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .flat_map(|(item, _)| item.iter().copied())
+        .collect::<Vec<_>>();
+    deduped.extend_from_slice(&args[end..]);
+    *args = deduped;
+}
+
+fn default_command(
     root: impl AsRef<Path>,
-    jobs: Option<&usize>,
+    jobs: Option<usize>,
     context: &Context,
     spec: &CrateSpec,
-) -> io::Result<Child> {
-    let mut cmd = Command::new("cargo");
-    init_command(root, jobs, context, spec, &mut cmd);
+) -> Command {
+    ProviderInput {
+        root: root.as_ref(),
+        jobs,
+        context,
+        spec,
+    }
+    .command()
+}
 
-    cmd.spawn()
+/// Limits the test threads to the number of jobs. This has to be done once all the arguments are
+/// added as everything after the `--` goes to the test binaries, a `--test-threads` already
+/// given for the project is kept
+fn add_test_threads(cmd: &mut Command, jobs: Option<usize>) {
+    let jobs = match jobs.filter(|j| *j > 0) {
+        Some(j) => j,
+        None => return,
+    };
+    let test_args = cmd
+        .get_args()
+        .map(|x| x.to_string_lossy().to_string())
+        .skip_while(|x| x != "--")
+        .collect::<Vec<_>>();
+    if test_args.is_empty() {
+        cmd.arg("--");
+    }
+    if !test_args
+        .iter()
+        .any(|x| x.split('=').next() == Some("--test-threads"))
+    {
+        cmd.args(["--test-threads", jobs.to_string().as_str()]);
+    }
 }
 
-pub fn spawn_tarpaulin(
+/// Adds args to the command before any `--` so they go to tarpaulin and not the test binaries.
+/// Commands can't have arguments inserted so a new one is built
+fn with_tarpaulin_args(cmd: &Command, forced: &[String]) -> Command {
+    let mut new_cmd = new_command(cmd.get_program());
+    let args = cmd.get_args().collect::<Vec<_>>();
+    let split = args.iter().position(|x| *x == "--").unwrap_or(args.len());
+    new_cmd
+        .args(&args[..split])
+        .args(forced)
+        .args(&args[split..]);
+    for (key, value) in cmd.get_envs() {
+        match value {
+            Some(value) => new_cmd.env(key, value),
+            None => new_cmd.env_remove(key),
+        };
+    }
+    if let Some(dir) = cmd.get_current_dir() {
+        new_cmd.current_dir(dir);
+    }
+    new_cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    new_cmd
+}
+
+/// Tarpaulin args which choose the packages to test, if any are given the command is left alone
+/// when checking for a workspace
+const PACKAGE_ARGS: &[&str] = &[
+    "--workspace",
+    "--all",
+    "-p",
+    "--packages",
+    "-e",
+    "--exclude",
+    "--manifest-path",
+    "--config",
+];
+
+/// Whether the command already picks which packages to test
+fn selects_packages(cmd: &Command) -> bool {
+    cmd.get_args()
+        .map(|x| x.to_string_lossy())
+        .take_while(|x| x != "--")
+        .any(|x| {
+            let flag = x.split('=').next().unwrap_or_default();
+            // Short flags can have their value attached like `-pfoo`
+            let short = if flag.starts_with("--") {
+                None
+            } else {
+                flag.get(..2)
+            };
+            PACKAGE_ARGS.contains(&flag) || short.is_some_and(|x| PACKAGE_ARGS.contains(&x))
+        })
+}
+
+/// Builds the tarpaulin command for a project from its CI config, falling back to a plain
+/// `cargo tarpaulin` if no CI config can be converted
+pub fn get_command(
     root: impl AsRef<Path>,
-    jobs: Option<&usize>,
+    jobs: Option<usize>,
     context: &Context,
     spec: &CrateSpec,
-) -> io::Result<Child> {
-    github::get_command(root.as_ref(), jobs, context, spec)
-        .or_else(|_| gitlab::get_command(root.as_ref(), jobs, context, spec))
-        .or_else(|_| travis::get_command(root.as_ref(), jobs, context, spec))
-        .or_else(|_| default_spawn(root, jobs, context, spec))
+) -> Command {
+    detect_command(root, jobs, context, spec).command
+}
+
+/// Builds the tarpaulin command like `get_command`, keeping which CI config it came from
+pub fn detect_command(
+    root: impl AsRef<Path>,
+    jobs: Option<usize>,
+    context: &Context,
+    spec: &CrateSpec,
+) -> Detection {
+    let input = ProviderInput {
+        root: root.as_ref(),
+        jobs,
+        context,
+        spec,
+    };
+    let mut failures = vec![];
+    let mut detected = None;
+    for (provider, get_command) in PROVIDERS {
+        match get_command(&input) {
+            Ok(cmd) => {
+                detected = Some((*provider, cmd));
+                break;
+            }
+            Err(e) => failures.push((*provider, e.to_string())),
+        }
+    }
+    let (provider, mut cmd) = detected.unwrap_or_else(|| {
+        (
+            CiProvider::Default,
+            default_command(input.root, jobs, context, spec),
+        )
+    });
+    if !context.force_args.is_empty() {
+        cmd = with_tarpaulin_args(&cmd, &context.force_args);
+    }
+    // Otherwise tarpaulin only covers the root package
+    if !spec.no_workspace && !selects_packages(&cmd) {
+        let dir = cmd.get_current_dir().unwrap_or(input.root).to_path_buf();
+        let args = metadata::workspace_args(&dir, spec.name().unwrap_or_default());
+        if !args.is_empty() {
+            cmd = with_tarpaulin_args(&cmd, &args);
+        }
+    }
+    add_test_threads(&mut cmd, jobs);
+    Detection {
+        provider,
+        command: cmd,
+        failures,
+    }
+}
+
+/// The command to run tarpaulin with for the project with the engine applied
+pub fn tarpaulin_command(
+    root: impl AsRef<Path>,
+    jobs: Option<usize>,
+    context: &Context,
+    spec: &CrateSpec,
+) -> Command {
+    detect_tarpaulin_command(root, jobs, context, spec).command
+}
+
+/// The command to run tarpaulin with like `tarpaulin_command`, keeping which CI config it came
+/// from
+pub fn detect_tarpaulin_command(
+    root: impl AsRef<Path>,
+    jobs: Option<usize>,
+    context: &Context,
+    spec: &CrateSpec,
+) -> Detection {
+    let mut detection = detect_command(root.as_ref(), jobs, context, spec);
+    detection.command = with_engine(detection.command, root, context, spec);
+    detection
+}
+
+/// The `cargo test` equivalent of the tarpaulin command for the project with the engine applied
+pub fn cargo_test_command(
+    root: impl AsRef<Path>,
+    jobs: Option<usize>,
+    context: &Context,
+    spec: &CrateSpec,
+) -> Command {
+    let cmd = to_cargo_test(&get_command(root.as_ref(), jobs, context, spec));
+    with_engine(cmd, root, context, spec)
+}
+
+/// Tarpaulin flags cargo test doesn't have
+const TARPAULIN_FLAGS: &[&str] = &[
+    "--debug",
+    "--dump-traces",
+    "--ignore-tests",
+    "--include-tests",
+    "--ignore-panics",
+    "--count",
+    "--line",
+    "-l",
+    "--branch",
+    "-b",
+    "--forward",
+    "-f",
+    "--skip-clean",
+    "--force-clean",
+    "--follow-exec",
+    "--no-dead-code",
+    "--ignore-config",
+    "--implicit-test-threads",
+    "--avoid-cfg-tarpaulin",
+    "--fail-immediately",
+    "--print-rust-flags",
+    "--print-rustdoc-flags",
+    "--stderr",
+];
+
+/// Tarpaulin options with a value cargo test doesn't have
+const TARPAULIN_OPTIONS: &[&str] = &[
+    "--out",
+    "-o",
+    "--output-dir",
+    "--exclude-files",
+    "--include-files",
+    "--coveralls",
+    "--report-uri",
+    "--ciserver",
+    "--fail-under",
+    "--run-types",
+    "--timeout",
+    "-t",
+    "--post-test-delay",
+    "--engine",
+    "--config",
+    "--root",
+    "-r",
+    "--profraw-folder",
+];
+
+/// Turns a `cargo tarpaulin` command into the `cargo test` command running the same tests by
+/// removing the tarpaulin only arguments. If tarpaulin is ran directly cargo is used instead,
+/// `cross tarpaulin` becomes `cross test`
+pub fn to_cargo_test(tarpaulin: &Command) -> Command {
+    let program = tarpaulin.get_program();
+    let ran_directly = program != "cargo" && program != "cross";
+    let mut cmd = new_command(if ran_directly {
+        OsStr::new("cargo")
+    } else {
+        program
+    });
+    if ran_directly {
+        cmd.arg("test");
+    }
+    let mut args = tarpaulin
+        .get_args()
+        .map(|x| x.to_string_lossy().to_string());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            cmd.arg(arg).args(args);
+            break;
+        }
+        let name = arg.split('=').next().unwrap_or_default();
+        if arg == "tarpaulin" {
+            cmd.arg("test");
+        } else if TARPAULIN_OPTIONS.contains(&name) {
+            if !arg.contains('=') {
+                args.next();
+            }
+        } else if !TARPAULIN_FLAGS.contains(&name) {
+            cmd.arg(arg);
+        }
+    }
+    for (key, value) in tarpaulin.get_envs() {
+        match value {
+            Some(value) => cmd.env(key, value),
+            None => cmd.env_remove(key),
+        };
+    }
+    if let Some(dir) = tarpaulin.get_current_dir() {
+        cmd.current_dir(dir);
+    }
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    cmd
+}
+
+/// Wraps the command so it's ran by the context's engine
+fn with_engine(
+    mut cmd: Command,
+    root: impl AsRef<Path>,
+    context: &Context,
+    spec: &CrateSpec,
+) -> Command {
+    if context.engine == Engine::Docker {
+        let image = spec
+            .image
+            .as_deref()
+            .or(context.image.as_deref())
+            .unwrap_or(docker::DEFAULT_IMAGE);
+        let name = spec.name().unwrap_or("unnamed_project");
+        cmd = docker::wrap_command(&cmd, root.as_ref(), image, name, context.no_network);
+    } else if context.no_network && !context.cross_for(spec) {
+        cmd = without_network(cmd);
+    }
+    cmd
+}
+
+/// Runs the command in its own network namespace with only loopback up, so tests can still talk
+/// to servers they start themselves. The user namespace lets this work without root, and `ip`
+/// failing just leaves loopback down. Namespaces are linux only so elsewhere this warns and the
+/// network stays up
+fn without_network(cmd: Command) -> Command {
+    if !cfg!(target_os = "linux") {
+        warn!("`no_network` is only supported on linux, the network is still available");
+        return cmd;
+    }
+    let mut unshare = new_command("unshare");
+    unshare
+        .args(["--net", "--map-root-user", "--", "sh", "-c"])
+        .arg(r#"ip link set lo up 2>/dev/null; exec "$@""#)
+        .arg("sh")
+        .arg(cmd.get_program())
+        .args(cmd.get_args());
+    for (key, value) in cmd.get_envs() {
+        match value {
+            Some(value) => unshare.env(key, value),
+            None => unshare.env_remove(key),
+        };
+    }
+    if let Some(dir) = cmd.get_current_dir() {
+        unshare.current_dir(dir);
+    }
+    unshare.stdout(Stdio::piped()).stderr(Stdio::piped());
+    unshare
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
+    pub(crate) use crate::testing::{test_project, TestProject};
+    use std::path::PathBuf;
+
+    pub(crate) fn test_spec() -> CrateSpec {
+        serde_json::from_str(r#"{ "repository_url": "https://github.com/xd009642/tater" }"#)
+            .unwrap()
+    }
+
+    pub(crate) fn args(cmd: &Command) -> Vec<String> {
+        cmd.get_args()
+            .map(|x| x.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn install_features() {
+        let mut context = Context::default();
+        assert_eq!(
+            tarpaulin_install_args(&context),
+            ["install", "cargo-tarpaulin"]
+        );
+        context.tarpaulin_features = vec!["vendored-openssl".to_string(), "foo".to_string()];
+        assert_eq!(
+            tarpaulin_install_args(&context),
+            [
+                "install",
+                "cargo-tarpaulin",
+                "--features",
+                "vendored-openssl,foo"
+            ]
+        );
+    }
+
+    #[test]
+    fn tarpaulin_version_parsed() {
+        assert_eq!(
+            parse_tarpaulin_version("cargo-tarpaulin version: 0.18.0\n").as_deref(),
+            Some("0.18.0")
+        );
+        assert_eq!(
+            parse_tarpaulin_version("cargo-tarpaulin 0.27.3\n").as_deref(),
+            Some("0.27.3")
+        );
+        assert_eq!(parse_tarpaulin_version("error: no such command"), None);
+        assert_eq!(parse_tarpaulin_version(""), None);
+    }
+
+    #[test]
+    fn provider_detected() {
+        let gitlab = "test:\n  script:\n    - cargo test --verbose\n";
+        let root = test_project("detect-gitlab", &[(".gitlab-ci.yml", gitlab)]);
+        let detection = detect_command(&root, None, &Context::default(), &test_spec());
+        assert_eq!(detection.provider, CiProvider::Gitlab);
+        assert_eq!(detection.failures.len(), 1);
+        assert_eq!(detection.failures[0].0, CiProvider::Github);
+        assert!(args(&detection.command).contains(&"--verbose".to_string()));
+
+        let root = test_project("detect-default", &[]);
+        let detection = detect_command(&root, None, &Context::default(), &test_spec());
+        assert_eq!(detection.provider, CiProvider::Default);
+        assert_eq!(
+            detection
+                .failures
+                .iter()
+                .map(|(provider, _)| *provider)
+                .collect::<Vec<_>>(),
+            [CiProvider::Github, CiProvider::Gitlab, CiProvider::Travis]
+        );
+    }
+
+    #[test]
+    fn workspace_added() {
+        let manifest = "[workspace]\nmembers = [\"core\", \"cli\"]\n";
+        let root = test_project("workspace-added", &[("Cargo.toml", manifest)]);
+        let mut spec = test_spec();
+        let cmd = get_command(&root, None, &Context::default(), &spec);
+        assert!(args(&cmd).contains(&"--workspace".to_string()));
+
+        spec.no_workspace = true;
+        let cmd = get_command(&root, None, &Context::default(), &spec);
+        assert!(!args(&cmd).contains(&"--workspace".to_string()));
+
+        // CI already picking the packages is left alone
+        let gitlab = "test:\n  script:\n    - cargo test -p core\n";
+        let root = test_project(
+            "workspace-selected",
+            &[("Cargo.toml", manifest), (".gitlab-ci.yml", gitlab)],
+        );
+        let cmd = get_command(&root, None, &Context::default(), &test_spec());
+        assert!(!args(&cmd).contains(&"--workspace".to_string()));
+
+        let selected = |x: &[&str]| {
+            let mut cmd = Command::new("cargo");
+            cmd.args(x);
+            selects_packages(&cmd)
+        };
+        assert!(selected(&["tarpaulin", "-pcore"]));
+        assert!(selected(&["tarpaulin", "--workspace", "--exclude", "fuzz"]));
+        assert!(selected(&["tarpaulin", "--exclude=fuzz"]));
+        assert!(!selected(&["tarpaulin", "--exclude-files", "fuzz/*"]));
+        assert!(!selected(&["tarpaulin", "--", "-p"]));
+    }
+
+    #[test]
+    fn working_dir_resolved() {
+        let root = test_project("working-dir", &[("crates/core/src/lib.rs", "")]);
+        let spec = test_spec();
+        let core = root.join("crates/core");
+        assert_eq!(working_dir(&root, &spec, None, "", "cargo test"), *root);
+        assert_eq!(
+            working_dir(&root, &spec, None, "", "(cd crates/core && cargo test)"),
+            core
+        );
+        assert_eq!(
+            working_dir(&root, &spec, Some("crates"), "", "cd core; cargo test"),
+            core
+        );
+        // Only directories in the checkout are used
+        assert_eq!(
+            working_dir(&root, &spec, None, "", "cd crates\ncd build\ncargo test"),
+            root.join("crates")
+        );
+        assert_eq!(
+            working_dir(&root, &spec, None, "", "cd ../.. && cargo test"),
+            *root
+        );
+        // A `cd` after the tests doesn't apply to them
+        assert_eq!(
+            working_dir(&root, &spec, None, "", "cargo test && cd crates/core"),
+            *root
+        );
+    }
+
+    #[test]
+    fn working_dir_every_backend() {
+        let github = "on: push
+jobs:
+  test:
+    runs-on: ubuntu-latest
+    steps:
+      - run: cd crates/core && cargo test --verbose
+";
+        let github_key = "on: push
+jobs:
+  test:
+    runs-on: ubuntu-latest
+    steps:
+      - run: cargo test --verbose
+        working-directory: crates/core
+";
+        let gitlab = "test:\n  script:\n    - cd crates/core\n    - cargo test --verbose\n";
+        let travis = "language: rust\nscript:\n  - cd crates/core\n  - cargo test --verbose\n";
+        let backends: [(&str, &str, GetCommand); 4] = [
+            (".github/workflows/ci.yml", github, github::get_command),
+            (".github/workflows/ci.yml", github_key, github::get_command),
+            (".gitlab-ci.yml", gitlab, gitlab::get_command),
+            (".travis.yml", travis, travis::get_command),
+        ];
+        for (i, (path, config, get_command)) in backends.iter().enumerate() {
+            let root = test_project(
+                &format!("working-dir-backend-{}", i),
+                &[(path, config), ("crates/core/src/lib.rs", "")],
+            );
+            let context = Context::default();
+            let command = |spec: &CrateSpec| {
+                get_command(&ProviderInput {
+                    root: &root,
+                    jobs: None,
+                    context: &context,
+                    spec,
+                })
+                .unwrap()
+            };
+            let mut spec = test_spec();
+            let cmd = command(&spec);
+            assert!(args(&cmd).contains(&"--verbose".to_string()), "{}", path);
+            assert_eq!(
+                cmd.get_current_dir(),
+                Some(root.join("crates/core").as_path()),
+                "{}",
+                config
+            );
+
+            // The crate's subdir wins over CI
+            spec.subdir = Some("crates".to_string());
+            assert_eq!(
+                command(&spec).get_current_dir(),
+                Some(root.join("crates").as_path()),
+                "{}",
+                config
+            );
+        }
+    }
+
+    #[test]
+    fn subdir_is_cwd() {
+        let root = Path::new("projects/tater");
+        let mut spec = test_spec();
+        let cmd = default_command(root, None, &Context::default(), &spec);
+        assert_eq!(cmd.get_current_dir(), Some(root));
+
+        spec.subdir = Some("crates/foo".to_string());
+        let cmd = default_command(root, None, &Context::default(), &spec);
+        assert_eq!(
+            cmd.get_current_dir(),
+            Some(root.join("crates/foo").as_path())
+        );
+    }
+
+    #[test]
+    fn tarpaulin_engine_arg() {
+        let mut spec = test_spec();
+        let cmd = default_command(".", None, &Context::default(), &spec);
+        assert!(!args(&cmd).contains(&"--engine".to_string()));
+
+        spec.tarpaulin_engine = Some(CoverageEngine::Llvm);
+        let cmd = default_command(".", None, &Context::default(), &spec);
+        assert!(args(&cmd).windows(2).any(|x| x == ["--engine", "llvm"]));
+    }
+
+    #[test]
+    fn quoted_args_kept() {
+        let args = command_args(r#"cargo +nightly test --features "serde std" -- --skip ''"#)
+            .collect::<Vec<_>>();
+        assert_eq!(args, ["--features", "serde std", "--", "--skip", ""]);
+        let mut args = args;
+        args.push("it's".to_string());
+        let joined = format!("cargo tarpaulin {}", join_args(&args));
+        assert_eq!(command_args(&joined).collect::<Vec<_>>(), args);
+        // Unbalanced quotes are split on whitespace
+        assert_eq!(
+            command_args("cargo test --features \"a b").collect::<Vec<_>>(),
+            ["--features", "\"a", "b"]
+        );
+    }
+
+    #[test]
+    fn build_cache_env() {
+        let env = |cmd: &Command, key: &str| {
+            cmd.get_envs()
+                .find(|(k, _)| *k == key)
+                .and_then(|(_, v)| v)
+                .map(PathBuf::from)
+        };
+        let cmd = get_command("projects/tater", None, &Context::default(), &test_spec());
+        assert_eq!(env(&cmd, "CARGO_TARGET_DIR"), None);
+        assert_eq!(env(&cmd, "RUSTC_WRAPPER"), None);
+
+        let mut context = Context {
+            shared_target_dir: Some(PathBuf::from("shared-target")),
+            sccache: true,
+            ..Default::default()
+        };
+        let cmd = get_command("projects/tater", None, &context, &test_spec());
+        let target = env(&cmd, "CARGO_TARGET_DIR").unwrap();
+        // Tarpaulin runs in the project so the directory has to be absolute
+        assert!(target.is_absolute());
+        assert!(target.ends_with("shared-target"));
+        assert_eq!(env(&cmd, "RUSTC_WRAPPER"), Some(PathBuf::from("sccache")));
+        let no_cache = CrateSpec {
+            no_cache: true,
+            ..test_spec()
+        };
+        let cmd = get_command("projects/tater", None, &context, &no_cache);
+        assert_eq!(env(&cmd, "RUSTC_WRAPPER"), None);
+        assert!(env(&cmd, "CARGO_TARGET_DIR").is_some());
+
+        context.engine = Engine::Docker;
+        let cmd = get_command("projects/tater", None, &context, &test_spec());
+        assert_eq!(env(&cmd, "CARGO_TARGET_DIR"), None);
+        assert_eq!(env(&cmd, "RUSTC_WRAPPER"), None);
+    }
+
+    #[test]
+    fn json_report_requested() {
+        let out = |cmd: &Command| {
+            let cmd_args = args(cmd);
+            cmd_args
+                .windows(2)
+                .filter(|x| x[0] == "--out")
+                .map(|x| x[1].clone())
+                .collect::<Vec<_>>()
+        };
+        let cmd = get_command("projects/tater", None, &Context::default(), &test_spec());
+        assert_eq!(out(&cmd), ["Json"]);
+
+        let mut spec = test_spec();
+        spec.args = vec!["--out".to_string(), "Html".to_string()];
+        let cmd = get_command("projects/tater", None, &Context::default(), &spec);
+        assert_eq!(out(&cmd), ["Json", "Html"]);
+        spec.args = vec!["--out".to_string(), "Json".to_string()];
+        let cmd = get_command("projects/tater", None, &Context::default(), &spec);
+        assert_eq!(out(&cmd), ["Json"]);
+        assert!(!args(&to_cargo_test(&cmd)).contains(&"Json".to_string()));
+    }
+
+    #[test]
+    fn strips_and_forces_args() {
+        let context = Context {
+            strip_args: vec!["-v".to_string()],
+            strip_options: vec!["--message-format".to_string()],
+            force_args: vec!["--skip-clean".to_string()],
+            ..Default::default()
+        };
+        let mut cmd = Command::new("cargo");
+        let found = try_to_populate_command(
+            "cargo test --message-format json -v --lib -- -v",
+            &context,
+            None,
+            &mut cmd,
+        );
+        assert!(found);
+        assert_eq!(args(&cmd), ["--lib", "--", "-v"]);
+
+        let found = ["--message-format=short", "--color=always", "--lib"]
+            .iter()
+            .map(|x| x.to_string());
+        assert_eq!(strip_args(found, &context), ["--lib"]);
+
+        let mut spec = test_spec();
+        spec.args = vec!["--".to_string(), "--nocapture".to_string()];
+        let cmd = get_command("projects/tater", None, &context, &spec);
+        let cmd_args = args(&cmd);
+        assert!(cmd_args.ends_with(&[
+            "--skip-clean".to_string(),
+            "--".to_string(),
+            "--nocapture".to_string()
+        ]));
+        assert_eq!(cmd.get_current_dir(), Some(Path::new("projects/tater")));
+    }
+
+    #[test]
+    fn duplicate_args_removed() {
+        let mut found = vec![
+            "tarpaulin",
+            "--color",
+            "never",
+            "--all-features",
+            "--out",
+            "Xml",
+            "--all-features",
+            "--color=always",
+            "--out",
+            "Html",
+            "--out",
+            "Xml",
+            "--",
+            "--all-features",
+        ];
+        dedup_args(&mut found);
+        assert_eq!(
+            found,
+            [
+                "tarpaulin",
+                "--all-features",
+                "--out",
+                "Xml",
+                "--color=always",
+                "--out",
+                "Html",
+                "--",
+                "--all-features"
+            ]
+        );
+
+        let context = Context {
+            args: vec![
+                "--all-features".to_string(),
+                "--timeout".to_string(),
+                "60".to_string(),
+            ],
+            ..Default::default()
+        };
+        let mut spec = test_spec();
+        spec.args = vec![
+            "--all-features".to_string(),
+            "--timeout".to_string(),
+            "120".to_string(),
+        ];
+        let cmd_args = args(&get_command("projects/tater", None, &context, &spec));
+        assert_eq!(
+            cmd_args.iter().filter(|x| *x == "--all-features").count(),
+            1
+        );
+        assert!(cmd_args.ends_with(&["--timeout".to_string(), "120".to_string()]));
+        assert!(!cmd_args.contains(&"60".to_string()));
+    }
+
+    #[test]
+    fn context_env_wins() {
+        let env = |cmd: &Command, key: &str| {
+            cmd.get_envs()
+                .find(|(k, _)| *k == key)
+                .and_then(|(_, v)| v)
+                .map(|v| v.to_string_lossy().to_string())
+        };
+        let cmd = get_command("projects/tater", None, &Context::default(), &test_spec());
+        assert_eq!(
+            env(&cmd, "RUST_LOG").as_deref(),
+            Some("cargo_tarpaulin=info")
+        );
+
+        let context = Context {
+            env: vec![
+                ("RUST_LOG".to_string(), "cargo_tarpaulin=trace".to_string()),
+                ("RUSTUP_TOOLCHAIN".to_string(), "nightly".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let cmd = get_command("projects/tater", None, &context, &test_spec());
+        assert_eq!(
+            env(&cmd, "RUST_LOG").as_deref(),
+            Some("cargo_tarpaulin=trace")
+        );
+        assert_eq!(env(&cmd, "RUST_BACKTRACE").as_deref(), Some("1"));
+
+        // A toolchain from CI doesn't replace one in the env
+        let mut cmd = get_command("projects/tater", None, &context, &test_spec());
+        assert!(try_to_populate_command(
+            "cargo +stable test --all-features",
+            &context,
+            None,
+            &mut cmd
+        ));
+        assert_eq!(env(&cmd, "RUSTUP_TOOLCHAIN").as_deref(), Some("nightly"));
+    }
+
+    #[test]
+    fn env_vars_expanded() {
+        let mut spec = test_spec();
+        spec.env
+            .insert("COV_DIR".to_string(), "/tmp/cov".to_string());
+        spec.args = vec![
+            "--output-dir".to_string(),
+            "$COV_DIR/tater".to_string(),
+            "--exclude-files=${COV_DIR}x".to_string(),
+            "--features=$TATER_UNDEFINED_VAR".to_string(),
+        ];
+        let context = Context {
+            env: vec![("COV_DIR".to_string(), "/cov".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let cmd_args = args(&get_command(
+            "projects/tater",
+            None,
+            &Context::default(),
+            &spec,
+        ));
+        assert!(cmd_args.ends_with(&[
+            "--output-dir".to_string(),
+            "/tmp/cov/tater".to_string(),
+            "--exclude-files=/tmp/covx".to_string(),
+            "--features=$TATER_UNDEFINED_VAR".to_string(),
+        ]));
+        // The context's env wins like it does when running tarpaulin
+        let cmd_args = args(&get_command("projects/tater", None, &context, &spec));
+        assert!(cmd_args.contains(&"/cov/tater".to_string()));
+    }
+
+    #[test]
+    fn jobs_limit_test_threads() {
+        let root = Path::new("projects/tater");
+        let mut spec = test_spec();
+        let cmd = get_command(root, Some(4), &Context::default(), &spec);
+        let cmd_args = args(&cmd);
+        assert!(cmd_args.ends_with(&[
+            "--".to_string(),
+            "--test-threads".to_string(),
+            "4".to_string()
+        ]));
+        assert!(cmd
+            .get_envs()
+            .any(|(k, v)| k == "RUST_TEST_THREADS" && v == Some("4".as_ref())));
+
+        spec.args = vec!["--".to_string(), "--nocapture".to_string()];
+        let cmd_args = args(&get_command(root, Some(4), &Context::default(), &spec));
+        assert_eq!(cmd_args.iter().filter(|x| *x == "--").count(), 1);
+        assert!(cmd_args.ends_with(&[
+            "--nocapture".to_string(),
+            "--test-threads".to_string(),
+            "4".to_string()
+        ]));
+
+        spec.args = vec![
+            "--".to_string(),
+            "--test-threads".to_string(),
+            "8".to_string(),
+        ];
+        let cmd_args = args(&get_command(root, Some(4), &Context::default(), &spec));
+        assert_eq!(cmd_args.iter().filter(|x| *x == "--").count(), 1);
+        assert_eq!(
+            cmd_args.iter().filter(|x| *x == "--test-threads").count(),
+            1
+        );
+        assert!(cmd_args.ends_with(&["--test-threads".to_string(), "8".to_string()]));
+
+        let cmd_args = args(&get_command(root, None, &Context::default(), &spec));
+        assert!(cmd_args.ends_with(&["8".to_string()]));
+
+        spec.args.clear();
+        let cmd = get_command(root, Some(0), &Context::default(), &spec);
+        assert!(!args(&cmd).iter().any(|x| x == "--jobs" || x == "--"));
+        assert!(!cmd.get_envs().any(|(k, _)| k == "RUST_TEST_THREADS"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn no_network_namespace() {
+        let context = Context {
+            no_network: true,
+            ..Default::default()
+        };
+        let spec = test_spec();
+        let cmd = default_command(".", None, &context, &spec);
+        assert!(args(&cmd).contains(&"--offline".to_string()));
+
+        let isolated = tarpaulin_command(".", None, &context, &spec);
+        assert_eq!(isolated.get_program(), "unshare");
+        let isolated_args = args(&isolated);
+        assert_eq!(isolated_args[..2], ["--net", "--map-root-user"]);
+        assert!(isolated_args.ends_with(&args(&cmd)));
+        assert_eq!(isolated.get_current_dir(), cmd.get_current_dir());
+
+        let networked = tarpaulin_command(".", None, &Context::default(), &spec);
+        assert_eq!(networked.get_program(), "cargo");
+    }
+
+    #[test]
+    fn cross_invocation() {
+        let context = Context {
+            cross: true,
+            toolchain: "nightly".to_string(),
+            ..Default::default()
+        };
+        let spec = test_spec();
+        // Nothing to cross compile for without a target
+        assert_eq!(
+            tarpaulin_command(".", None, &context, &spec).get_program(),
+            "cargo"
+        );
+
+        let spec = CrateSpec {
+            target: Some("aarch64-unknown-linux-gnu".to_string()),
+            ..spec
+        };
+        let cmd = tarpaulin_command(".", Some(2), &context, &spec);
+        assert_eq!(cmd.get_program(), "cross");
+        let cmd_args = args(&cmd);
+        assert_eq!(cmd_args[..4], ["+nightly", "--jobs", "2", "tarpaulin"]);
+        assert!(cmd_args
+            .windows(2)
+            .any(|x| x == ["--target", "aarch64-unknown-linux-gnu"]));
+        let opts = cmd
+            .get_envs()
+            .find(|(k, _)| *k == "CROSS_CONTAINER_OPTS")
+            .and_then(|(_, v)| v);
+        assert_eq!(opts, Some(std::ffi::OsStr::new("--name tater-tater")));
+        assert!(context.in_container(&spec));
+
+        let test = to_cargo_test(&cmd);
+        assert_eq!(test.get_program(), "cross");
+        assert_eq!(args(&test)[..2], ["+nightly", "--jobs"]);
+        assert!(args(&test).contains(&"test".to_string()));
+        assert!(!args(&test).contains(&"tarpaulin".to_string()));
+
+        let docker = Context {
+            engine: Engine::Docker,
+            ..context
+        };
+        assert!(!docker.cross_for(&spec));
+    }
+
+    #[test]
+    fn tarpaulin_path_ran_directly() {
+        let context = Context {
+            tarpaulin_path: Some(PathBuf::from("/tarpaulin/target/debug/cargo-tarpaulin")),
+            ..Default::default()
+        };
+        let cmd = default_command(".", Some(2), &context, &test_spec());
+        assert_eq!(cmd.get_program(), "/tarpaulin/target/debug/cargo-tarpaulin");
+        assert_eq!(
+            args(&cmd)[..5],
+            ["--jobs", "2", "--debug", "--color", "never"]
+        );
+
+        let test = to_cargo_test(&cmd);
+        assert_eq!(test.get_program(), "cargo");
+        assert_eq!(
+            args(&test)[..5],
+            ["test", "--jobs", "2", "--color", "never"]
+        );
+
+        assert!(
+            TarpaulinBinary::inspect(Path::new("/tarpaulin/target/debug/cargo-tarpaulin")).is_err()
+        );
+    }
+
+    #[test]
+    fn tarpaulin_to_cargo_test() {
+        let mut cmd = Command::new("cargo");
+        cmd.args([
+            "tarpaulin",
+            "--debug",
+            "--color",
+            "never",
+            "--out",
+            "Xml",
+            "--all-features",
+            "--timeout=120",
+            "--",
+            "--debug",
+        ])
+        .env("FOO", "bar")
+        .current_dir("projects/foo");
+
+        let test = to_cargo_test(&cmd);
+        assert_eq!(
+            args(&test),
+            [
+                "test",
+                "--color",
+                "never",
+                "--all-features",
+                "--",
+                "--debug"
+            ]
+        );
+        assert_eq!(test.get_current_dir(), Some(Path::new("projects/foo")));
+        assert!(test
+            .get_envs()
+            .any(|(k, v)| k == "FOO" && v == Some("bar".as_ref())));
+    }
+
+    #[test]
+    fn multiple_commands_config() {
+        let root = test_project(
+            "multiple_commands_config",
+            &[("Cargo.toml", "[package]\nname = \"foo\"\n")],
+        );
+        let mut cmd = Command::new("cargo");
+        cmd.current_dir(&root);
+        let script = "cargo test --features \"a b\" -F c --lib parser && \
+                      cargo test -v -p foo --no-default-features --out Html Lcov -- --ignored";
+        let path = root.join("configs/foo.toml");
+        assert!(try_to_populate_command(
+            script,
+            &Context::default(),
+            Some(&path),
+            &mut cmd
+        ));
+        assert_eq!(
+            args(&cmd),
+            ["--config".to_string(), path.display().to_string()]
+        );
+        let config = std::fs::read_to_string(&path).unwrap();
+        let manifest = toml_string(
+            &root
+                .canonicalize()
+                .unwrap()
+                .join("Cargo.toml")
+                .to_string_lossy(),
+        );
+        assert_eq!(
+            config,
+            format!(
+                r#"[packages.command_1]
+manifest-path = {0}
+features = "a b c"
+lib = true
+args = ["parser"]
+
+[packages.command_2]
+manifest-path = {0}
+verbose = true
+packages = ["foo"]
+no-default-features = true
+out = ["Html", "Lcov"]
+args = ["--ignored"]
+"#,
+                manifest
+            )
+        );
+        // Nothing is written to the project
+        assert!(!root.join("tarpaulin.toml").exists());
+
+        // Without anywhere to write the config to only the first is ran
+        let mut cmd = Command::new("cargo");
+        cmd.current_dir(&root);
+        try_to_populate_command(script, &Context::default(), None, &mut cmd);
+        assert_eq!(
+            args(&cmd),
+            ["--features", "a b", "-F", "c", "--lib", "parser"]
+        );
+    }
 
     #[test]
     fn command_regex_test() {
@@ -139,5 +1854,148 @@ mod test {
             extract_tarpaulin_commands("cargo test\n -- hello"),
             vec!["cargo tarpaulin".to_string()]
         );
+        assert_eq!(
+            extract_tarpaulin_commands("cargo +nightly test --all-features"),
+            vec!["cargo +nightly tarpaulin --all-features".to_string()]
+        );
+        assert_eq!(
+            extract_tarpaulin_commands("cargo +1.56.0 test"),
+            vec!["cargo +1.56.0 tarpaulin".to_string()]
+        );
+        assert_eq!(
+            extract_tarpaulin_commands("cargo test --all --features=\"extras\""),
+            vec!["cargo tarpaulin --all --features=\"extras\"".to_string()]
+        );
+    }
+
+    #[test]
+    fn cross_commands() {
+        let commands =
+            extract_tarpaulin_commands("cross test --target aarch64-unknown-linux-gnu --lib");
+        assert_eq!(
+            commands,
+            ["cargo tarpaulin --target aarch64-unknown-linux-gnu --lib"]
+        );
+        assert_eq!(
+            cross_target(&commands[0]),
+            Some("aarch64-unknown-linux-gnu")
+        );
+        assert_eq!(
+            extract_tarpaulin_commands("cross +nightly test --target x86_64-unknown-linux-musl"),
+            ["cargo +nightly tarpaulin --target x86_64-unknown-linux-musl"]
+        );
+        assert_eq!(
+            cross_target("cross test --target=armv7-unknown-linux-gnueabihf"),
+            Some("armv7-unknown-linux-gnueabihf")
+        );
+
+        let mut cmd = Command::new("cargo");
+        assert!(try_to_populate_command(
+            "cross test --target aarch64-unknown-linux-gnu",
+            &Context::default(),
+            None,
+            &mut cmd
+        ));
+        assert_eq!(args(&cmd), ["--target", "aarch64-unknown-linux-gnu"]);
+    }
+
+    #[test]
+    fn toolchain_used() {
+        let spec = test_spec();
+        let context = Context {
+            toolchain: "nightly".to_string(),
+            ..Default::default()
+        };
+        let cmd = get_command("projects/tater", None, &context, &spec);
+        assert_eq!(args(&cmd)[..2], ["+nightly", "tarpaulin"]);
+        assert!(cmd.get_envs().all(|(k, _)| k != "RUSTUP_TOOLCHAIN"));
+
+        // A toolchain in CI is used when the repos file doesn't set one
+        let mut cmd = Command::new("cargo");
+        try_to_populate_command(
+            "cargo +nightly test --all-features",
+            &Context::default(),
+            None,
+            &mut cmd,
+        );
+        assert_eq!(args(&cmd), ["--all-features"]);
+        assert!(cmd
+            .get_envs()
+            .any(|(k, v)| k == "RUSTUP_TOOLCHAIN" && v == Some("nightly".as_ref())));
+        let mut cmd = Command::new("cargo");
+        try_to_populate_command("cargo +nightly test", &context, None, &mut cmd);
+        assert!(cmd.get_envs().all(|(k, _)| k != "RUSTUP_TOOLCHAIN"));
+    }
+}
+
+#[cfg(test)]
+mod prop_tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    lazy_static! {
+        /// What every extracted command starts with, the toolchain the CI used is kept
+        static ref TARPAULIN_PREFIX: Regex = Regex::new(r"^cargo (\+\S+ )?tarpaulin").unwrap();
+    }
+
+    /// A `cargo test` or `cross test` invocation with arguments and how the shell line ends
+    fn invocation() -> impl Strategy<Value = String> {
+        (
+            prop_oneof![Just("cargo"), Just("cross")],
+            prop::option::of("\\+[a-z0-9.\\-]{1,10}"),
+            "[ \t]{1,3}",
+            prop::collection::vec("--?[a-z][a-z\\-]{0,10}( [a-z0-9]{1,5})?", 0..4),
+            prop_oneof![
+                Just(""),
+                Just(";"),
+                Just(" && echo done"),
+                Just(" \\\n  --verbose"),
+                Just(" \\\n"),
+            ],
+        )
+            .prop_map(|(program, toolchain, space, args, end)| {
+                let mut cmd = program.to_string();
+                if let Some(toolchain) = toolchain {
+                    cmd.push_str(&space);
+                    cmd.push_str(&toolchain);
+                }
+                cmd.push_str(&space);
+                cmd.push_str("test");
+                for arg in args {
+                    cmd.push(' ');
+                    cmd.push_str(&arg);
+                }
+                cmd + end
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn extracted_commands_are_tarpaulin(
+            before in "[ -~\n]{0,40}",
+            invocation in invocation(),
+            after in "[ -~\n]{0,40}",
+        ) {
+            let input = format!("{}\n{}\n{}", before, invocation, after);
+            let commands = extract_tarpaulin_commands(&input);
+            prop_assert!(!commands.is_empty(), "nothing found in {:?}", input);
+            for command in &commands {
+                prop_assert!(
+                    TARPAULIN_PREFIX.is_match(command),
+                    "{:?} from {:?}",
+                    command,
+                    input
+                );
+                prop_assert!(!command.contains('\n'), "{:?} from {:?}", command, input);
+            }
+        }
+
+        #[test]
+        fn arbitrary_input_handled(input in "[ -~\n\t]{0,200}") {
+            for command in extract_tarpaulin_commands(&input) {
+                prop_assert!(TARPAULIN_PREFIX.is_match(&command), "{:?}", command);
+                prop_assert!(!command.contains('\n'), "{:?}", command);
+            }
+        }
     }
 }
@@ -3,42 +3,41 @@ use crate::ci::*;
 use serde::Deserialize;
 use std::fs;
 use std::io;
-use std::path::Path;
-use std::process::{Child, Command};
+use std::process::Command;
 
 #[derive(Debug, Deserialize)]
 pub struct Workflow {
-    language: Option<String>,
     #[serde(default)]
     script: Vec<String>,
     after_success: Option<SingleOrMultiString>,
 }
 
-pub fn get_command(
-    root: impl AsRef<Path>,
-    jobs: Option<&usize>,
-    context: &Context,
-    spec: &CrateSpec,
-) -> io::Result<Child> {
-    let workflow = root.as_ref().join(".travis.yml");
+pub fn get_command(input: &ProviderInput) -> io::Result<Command> {
+    let workflow = input.root.join(".travis.yml");
     if workflow.exists() {
         let workflow = fs::File::open(workflow)?;
         let workflow: Workflow = serde_yaml::from_reader(workflow)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
 
-        let mut cmd = Command::new("cargo");
-        init_command(root.as_ref(), jobs, context, spec, &mut cmd);
+        let mut cmd = input.command();
+        // Every phase runs in the same shell, so a `cd` in the script still applies after it
+        let mut earlier = String::new();
         if let Some(after_success) = workflow.after_success.as_ref() {
-            for line in after_success.lines() {
-                if try_to_populate_command(line, &mut cmd) {
-                    return cmd.spawn();
+            earlier = workflow.script.join("\n");
+            for command in after_success.iter_commands() {
+                if populate_command(input, None, &earlier, &command, &mut cmd) {
+                    return Ok(cmd);
                 }
+                earlier.push('\n');
+                earlier.push_str(&command);
             }
         } else {
             for line in &workflow.script {
-                if try_to_populate_command(line.as_str(), &mut cmd) {
-                    return cmd.spawn();
+                if populate_command(input, None, &earlier, line, &mut cmd) {
+                    return Ok(cmd);
                 }
+                earlier.push('\n');
+                earlier.push_str(line);
             }
         }
         Err(io::Error::new(
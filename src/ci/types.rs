@@ -1,3 +1,4 @@
+use crate::ci::FIX_LINES;
 use serde::Deserialize;
 use std::borrow::Cow;
 
@@ -17,6 +18,14 @@ impl SingleOrMultiString {
         }
     }
 
+    /// The shell commands in the script with line continuations joined, split on newlines and `;`
+    pub fn iter_commands<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        match self {
+            Self::Single(ref s) => commands(s),
+            Self::Multi(ref s) => Box::new(s.iter().flat_map(|x| commands(x))),
+        }
+    }
+
     pub fn joined<'a>(&'a self) -> Cow<'a, str> {
         match self {
             Self::Single(ref s) => Cow::Borrowed(s.as_str()),
@@ -24,3 +33,50 @@ impl SingleOrMultiString {
         }
     }
 }
+
+fn split_commands(script: &str) -> impl Iterator<Item = &str> {
+    script
+        .split(['\n', ';'])
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+}
+
+fn commands(script: &str) -> Box<dyn Iterator<Item = Cow<'_, str>> + '_> {
+    match FIX_LINES.replace_all(script, " ") {
+        Cow::Borrowed(script) => Box::new(split_commands(script).map(Cow::Borrowed)),
+        Cow::Owned(script) => Box::new(
+            split_commands(&script)
+                .map(|x| Cow::Owned(x.to_string()))
+                .collect::<Vec<_>>()
+                .into_iter(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_split() {
+        let script = SingleOrMultiString::Single(
+            "cargo build; cargo tarpaulin --all-features \\\n--out Xml\n\ncargo doc\n".to_string(),
+        );
+        assert_eq!(
+            script.iter_commands().collect::<Vec<_>>(),
+            [
+                "cargo build",
+                "cargo tarpaulin --all-features  --out Xml",
+                "cargo doc"
+            ]
+        );
+        let script = SingleOrMultiString::Multi(vec![
+            "cargo build".to_string(),
+            "cargo test \\\n--lib; cargo doc".to_string(),
+        ]);
+        assert_eq!(
+            script.iter_commands().collect::<Vec<_>>(),
+            ["cargo build", "cargo test  --lib", "cargo doc"]
+        );
+    }
+}
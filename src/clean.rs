@@ -0,0 +1,177 @@
+use crate::report::*;
+use std::collections::HashSet;
+use std::fs::{read_dir, remove_dir_all};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use tracing::{error, info};
+
+/// Removing more project directories than this asks for confirmation first
+const CONFIRM_OVER: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, StructOpt)]
+pub struct CleanArgs {
+    /// Only remove projects that passed in the latest run
+    #[structopt(long = "clean-passing", conflicts_with = "clean-all")]
+    pub clean_passing: bool,
+    /// Remove every project directory, including ones that haven't been ran
+    #[structopt(long = "clean-all")]
+    pub clean_all: bool,
+    /// List the directories that would be removed without removing them
+    #[structopt(long = "dry-run")]
+    pub dry_run: bool,
+    /// Don't ask for confirmation before removing more than 10 directories
+    #[structopt(long = "yes", short = "y")]
+    pub yes: bool,
+}
+
+/// Project names in the pass file, results for a single engine are named `<project>/<engine>`
+fn passing(run: &RunDirs) -> HashSet<String> {
+    read_status_file(&run.status.join("pass"), "pass")
+        .into_iter()
+        .filter_map(|x| x.name.split('/').next().map(String::from))
+        .collect()
+}
+
+/// Projects which have a result in the latest run so removing them doesn't lose anything
+fn with_results(run: &RunDirs) -> HashSet<String> {
+    read_dir(&run.results)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|x| x.file_name().to_string_lossy().to_string())
+        .filter(|name| {
+            let dir = run.results.join(name);
+            dir.join(RESULT_FILE).is_file()
+                || read_dir(dir)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .any(|x| x.path().join(RESULT_FILE).is_file())
+        })
+        .collect()
+}
+
+/// The project directories to remove. By default these are the projects with results in the
+/// latest run
+pub fn to_remove(output: &Path, args: &CleanArgs) -> io::Result<Vec<PathBuf>> {
+    let run = RunDirs::latest(output);
+    let only = if args.clean_all {
+        None
+    } else if args.clean_passing {
+        Some(passing(&run))
+    } else {
+        Some(with_results(&run))
+    };
+    let mut dirs = read_dir(output.join("projects"))?
+        .flatten()
+        .filter(|x| x.path().is_dir())
+        .filter(|x| match only.as_ref() {
+            Some(names) => names.contains(x.file_name().to_string_lossy().as_ref()),
+            None => true,
+        })
+        .map(|x| x.path())
+        .collect::<Vec<_>>();
+    dirs.sort();
+    Ok(dirs)
+}
+
+fn confirm(count: usize) -> bool {
+    print!("Remove {} project directories? [y/N] ", count);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    let _ = io::stdin().lock().read_line(&mut answer);
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Removes cloned projects from the output directory, the results are left alone
+pub fn clean(output: &Path, args: &CleanArgs) -> Result<(), String> {
+    let dirs = to_remove(output, args).map_err(|e| {
+        format!(
+            "Unable to read projects in {}: {}",
+            output.join("projects").display(),
+            e
+        )
+    })?;
+    if args.dry_run {
+        for dir in &dirs {
+            println!("{}", dir.display());
+        }
+        return Ok(());
+    }
+    if dirs.len() > CONFIRM_OVER && !args.yes && !confirm(dirs.len()) {
+        info!("Not removing anything");
+        return Ok(());
+    }
+    let mut removed = 0;
+    for dir in &dirs {
+        match remove_dir_all(dir) {
+            Ok(()) => removed += 1,
+            Err(e) => error!("Failed to remove {}: {}", dir.display(), e),
+        }
+    }
+    info!("Removed {} project directories", removed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_project;
+    use std::fs;
+
+    #[test]
+    fn chooses_projects() {
+        let output = test_project("clean", &[]);
+        for name in ["a", "b", "c", "d"] {
+            fs::create_dir_all(output.join("projects").join(name)).unwrap();
+        }
+        for name in ["a/ptrace", "b", "c"] {
+            let dir = output.join("results").join(name);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(RESULT_FILE), "{}").unwrap();
+        }
+        fs::write(
+            output.join("pass"),
+            "a/ptrace\tpass\t2021-03-01T00:00:00Z\t10.0\nb\n",
+        )
+        .unwrap();
+        fs::write(output.join("fail"), "c\tcompile_error\n").unwrap();
+
+        let args = CleanArgs {
+            clean_passing: false,
+            clean_all: false,
+            dry_run: true,
+            yes: false,
+        };
+        let names = |args: &CleanArgs| {
+            to_remove(&output, args)
+                .unwrap()
+                .iter()
+                .map(|x| x.file_name().unwrap().to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&args), ["a", "b", "c"]);
+        let passing = CleanArgs {
+            clean_passing: true,
+            ..args.clone()
+        };
+        assert_eq!(names(&passing), ["a", "b"]);
+        let all = CleanArgs {
+            clean_all: true,
+            ..args.clone()
+        };
+        assert_eq!(names(&all), ["a", "b", "c", "d"]);
+
+        clean(&output, &args).unwrap();
+        assert!(output.join("projects/a").is_dir());
+        let remove = CleanArgs {
+            dry_run: false,
+            ..passing
+        };
+        clean(&output, &remove).unwrap();
+        assert!(!output.join("projects/a").exists());
+        assert!(output.join("projects/c").is_dir());
+        assert!(output.join("results/b").join(RESULT_FILE).is_file());
+    }
+}
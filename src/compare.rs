@@ -0,0 +1,606 @@
+use crate::report::*;
+use crate::runner::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{read_dir, File};
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use structopt::StructOpt;
+use tracing::{error, info};
+
+#[derive(Debug, Clone, PartialEq, StructOpt)]
+pub struct DiffArgs {
+    /// Output directory of the earlier run
+    #[structopt(name = "before")]
+    pub before: PathBuf,
+    /// Output directory of the later run
+    #[structopt(name = "after")]
+    pub after: PathBuf,
+    /// Coverage changes in percentage points above this are reported, drops count as regressions
+    #[structopt(long = "threshold", default_value = "1.0")]
+    pub threshold: f64,
+    /// Output format, text or json
+    #[structopt(long = "format", default_value = "text")]
+    pub format: DiffFormat,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DiffFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for DiffFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            e => Err(format!("Unsupported format: {}", e)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct StatusChange<'a> {
+    name: &'a str,
+    before: Status,
+    after: Status,
+}
+
+#[derive(Debug, Serialize)]
+struct CoverageChange<'a> {
+    name: &'a str,
+    before: f64,
+    after: f64,
+}
+
+/// JSON output of the diff subcommand
+#[derive(Debug, Serialize)]
+struct DiffReport<'a> {
+    newly_failing: Vec<StatusChange<'a>>,
+    newly_passing: Vec<StatusChange<'a>>,
+    coverage_changes: Vec<CoverageChange<'a>>,
+    file_changes: &'a [FileChanges],
+    only_in_before: &'a [String],
+    only_in_after: &'a [String],
+    regressions: usize,
+}
+
+impl<'a> From<&'a Comparison> for DiffReport<'a> {
+    fn from(comparison: &'a Comparison) -> Self {
+        Self {
+            newly_failing: comparison
+                .regressed
+                .iter()
+                .map(|(name, after)| StatusChange {
+                    name,
+                    before: Status::Pass,
+                    after: *after,
+                })
+                .collect(),
+            newly_passing: comparison
+                .fixed
+                .iter()
+                .map(|(name, before)| StatusChange {
+                    name,
+                    before: *before,
+                    after: Status::Pass,
+                })
+                .collect(),
+            coverage_changes: comparison
+                .coverage_changes
+                .iter()
+                .map(|(name, before, after)| CoverageChange {
+                    name,
+                    before: *before,
+                    after: *after,
+                })
+                .collect(),
+            file_changes: &comparison.file_changes,
+            only_in_before: &comparison.only_in_baseline,
+            only_in_after: &comparison.only_in_current,
+            regressions: comparison.regressions(),
+        }
+    }
+}
+
+/// What we compare between runs for a project
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectState {
+    pub status: Status,
+    /// Coverage percentage if tarpaulin reported it
+    pub coverage: Option<f64>,
+    /// Source files in tarpaulin's JSON report if it wrote one
+    pub files: Option<Vec<String>>,
+}
+
+impl From<ProjectResult> for ProjectState {
+    fn from(result: ProjectResult) -> Self {
+        let coverage = match result.coverage {
+            Some(CoverageReport::Found(c)) => Some(c.percentage),
+            _ => None,
+        };
+        Self {
+            status: result.status,
+            coverage,
+            files: result.report_files,
+        }
+    }
+}
+
+/// Source files that dropped out of or showed up in a project's coverage report, these can
+/// change without the coverage percentage moving
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileChanges {
+    pub name: String,
+    /// In the baseline's report but not the current one
+    pub disappeared: Vec<String>,
+    /// In the current report but not the baseline's
+    pub appeared: Vec<String>,
+}
+
+impl FileChanges {
+    fn new(name: &str, before: &[String], after: &[String]) -> Option<Self> {
+        let missing_from = |files: &[String], other: &[String]| {
+            files
+                .iter()
+                .filter(|x| !other.contains(x))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let disappeared = missing_from(before, after);
+        let appeared = missing_from(after, before);
+        if disappeared.is_empty() && appeared.is_empty() {
+            None
+        } else {
+            Some(Self {
+                name: name.to_string(),
+                disappeared,
+                appeared,
+            })
+        }
+    }
+}
+
+/// Differences between a baseline run and the current one, or between two runs in general
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Comparison {
+    /// Passed in the baseline but not now
+    pub regressed: Vec<(String, Status)>,
+    /// Didn't pass in the baseline but do now
+    pub fixed: Vec<(String, Status)>,
+    /// Projects where the coverage changed by more than the threshold, baseline then current
+    pub coverage_changes: Vec<(String, f64, f64)>,
+    /// Projects where the files in the coverage report changed
+    pub file_changes: Vec<FileChanges>,
+    pub only_in_baseline: Vec<String>,
+    pub only_in_current: Vec<String>,
+}
+
+/// Reads the result of every project in a results directory which was actually ran. If `subdir`
+/// is given the results are read from that directory in each project's results, otherwise a run
+/// comparing engines has a result for each engine named `crate/engine`
+pub fn read_results(results: &Path, subdir: Option<&str>) -> BTreeMap<String, ProjectState> {
+    let mut states = BTreeMap::new();
+    let entries = match read_dir(results) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Unable to read results in {}: {}", results.display(), e);
+            return states;
+        }
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let found = match subdir {
+            Some(subdir) => read_result(&result_file(&results.join(&name), subdir))
+                .map(|x| (name, x))
+                .into_iter()
+                .collect(),
+            None => crate_results(results, &name)
+                .into_iter()
+                .map(|x| (x.name.clone(), x))
+                .collect::<Vec<_>>(),
+        };
+        for (name, result) in found {
+            if result.status != Status::Skipped {
+                states.insert(name, result.into());
+            }
+        }
+    }
+    states
+}
+
+impl Comparison {
+    pub fn new(
+        baseline: &BTreeMap<String, ProjectState>,
+        current: &BTreeMap<String, ProjectState>,
+        threshold: f64,
+    ) -> Self {
+        let mut comparison = Self::default();
+        for (name, before) in baseline {
+            let after = match current.get(name) {
+                Some(after) => after,
+                None => {
+                    comparison.only_in_baseline.push(name.clone());
+                    continue;
+                }
+            };
+            match (before.status == Status::Pass, after.status == Status::Pass) {
+                (true, false) => comparison.regressed.push((name.clone(), after.status)),
+                (false, true) => comparison.fixed.push((name.clone(), before.status)),
+                _ => {}
+            }
+            if let (Some(before), Some(after)) = (before.coverage, after.coverage) {
+                if (after - before).abs() > threshold {
+                    comparison
+                        .coverage_changes
+                        .push((name.clone(), before, after));
+                }
+            }
+            if let (Some(before), Some(after)) = (before.files.as_ref(), after.files.as_ref()) {
+                comparison
+                    .file_changes
+                    .extend(FileChanges::new(name, before, after));
+            }
+        }
+        comparison.only_in_current = current
+            .keys()
+            .filter(|x| !baseline.contains_key(*x))
+            .cloned()
+            .collect();
+        comparison
+    }
+
+    /// Projects that now fail, whose coverage dropped by more than the threshold or that lost
+    /// files from their coverage report. A project is counted once for each of these
+    pub fn regressions(&self) -> usize {
+        self.regressed.len()
+            + self
+                .coverage_changes
+                .iter()
+                .filter(|(_, before, after)| after < before)
+                .count()
+            + self
+                .file_changes
+                .iter()
+                .filter(|x| !x.disappeared.is_empty())
+                .count()
+    }
+
+    /// Writes the comparison as markdown tables, `before` and `after` name the two runs
+    pub fn write_markdown(
+        &self,
+        mut writer: impl Write,
+        before: &str,
+        after: &str,
+    ) -> io::Result<()> {
+        writeln!(writer, "# Passing with {} only\n", before)?;
+        writeln!(writer, "| Crate | Status |\n|---|---|")?;
+        for (name, status) in &self.regressed {
+            writeln!(writer, "| {} | pass -> {} |", name, status)?;
+        }
+        writeln!(writer, "\n# Passing with {} only\n", after)?;
+        writeln!(writer, "| Crate | Status |\n|---|---|")?;
+        for (name, status) in &self.fixed {
+            writeln!(writer, "| {} | {} -> pass |", name, status)?;
+        }
+        writeln!(writer, "\n# Coverage changes\n")?;
+        writeln!(
+            writer,
+            "| Crate | {} | {} | Change |\n|---|---|---|---|",
+            before, after
+        )?;
+        for (name, before, after) in &self.coverage_changes {
+            writeln!(
+                writer,
+                "| {} | {:.2}% | {:.2}% | {:+.2}% |",
+                name,
+                before,
+                after,
+                after - before
+            )?;
+        }
+        writeln!(writer, "\n# Files in the coverage report\n")?;
+        writeln!(
+            writer,
+            "| Crate | Only with {} | Only with {} |\n|---|---|---|",
+            before, after
+        )?;
+        for changes in &self.file_changes {
+            writeln!(
+                writer,
+                "| {} | {} | {} |",
+                changes.name,
+                changes.disappeared.join("<br>"),
+                changes.appeared.join("<br>")
+            )?;
+        }
+        writeln!(writer, "\n# Only ran with {}\n", before)?;
+        for name in &self.only_in_baseline {
+            writeln!(writer, "* {}", name)?;
+        }
+        writeln!(writer, "\n# Only ran with {}\n", after)?;
+        for name in &self.only_in_current {
+            writeln!(writer, "* {}", name)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the comparison as plain text for the diff subcommand
+    pub fn write_text(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "Newly failing:")?;
+        for (name, status) in &self.regressed {
+            writeln!(writer, "  {}: pass -> {}", name, status)?;
+        }
+        writeln!(writer, "Newly passing:")?;
+        for (name, status) in &self.fixed {
+            writeln!(writer, "  {}: {} -> pass", name, status)?;
+        }
+        writeln!(writer, "Coverage changes:")?;
+        for (name, before, after) in &self.coverage_changes {
+            writeln!(
+                writer,
+                "  {}: {:.2}% -> {:.2}% ({:+.2}%)",
+                name,
+                before,
+                after,
+                after - before
+            )?;
+        }
+        writeln!(writer, "Report file changes:")?;
+        for changes in &self.file_changes {
+            writeln!(writer, "  {}:", changes.name)?;
+            for file in &changes.disappeared {
+                writeln!(writer, "    - {}", file)?;
+            }
+            for file in &changes.appeared {
+                writeln!(writer, "    + {}", file)?;
+            }
+        }
+        writeln!(writer, "Only in before:")?;
+        for name in &self.only_in_baseline {
+            writeln!(writer, "  {}", name)?;
+        }
+        writeln!(writer, "Only in after:")?;
+        for name in &self.only_in_current {
+            writeln!(writer, "  {}", name)?;
+        }
+        writeln!(writer, "{} regressions", self.regressions())
+    }
+}
+
+/// Compares the results of two output directories printing the differences to stdout. Returns
+/// the number of regressions
+pub fn diff(args: &DiffArgs) -> Result<usize, String> {
+    let read = |output: &Path| {
+        let results = RunDirs::latest(output).results;
+        if results.is_dir() {
+            Ok(read_results(&results, None))
+        } else {
+            Err(format!("No results directory in {}", output.display()))
+        }
+    };
+    let comparison = Comparison::new(&read(&args.before)?, &read(&args.after)?, args.threshold);
+    let stdout = io::stdout();
+    let mut writer = stdout.lock();
+    let res = match args.format {
+        DiffFormat::Text => comparison.write_text(&mut writer),
+        DiffFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &DiffReport::from(&comparison))
+                .map_err(io::Error::from)
+                .and_then(|_| writeln!(writer))
+        }
+    };
+    res.map_err(|e| format!("Failed to write diff: {}", e))?;
+    Ok(comparison.regressions())
+}
+
+fn write_comparison(path: &Path, comparison: &Comparison, before: &str, after: &str) {
+    let write = || -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        comparison.write_markdown(&mut writer, before, after)?;
+        writer.flush()
+    };
+    if let Err(e) = write() {
+        error!("Failed to write {}: {}", path.display(), e);
+    }
+}
+
+/// Compares the results in the output directory against a baseline output directory writing
+/// `regressions.md`. Returns the number of regressions
+pub fn compare_runs(baseline: &Path, output: &Path, threshold: f64) -> usize {
+    let comparison = Comparison::new(
+        &read_results(&RunDirs::latest(baseline).results, None),
+        &read_results(&RunDirs::latest(output).results, None),
+        threshold,
+    );
+    write_comparison(
+        &output.join("regressions.md"),
+        &comparison,
+        "baseline",
+        "current",
+    );
+    let regressions = comparison.regressions();
+    if regressions > 0 {
+        error!(
+            "{} regressions compared to {}",
+            regressions,
+            baseline.display()
+        );
+    } else {
+        info!("No regressions compared to {}", baseline.display());
+    }
+    regressions
+}
+
+/// Compares the results of running the projects with the ptrace and llvm engines writing
+/// `engines.md`
+pub fn compare_engines(output: &Path, threshold: f64) {
+    let results = RunDirs::latest(output).results;
+    let ptrace = CoverageEngine::Ptrace.to_string();
+    let llvm = CoverageEngine::Llvm.to_string();
+    let comparison = Comparison::new(
+        &read_results(&results, Some(&ptrace)),
+        &read_results(&results, Some(&llvm)),
+        threshold,
+    );
+    write_comparison(&output.join("engines.md"), &comparison, &ptrace, &llvm);
+    info!(
+        "{} crates only pass with ptrace, {} only pass with llvm, {} have different coverage",
+        comparison.regressed.len(),
+        comparison.fixed.len(),
+        comparison.coverage_changes.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_project;
+
+    fn state(status: Status, coverage: Option<f64>) -> ProjectState {
+        ProjectState {
+            status,
+            coverage,
+            files: None,
+        }
+    }
+
+    #[test]
+    fn compares_runs() {
+        let mut baseline = BTreeMap::new();
+        baseline.insert("a".to_string(), state(Status::Pass, Some(50.0)));
+        baseline.insert("b".to_string(), state(Status::Fail, None));
+        baseline.insert("c".to_string(), state(Status::Pass, Some(80.0)));
+        baseline.insert("d".to_string(), state(Status::Pass, Some(10.0)));
+        let mut current = BTreeMap::new();
+        current.insert("a".to_string(), state(Status::Stalled, None));
+        current.insert("b".to_string(), state(Status::Pass, Some(20.0)));
+        current.insert("c".to_string(), state(Status::Pass, Some(70.0)));
+        current.insert("e".to_string(), state(Status::Fail, None));
+
+        let comparison = Comparison::new(&baseline, &current, 1.0);
+        assert_eq!(
+            comparison.regressed,
+            vec![("a".to_string(), Status::Stalled)]
+        );
+        assert_eq!(comparison.fixed, vec![("b".to_string(), Status::Fail)]);
+        assert_eq!(
+            comparison.coverage_changes,
+            vec![("c".to_string(), 80.0, 70.0)]
+        );
+        assert_eq!(comparison.only_in_baseline, vec!["d".to_string()]);
+        assert_eq!(comparison.only_in_current, vec!["e".to_string()]);
+        assert_eq!(comparison.regressions(), 2);
+
+        let comparison = Comparison::new(&baseline, &current, 20.0);
+        assert!(comparison.coverage_changes.is_empty());
+        assert_eq!(comparison.regressions(), 1);
+    }
+
+    #[test]
+    fn results_read_from_both_layouts() {
+        let results = test_project("compare", &[]);
+        let write = |path: &str, status: &str| {
+            let path = results.join(path).join(RESULT_FILE);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, serde_json::json!({ "status": status }).to_string()).unwrap();
+        };
+        write("flat", "pass");
+        write("skipped", "skipped");
+        write("engines/ptrace", "pass");
+        write("engines/llvm", "fail");
+
+        let states = read_results(&results, None);
+        let _ = std::fs::remove_dir_all(&results);
+        assert_eq!(
+            states.keys().collect::<Vec<_>>(),
+            ["engines/llvm", "engines/ptrace", "flat"]
+        );
+        assert_eq!(states["engines/llvm"].status, Status::Fail);
+        assert_eq!(states["flat"].status, Status::Pass);
+    }
+
+    #[test]
+    fn diff_output() {
+        let comparison = Comparison {
+            regressed: vec![("a".to_string(), Status::Stalled)],
+            fixed: vec![("b".to_string(), Status::Fail)],
+            coverage_changes: vec![("c".to_string(), 80.0, 70.0)],
+            file_changes: vec![],
+            only_in_baseline: vec![],
+            only_in_current: vec!["e".to_string()],
+        };
+        let mut text = vec![];
+        comparison.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("  a: pass -> stalled\n"));
+        assert!(text.contains("  b: fail -> pass\n"));
+        assert!(text.contains("  c: 80.00% -> 70.00% (-10.00%)\n"));
+        assert!(text.ends_with("2 regressions\n"));
+
+        let json = serde_json::to_value(DiffReport::from(&comparison)).unwrap();
+        assert_eq!(json["newly_failing"][0]["after"], "stalled");
+        assert_eq!(json["newly_passing"][0]["name"], "b");
+        assert_eq!(json["only_in_after"][0], "e");
+        assert_eq!(json["regressions"], 2);
+    }
+
+    #[test]
+    fn report_files_diffed() {
+        let report = |files: &[&str]| {
+            let files = files
+                .iter()
+                .map(|x| {
+                    let path = ["/", "projects", "foo"]
+                        .iter()
+                        .copied()
+                        .chain(x.split('/'))
+                        .collect::<Vec<_>>();
+                    serde_json::json!({ "path": path, "covered": 1, "coverable": 2 })
+                })
+                .collect::<Vec<_>>();
+            serde_json::json!({ "files": files, "covered": 1, "coverable": 2 }).to_string()
+        };
+        let root = Path::new("/projects/foo");
+        let before = report(&["src/lib.rs", "src/parser.rs", "src/util.rs"]);
+        let after = report(&["src/lib.rs", "src/util.rs", "src/new.rs"]);
+        let with_files = |report: &str| ProjectState {
+            files: json_report_files(report, root),
+            ..state(Status::Pass, Some(50.0))
+        };
+        let mut baseline = BTreeMap::new();
+        baseline.insert("foo".to_string(), with_files(&before));
+        baseline.insert("bar".to_string(), with_files(&before));
+        let mut current = BTreeMap::new();
+        current.insert("foo".to_string(), with_files(&after));
+        current.insert("bar".to_string(), with_files(&before));
+
+        // The percentage is the same so only the files show the regression
+        let comparison = Comparison::new(&baseline, &current, 1.0);
+        assert!(comparison.coverage_changes.is_empty());
+        assert_eq!(
+            comparison.file_changes,
+            vec![FileChanges {
+                name: "foo".to_string(),
+                disappeared: vec!["src/parser.rs".to_string()],
+                appeared: vec!["src/new.rs".to_string()],
+            }]
+        );
+        assert_eq!(comparison.regressions(), 1);
+        let mut text = vec![];
+        comparison.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("  foo:\n    - src/parser.rs\n    + src/new.rs\n"));
+
+        // Files only appearing isn't a regression
+        baseline.insert("foo".to_string(), with_files(&report(&["src/lib.rs"])));
+        let comparison = Comparison::new(&baseline, &current, 1.0);
+        assert_eq!(comparison.file_changes[0].disappeared, Vec::<String>::new());
+        assert_eq!(comparison.regressions(), 0);
+    }
+}
@@ -0,0 +1,368 @@
+use crate::events::RunEvent;
+use crate::runner::Status;
+use std::time::{Duration, Instant};
+
+/// Where a crate is up to, driven by the events sent while running it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CrateState {
+    Cloning,
+    SettingUp,
+    RunningTarpaulin,
+    /// Tarpaulin exited and the results are being collected
+    Finishing,
+    Finished(Status),
+}
+
+impl CrateState {
+    pub fn label(&self) -> String {
+        match self {
+            Self::Cloning => "cloning".to_string(),
+            Self::SettingUp => "setting up".to_string(),
+            Self::RunningTarpaulin => "running tarpaulin".to_string(),
+            Self::Finishing => "finishing".to_string(),
+            Self::Finished(status) => format!("{:?}", status).to_lowercase(),
+        }
+    }
+}
+
+/// A crate in the dashboard table
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrateRow {
+    pub name: String,
+    pub state: CrateState,
+    pub started: Instant,
+    /// Set once the crate is finished so the time stops counting
+    pub finished: Option<Instant>,
+}
+
+impl CrateRow {
+    pub fn elapsed(&self, now: Instant) -> Duration {
+        self.finished
+            .unwrap_or(now)
+            .saturating_duration_since(self.started)
+    }
+}
+
+/// Everything the dashboard shows, built up from the run events
+#[derive(Debug, Clone, PartialEq)]
+pub struct DashboardState {
+    pub total: usize,
+    pub rows: Vec<CrateRow>,
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+impl DashboardState {
+    pub fn new(total: usize) -> Self {
+        Self {
+            total,
+            rows: vec![],
+            passed: 0,
+            failed: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Moves the crate the event is about to its next state. A crate that's started again, such
+    /// as when failures are retried, goes back to the bottom of the table
+    pub fn update(&mut self, event: &RunEvent, now: Instant) {
+        let (name, state) = match event {
+            RunEvent::CrateStarted { name, .. } => {
+                // The crate's earlier result is replaced so it's no longer counted
+                if let Some(pos) = self.rows.iter().position(|x| x.name == *name) {
+                    if let CrateState::Finished(status) = self.rows.remove(pos).state {
+                        *self.count(status) -= 1;
+                    }
+                }
+                self.rows.push(CrateRow {
+                    name: name.clone(),
+                    state: CrateState::Cloning,
+                    started: now,
+                    finished: None,
+                });
+                return;
+            }
+            RunEvent::Cloned { name } => (name, CrateState::SettingUp),
+            RunEvent::SetupDone { name } => (name, CrateState::RunningTarpaulin),
+            RunEvent::TarpaulinExited { name, .. } => (name, CrateState::Finishing),
+            RunEvent::Result { name, status, .. } => (name, CrateState::Finished(*status)),
+        };
+        let row = match self.rows.iter_mut().rev().find(|x| x.name == *name) {
+            Some(row) => row,
+            None => return,
+        };
+        row.state = state;
+        if let CrateState::Finished(status) = state {
+            row.finished = Some(now);
+            *self.count(status) += 1;
+        }
+    }
+
+    /// The counter a finished crate with the status is added to
+    fn count(&mut self, status: Status) -> &mut usize {
+        match status {
+            Status::Pass => &mut self.passed,
+            Status::Skipped => &mut self.skipped,
+            _ => &mut self.failed,
+        }
+    }
+
+    /// The line shown above the table
+    pub fn summary(&self) -> String {
+        format!(
+            "{}/{} started | {} passed | {} failed | {} skipped",
+            self.rows.len(),
+            self.total,
+            self.passed,
+            self.failed,
+            self.skipped
+        )
+    }
+}
+
+#[cfg(feature = "dashboard")]
+pub use self::terminal::Dashboard;
+
+#[cfg(feature = "dashboard")]
+mod terminal {
+    use super::*;
+    use crate::report::human_duration;
+    use crossterm::cursor::{Hide, Show};
+    use crossterm::execute;
+    use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+    use std::io::{self, Stdout};
+    use std::panic;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex, Once};
+    use std::thread::{self, JoinHandle};
+    use tui::backend::CrosstermBackend;
+    use tui::layout::{Constraint, Direction, Layout};
+    use tui::widgets::{Block, Borders, Paragraph, Row, Table};
+    use tui::Terminal;
+
+    /// How often the dashboard is redrawn so the elapsed times keep counting
+    const REDRAW_INTERVAL: Duration = Duration::from_millis(500);
+
+    /// Set while a dashboard has the terminal, so a panic knows to give it back
+    static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+    /// Leaves the alternate screen and shows the cursor again if a dashboard has the terminal
+    fn restore_terminal() {
+        if ACTIVE.swap(false, Ordering::SeqCst) {
+            let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        }
+    }
+
+    /// A live table of the crates in the run drawn on the terminal's alternate screen. Raw mode
+    /// isn't used so ctrl-c still pauses the run
+    pub struct Dashboard {
+        state: Arc<Mutex<DashboardState>>,
+        stop: Arc<AtomicBool>,
+        drawing: Option<JoinHandle<()>>,
+    }
+
+    fn draw(terminal: &mut Terminal<CrosstermBackend<Stdout>>, state: &DashboardState) {
+        let now = Instant::now();
+        let _ = terminal.draw(|frame| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(3), Constraint::Min(0)])
+                .split(frame.size());
+            let summary = Paragraph::new(state.summary())
+                .block(Block::default().borders(Borders::ALL).title("tater"));
+            frame.render_widget(summary, chunks[0]);
+            // Borders and the header take 3 lines, the newest crates are kept in view
+            let visible = (chunks[1].height as usize).saturating_sub(3);
+            let skip = state.rows.len().saturating_sub(visible);
+            let rows = state.rows.iter().skip(skip).map(|x| {
+                Row::new(vec![
+                    x.name.clone(),
+                    x.state.label(),
+                    human_duration(x.elapsed(now)),
+                ])
+            });
+            let widths = [
+                Constraint::Percentage(50),
+                Constraint::Percentage(30),
+                Constraint::Percentage(20),
+            ];
+            let table = Table::new(rows)
+                .header(Row::new(vec!["Crate", "State", "Elapsed"]))
+                .block(Block::default().borders(Borders::ALL))
+                .widths(&widths);
+            frame.render_widget(table, chunks[1]);
+        });
+    }
+
+    impl Dashboard {
+        /// Takes over the terminal until the dashboard finishes, anything else writing to the
+        /// terminal should be stopped until then
+        pub fn start(total: usize) -> io::Result<Self> {
+            static PANIC_HOOK: Once = Once::new();
+            PANIC_HOOK.call_once(|| {
+                let previous = panic::take_hook();
+                panic::set_hook(Box::new(move |info| {
+                    restore_terminal();
+                    previous(info);
+                }));
+            });
+            let mut stdout = io::stdout();
+            ACTIVE.store(true, Ordering::SeqCst);
+            let terminal = execute!(stdout, EnterAlternateScreen, Hide)
+                .and_then(|_| Terminal::new(CrosstermBackend::new(stdout)));
+            let mut terminal = match terminal {
+                Ok(terminal) => terminal,
+                Err(e) => {
+                    restore_terminal();
+                    return Err(e);
+                }
+            };
+            let state = Arc::new(Mutex::new(DashboardState::new(total)));
+            let stop = Arc::new(AtomicBool::new(false));
+            let drawing = {
+                let state = Arc::clone(&state);
+                let stop = Arc::clone(&stop);
+                thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        let state = state.lock().unwrap().clone();
+                        draw(&mut terminal, &state);
+                        thread::sleep(REDRAW_INTERVAL);
+                    }
+                })
+            };
+            Ok(Self {
+                state,
+                stop,
+                drawing: Some(drawing),
+            })
+        }
+
+        pub fn event(&self, event: &RunEvent) {
+            self.state.lock().unwrap().update(event, Instant::now());
+        }
+
+        /// Gives the terminal back, the same as dropping the dashboard
+        pub fn finish(self) {}
+    }
+
+    impl Drop for Dashboard {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(drawing) = self.drawing.take() {
+                let _ = drawing.join();
+            }
+            restore_terminal();
+        }
+    }
+}
+
+/// Stand in when tater is built without the dashboard, it can't be started
+#[cfg(not(feature = "dashboard"))]
+pub struct Dashboard(());
+
+#[cfg(not(feature = "dashboard"))]
+impl Dashboard {
+    pub fn start(_total: usize) -> std::io::Result<Self> {
+        Err(std::io::Error::other(
+            "tater was built without the `dashboard` feature",
+        ))
+    }
+
+    pub fn event(&self, _event: &RunEvent) {}
+
+    pub fn finish(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_update_rows() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let name = |x: &str| x.to_string();
+        let mut state = DashboardState::new(3);
+        state.update(
+            &RunEvent::CrateStarted {
+                index: 0,
+                name: name("foo"),
+            },
+            at(0),
+        );
+        assert_eq!(state.rows[0].state, CrateState::Cloning);
+        state.update(&RunEvent::Cloned { name: name("foo") }, at(5));
+        assert_eq!(state.rows[0].state, CrateState::SettingUp);
+        state.update(&RunEvent::SetupDone { name: name("foo") }, at(6));
+        assert_eq!(state.rows[0].state, CrateState::RunningTarpaulin);
+        state.update(
+            &RunEvent::TarpaulinExited {
+                name: name("foo"),
+                exit_code: Some(1),
+            },
+            at(60),
+        );
+        assert_eq!(state.rows[0].state, CrateState::Finishing);
+        state.update(
+            &RunEvent::Result {
+                name: name("foo"),
+                status: Status::Fail,
+                coverage_pct: None,
+            },
+            at(61),
+        );
+        assert_eq!(state.rows[0].state, CrateState::Finished(Status::Fail));
+        assert_eq!(state.rows[0].elapsed(at(300)), Duration::from_secs(61));
+        assert_eq!(state.failed, 1);
+
+        state.update(
+            &RunEvent::CrateStarted {
+                index: 1,
+                name: name("bar"),
+            },
+            at(62),
+        );
+        assert_eq!(state.rows[1].elapsed(at(70)), Duration::from_secs(8));
+        state.update(
+            &RunEvent::Result {
+                name: name("bar"),
+                status: Status::Pass,
+                coverage_pct: Some(80.0),
+            },
+            at(80),
+        );
+        // Events for crates that never started are ignored
+        state.update(&RunEvent::Cloned { name: name("baz") }, at(81));
+        assert_eq!(state.rows.len(), 2);
+        assert_eq!(state.passed, 1);
+        assert_eq!(
+            state.summary(),
+            "2/3 started | 1 passed | 1 failed | 0 skipped"
+        );
+
+        // A retried crate is only counted for its latest result
+        state.update(
+            &RunEvent::CrateStarted {
+                index: 0,
+                name: name("foo"),
+            },
+            at(90),
+        );
+        assert_eq!(state.rows.len(), 2);
+        assert_eq!(state.rows[1].state, CrateState::Cloning);
+        assert_eq!(state.failed, 0);
+        state.update(
+            &RunEvent::Result {
+                name: name("foo"),
+                status: Status::Pass,
+                coverage_pct: Some(50.0),
+            },
+            at(100),
+        );
+        assert_eq!(
+            state.summary(),
+            "2/3 started | 2 passed | 0 failed | 0 skipped"
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use crate::cache::format_gb;
+use std::fs::{read_dir, remove_dir_all};
+use std::path::{Path, PathBuf};
+use sysinfo::{DiskExt, RefreshKind, System, SystemExt};
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// There isn't enough free disk to carry on running projects. Clones and builds fail in confusing
+/// ways once the disk fills, so the run stops before that happens
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("only {} free on the disk holding {}, below the {} minimum", format_gb(*.available), .path.display(), format_gb(*.min_free))]
+pub struct DiskFull {
+    pub path: PathBuf,
+    pub available: u64,
+    pub min_free: u64,
+}
+
+/// Free space on the disk holding the path, `None` if the disk can't be found
+pub fn available_space(path: &Path) -> Option<u64> {
+    let path = path.canonicalize().ok()?;
+    let system = System::new_with_specifics(RefreshKind::new().with_disks_list());
+    // Mounts can be nested so the disk is the one with the longest matching mount point
+    system
+        .disks()
+        .iter()
+        .filter(|x| path.starts_with(x.mount_point()))
+        .max_by_key(|x| x.mount_point().as_os_str().len())
+        .map(|x| x.available_space())
+}
+
+/// Checks there's at least `min_free` bytes available for the path, it's assumed there is if the
+/// available space can't be worked out
+pub fn check_space(
+    path: &Path,
+    min_free: u64,
+    available: impl Fn(&Path) -> Option<u64>,
+) -> Result<(), DiskFull> {
+    match available(path) {
+        Some(available) if available < min_free => Err(DiskFull {
+            path: path.to_path_buf(),
+            available,
+            min_free,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Deletes the target directory of every project, including ones kept with `keep_target`, to
+/// free up space
+pub fn remove_targets(projects: &Path) {
+    for project in read_dir(projects).into_iter().flatten().flatten() {
+        let target = project.path().join("target");
+        if target.is_dir() {
+            match remove_dir_all(&target) {
+                Ok(()) => info!("Removed {} to free disk space", target.display()),
+                Err(e) => warn!("Failed to remove {}: {}", target.display(), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::GIGABYTE;
+
+    #[test]
+    fn threshold() {
+        let path = Path::new("output/projects");
+        assert_eq!(check_space(path, GIGABYTE, |_| Some(2 * GIGABYTE)), Ok(()));
+        assert_eq!(check_space(path, GIGABYTE, |_| Some(GIGABYTE)), Ok(()));
+        assert_eq!(check_space(path, GIGABYTE, |_| None), Ok(()));
+        let err = check_space(path, 2 * GIGABYTE, |_| Some(GIGABYTE / 2)).unwrap_err();
+        assert_eq!(err.available, GIGABYTE / 2);
+        assert_eq!(
+            err.to_string(),
+            "only 0.50GB free on the disk holding output/projects, below the 2.00GB minimum"
+        );
+        // Whatever disk the temp dir is on should be found
+        assert!(available_space(&std::env::temp_dir()).is_some());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::runner::new_command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::{info, warn};
+
+/// Image used if neither the context or the crate specify one
+pub const DEFAULT_IMAGE: &str = "xd009642/tarpaulin";
+/// Volume the cargo registry is kept in so dependencies aren't downloaded for every project
+const CARGO_CACHE_VOLUME: &str = "tater-cargo-registry";
+/// Where the project is mounted inside the container, cross mounts projects here too
+pub const PROJECT_MOUNT: &str = "/project";
+
+pub fn container_name(proj_name: &str) -> String {
+    format!("tater-{}", proj_name)
+}
+
+/// Takes a command that would be ran on the host in the project directory and turns it into a
+/// `docker run` invocation running the same command in a container with the project mounted in.
+/// With `no_network` the container has no network access
+pub fn wrap_command(
+    cmd: &Command,
+    root: &Path,
+    image: &str,
+    proj_name: &str,
+    no_network: bool,
+) -> Command {
+    warn!("Running container with `--security-opt seccomp=unconfined` so tarpaulin can ptrace");
+    let workdir = cmd
+        .get_current_dir()
+        .and_then(|dir| dir.strip_prefix(root).ok())
+        .map(|dir| Path::new(PROJECT_MOUNT).join(dir))
+        .unwrap_or_else(|| PathBuf::from(PROJECT_MOUNT));
+    let host_root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+
+    let mut docker = new_command("docker");
+    docker
+        .args(["run", "--rm", "--name", &container_name(proj_name)])
+        .args(["--security-opt", "seccomp=unconfined"])
+        .arg("-v")
+        .arg(format!("{}:{}", host_root.display(), PROJECT_MOUNT))
+        .arg("-v")
+        .arg(format!("{}:/usr/local/cargo/registry", CARGO_CACHE_VOLUME))
+        .arg("-w")
+        .arg(workdir);
+    if no_network {
+        docker.args(["--network", "none"]);
+    }
+    for (key, value) in cmd.get_envs() {
+        if let Some(value) = value {
+            docker.arg("-e").arg(format!(
+                "{}={}",
+                key.to_string_lossy(),
+                value.to_string_lossy()
+            ));
+        }
+    }
+    docker
+        .arg(image)
+        .arg(cmd.get_program())
+        .args(cmd.get_args())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    docker
+}
+
+/// Gets the CPU usage of the container running the project as a percentage
+pub fn cpu_usage(proj_name: &str) -> Option<f32> {
+    let output = new_command("docker")
+        .args(["stats", "--no-stream", "--format", "{{.CPUPerc}}"])
+        .arg(container_name(proj_name))
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .trim_end_matches('%')
+        .parse()
+        .ok()
+}
+
+pub fn kill_container(proj_name: &str) {
+    let name = container_name(proj_name);
+    match new_command("docker").args(["kill", &name]).output() {
+        Ok(out) if out.status.success() => info!("Killed container {}", name),
+        _ => warn!("Failed to kill container {}", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_command() {
+        let root = Path::new("projects/foo");
+        let mut cmd = Command::new("cargo");
+        cmd.args(["tarpaulin", "--debug"])
+            .env("RUST_LOG", "cargo_tarpaulin=info")
+            .current_dir(root.join("sub"));
+
+        let docker = wrap_command(&cmd, root, DEFAULT_IMAGE, "foo", false);
+        let args = docker
+            .get_args()
+            .map(|x| x.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(docker.get_program(), "docker");
+        assert!(args.windows(2).any(|x| x == ["--name", "tater-foo"]));
+        assert!(args
+            .windows(2)
+            .any(|x| x == ["--security-opt", "seccomp=unconfined"]));
+        assert!(args.windows(2).any(|x| x == ["-w", "/project/sub"]));
+        assert!(args
+            .windows(2)
+            .any(|x| x == ["-e", "RUST_LOG=cargo_tarpaulin=info"]));
+        assert!(args.ends_with(&[
+            DEFAULT_IMAGE.to_string(),
+            "cargo".to_string(),
+            "tarpaulin".to_string(),
+            "--debug".to_string()
+        ]));
+        assert!(!args.contains(&"--network".to_string()));
+
+        let docker = wrap_command(&cmd, root, DEFAULT_IMAGE, "foo", true);
+        assert!(docker
+            .get_args()
+            .collect::<Vec<_>>()
+            .windows(2)
+            .any(|x| x == ["--network", "none"]));
+    }
+}
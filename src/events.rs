@@ -0,0 +1,185 @@
+use crate::runner::Status;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// State transitions written to the events file
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Started,
+    Passed,
+    Failed,
+    Stalled,
+    /// Tarpaulin ran but the project has no tests
+    Skipped,
+    /// Ctrl-C was received and the run is pausing
+    Interrupted,
+}
+
+/// What's happening to a project, sent to the event sink given to
+/// [`run_all`](crate::runner::run_all) as it happens so a wrapper can show live progress
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunEvent {
+    CrateStarted {
+        index: usize,
+        name: String,
+    },
+    /// The project was cloned, or was already cloned
+    Cloned {
+        name: String,
+    },
+    /// The project's setup script ran, or it doesn't have one
+    SetupDone {
+        name: String,
+    },
+    /// `exit_code` is `None` if tarpaulin was killed
+    TarpaulinExited {
+        name: String,
+        exit_code: Option<i32>,
+    },
+    Result {
+        name: String,
+        status: Status,
+        coverage_pct: Option<f64>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event<'a> {
+    /// Unix time in milliseconds
+    pub ts: u128,
+    pub event: EventKind,
+    pub name: &'a str,
+    pub coverage_pct: Option<f64>,
+}
+
+impl RunEvent {
+    /// The same event for the project reported under another name
+    pub(crate) fn renamed(mut self, new_name: &str) -> Self {
+        match &mut self {
+            Self::CrateStarted { name, .. }
+            | Self::Cloned { name }
+            | Self::SetupDone { name }
+            | Self::TarpaulinExited { name, .. }
+            | Self::Result { name, .. } => *name = new_name.to_string(),
+        }
+        self
+    }
+}
+
+/// Appends the starts and results of projects as newline delimited JSON so other tools can follow
+/// a run, does nothing if no events file was given
+#[derive(Default)]
+pub struct EventWriter(Mutex<Option<BufWriter<File>>>);
+
+impl EventWriter {
+    pub fn new(path: Option<&Path>) -> io::Result<Self> {
+        match path {
+            Some(path) => {
+                let file = OpenOptions::new().append(true).create(true).open(path)?;
+                Ok(Self(Mutex::new(Some(BufWriter::new(file)))))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Writes the event if it's one the events file has
+    pub fn event(&self, event: &RunEvent) {
+        match event {
+            RunEvent::CrateStarted { name, .. } => self.emit(EventKind::Started, name, None),
+            RunEvent::Result {
+                name,
+                status,
+                coverage_pct,
+            } => {
+                let kind = match status {
+                    Status::Pass => EventKind::Passed,
+                    Status::Stalled => EventKind::Stalled,
+                    Status::Skipped => EventKind::Skipped,
+                    _ => EventKind::Failed,
+                };
+                self.emit(kind, name, *coverage_pct);
+            }
+            _ => {}
+        }
+    }
+
+    /// Records the run being paused at the project
+    pub fn interrupted(&self, name: &str) {
+        self.emit(EventKind::Interrupted, name, None);
+    }
+
+    fn emit(&self, event: EventKind, name: &str, coverage_pct: Option<f64>) {
+        if let Some(writer) = self.0.lock().unwrap().as_mut() {
+            let ts = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_millis())
+                .unwrap_or_default();
+            let event = Event {
+                ts,
+                event,
+                name,
+                coverage_pct,
+            };
+            let res = serde_json::to_writer(&mut *writer, &event)
+                .map_err(io::Error::from)
+                .and_then(|_| writer.write_all(b"\n"))
+                .and_then(|_| writer.flush());
+            if let Err(e) = res {
+                warn!("Failed to write event: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_project;
+
+    #[test]
+    fn event_format() {
+        let event = Event {
+            ts: 1000,
+            event: EventKind::Passed,
+            name: "tater",
+            coverage_pct: Some(42.5),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"ts":1000,"event":"passed","name":"tater","coverage_pct":42.5}"#
+        );
+    }
+
+    #[test]
+    fn run_events_written() {
+        let dir = test_project("events", &[]);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.jsonl");
+        let writer = EventWriter::new(Some(&path)).unwrap();
+        let name = || "tater".to_string();
+        writer.event(&RunEvent::CrateStarted {
+            index: 0,
+            name: name(),
+        });
+        writer.event(&RunEvent::Cloned { name: name() });
+        writer.event(&RunEvent::Result {
+            name: name(),
+            status: Status::Stalled,
+            coverage_pct: None,
+        });
+        writer.interrupted("tater");
+        let written = std::fs::read_to_string(&path).unwrap();
+        let events = written
+            .lines()
+            .map(|x| serde_json::from_str::<serde_json::Value>(x).unwrap()["event"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(events, ["started", "stalled", "interrupted"]);
+    }
+}
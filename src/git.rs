@@ -0,0 +1,371 @@
+use crate::runner::new_command;
+use git2::build::RepoBuilder;
+use git2::{
+    ErrorClass, ErrorCode, FetchOptions, RemoteCallbacks, Repository, ResetType,
+    SubmoduleUpdateOptions,
+};
+use std::fs::remove_dir_all;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// How often the progress of a clone or fetch is logged
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+/// Only the latest commit is tested so the history isn't needed
+const DEPTH: i32 = 1;
+
+/// Why cloning or updating a project failed
+#[derive(Debug, Error)]
+pub enum GitError {
+    /// libgit2 failed, the class says where such as `Net` or `Ssl` for network problems
+    #[error("{message} ({class:?} error)")]
+    Libgit2 { class: ErrorClass, message: String },
+    /// The git binary failed, only used with `use_system_git`
+    #[error("{0}")]
+    System(String),
+    #[error("Failed to remove {}: {1}", .0.display())]
+    Remove(PathBuf, io::Error),
+    /// The project directory isn't a clone tater made, it's left alone rather than deleted
+    #[error("{} isn't a clone that can be reused ({1}), remove it to clone again", .0.display())]
+    Unusable(PathBuf, String),
+}
+
+impl From<git2::Error> for GitError {
+    fn from(e: git2::Error) -> Self {
+        Self::Libgit2 {
+            class: e.class(),
+            message: e.message().to_string(),
+        }
+    }
+}
+
+/// What has to be done to get a usable clone of a project
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CloneAction {
+    /// Nothing's been cloned yet
+    Clone,
+    /// There's a working clone from a previous run, it only needs bringing up to date
+    Update,
+    /// There's an interrupted clone or an empty directory, so it's deleted and cloned again
+    Reclone,
+}
+
+/// Decides whether the project directory can be reused. A clone is reused if it has a commit
+/// checked out, interrupted clones have a `.git` directory with no HEAD or an unborn branch. If
+/// it's anything else it could be someone's work so it's an error rather than being deleted
+pub fn clone_action(proj_dir: &Path) -> Result<CloneAction, GitError> {
+    let unusable = |reason: String| GitError::Unusable(proj_dir.to_path_buf(), reason);
+    let git_dir = proj_dir.join(".git");
+    if !proj_dir.exists() {
+        return Ok(CloneAction::Clone);
+    } else if !git_dir.is_dir() {
+        let empty = proj_dir
+            .read_dir()
+            .map(|mut x| x.next().is_none())
+            .unwrap_or(false);
+        return if empty {
+            Ok(CloneAction::Reclone)
+        } else {
+            Err(unusable("it has no .git directory".to_string()))
+        };
+    } else if !git_dir.join("HEAD").is_file() {
+        return Ok(CloneAction::Reclone);
+    }
+    let repo = Repository::open(proj_dir).map_err(|e| unusable(e.message().to_string()))?;
+    let head = repo.head().and_then(|x| x.peel_to_commit()).map(|x| x.id());
+    match head {
+        Ok(_) => Ok(CloneAction::Update),
+        Err(e) if matches!(e.code(), ErrorCode::UnbornBranch | ErrorCode::NotFound) => {
+            Ok(CloneAction::Reclone)
+        }
+        Err(e) => Err(unusable(e.message().to_string())),
+    }
+}
+
+/// Gets the commit the project is currently at
+pub fn head_commit(proj_dir: &Path) -> Option<String> {
+    let repo = Repository::open(proj_dir).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(commit.id().to_string())
+}
+
+/// Shallow fetch options logging the progress every few seconds
+fn fetch_options() -> FetchOptions<'static> {
+    let mut last_logged = Instant::now();
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.transfer_progress(move |progress| {
+        if last_logged.elapsed() >= PROGRESS_INTERVAL {
+            info!(
+                "Received {}/{} objects ({}KiB)",
+                progress.received_objects(),
+                progress.total_objects(),
+                progress.received_bytes() / 1024
+            );
+            last_logged = Instant::now();
+        }
+        true
+    });
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks).depth(DEPTH);
+    options
+}
+
+/// Initialises and updates the submodules, and any submodules they have
+fn update_submodules(repo: &Repository) -> Result<(), git2::Error> {
+    for mut submodule in repo.submodules()? {
+        let mut options = SubmoduleUpdateOptions::new();
+        options.fetch(fetch_options());
+        submodule.update(true, Some(&mut options))?;
+        update_submodules(&submodule.open()?)?;
+    }
+    Ok(())
+}
+
+/// Runs git with the args in `dir`, the error has git's stderr
+fn run_git(dir: &Path, args: &[&str]) -> Result<(), GitError> {
+    let git = new_command("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| GitError::System(format!("Git may not be installed: {}", e)))?;
+    if git.status.success() {
+        Ok(())
+    } else {
+        Err(GitError::System(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&git.stderr).trim()
+        )))
+    }
+}
+
+/// Clones the project into `projects/proj_name` along with its submodules. With `system_git` the
+/// git binary is used instead of libgit2
+pub fn clone_project(
+    projects: impl AsRef<Path>,
+    repository_url: &str,
+    proj_name: &str,
+    system_git: bool,
+) -> Result<(), GitError> {
+    let projects = projects.as_ref();
+    if system_git {
+        run_git(
+            projects,
+            &[
+                "clone",
+                "--recurse-submodules",
+                "--depth",
+                "1",
+                repository_url,
+                proj_name,
+            ],
+        )?;
+    } else {
+        let repo = RepoBuilder::new()
+            .fetch_options(fetch_options())
+            .clone(repository_url, &projects.join(proj_name))?;
+        update_submodules(&repo)?;
+    }
+    info!("{} cloned successfully", proj_name);
+    Ok(())
+}
+
+/// Brings an existing clone up to date with the remote's default branch, throwing away anything
+/// left behind by a previous run. Untracked files such as the target directory are kept
+pub fn update_project(proj_dir: &Path, system_git: bool) -> Result<(), GitError> {
+    if system_git {
+        let steps: [&[&str]; 3] = [
+            &["fetch", "--depth", "1", "origin"],
+            &["reset", "--hard", "origin/HEAD"],
+            &[
+                "submodule",
+                "update",
+                "--init",
+                "--recursive",
+                "--depth",
+                "1",
+            ],
+        ];
+        return steps.iter().try_for_each(|args| run_git(proj_dir, args));
+    }
+    let repo = Repository::open(proj_dir)?;
+    let mut remote = repo.find_remote("origin")?;
+    // The refspecs the clone set up
+    remote.fetch(&[] as &[&str], Some(&mut fetch_options()), None)?;
+    let head = repo
+        .find_reference("refs/remotes/origin/HEAD")?
+        .resolve()?
+        .peel_to_commit()?;
+    repo.reset(head.as_object(), ResetType::Hard, None)?;
+    update_submodules(&repo)?;
+    Ok(())
+}
+
+/// Makes sure the project is cloned and up to date, returning what was done. Failing to update a
+/// working clone isn't an error, the existing version is used and the commit it's at is recorded
+pub fn prepare_project(
+    projects: &Path,
+    repository_url: &str,
+    proj_name: &str,
+    system_git: bool,
+) -> Result<CloneAction, GitError> {
+    let proj_dir = projects.join(proj_name);
+    let action = clone_action(&proj_dir)?;
+    match action {
+        CloneAction::Update => {
+            info!("Project already cloned, updating it");
+            if let Err(e) = update_project(&proj_dir, system_git) {
+                warn!("Failed to update, using existing version: {}", e);
+            }
+            return Ok(action);
+        }
+        CloneAction::Reclone => {
+            warn!(
+                "{} is an interrupted clone, cloning again",
+                proj_dir.display()
+            );
+            remove_dir_all(&proj_dir).map_err(|e| GitError::Remove(proj_dir.clone(), e))?;
+        }
+        CloneAction::Clone => {}
+    }
+    clone_project(projects, repository_url, proj_name, system_git)?;
+    Ok(action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_project, TestProject};
+    use std::fs::create_dir_all;
+    use std::process::Command;
+
+    /// A repo with one commit to clone from, the helper runs git in it
+    fn upstream(name: &str) -> (TestProject, impl Fn(&Path, &[&str])) {
+        let root = test_project(name, &[]);
+        let upstream = root.join("upstream");
+        create_dir_all(&upstream).unwrap();
+        let git = |dir: &Path, args: &[&str]| {
+            let status = Command::new("git")
+                .args([
+                    "-c",
+                    "user.name=tater",
+                    "-c",
+                    "user.email=tater@example.com",
+                ])
+                .args(args)
+                .current_dir(dir)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?}", args);
+        };
+        git(&upstream, &["init", "-q"]);
+        git(&upstream, &["commit", "-q", "--allow-empty", "-m", "first"]);
+        (root, git)
+    }
+
+    fn clones_reused(system_git: bool) {
+        let name = format!("clone-reuse-{}", system_git);
+        let (root, git) = upstream(&name);
+        let upstream = root.join("upstream");
+        let projects = root.join("projects");
+        create_dir_all(&projects).unwrap();
+        let url = format!("file://{}", upstream.display());
+        let proj_dir = projects.join("foo");
+
+        assert_eq!(clone_action(&proj_dir).unwrap(), CloneAction::Clone);
+        assert_eq!(
+            prepare_project(&projects, &url, "foo", system_git).unwrap(),
+            CloneAction::Clone
+        );
+        assert_eq!(head_commit(&proj_dir), head_commit(&upstream));
+
+        // A repeated run tests the latest code
+        git(
+            &upstream,
+            &["commit", "-q", "--allow-empty", "-m", "second"],
+        );
+        assert_ne!(head_commit(&proj_dir), head_commit(&upstream));
+        assert_eq!(clone_action(&proj_dir).unwrap(), CloneAction::Update);
+        assert_eq!(
+            prepare_project(&projects, &url, "foo", system_git).unwrap(),
+            CloneAction::Update
+        );
+        assert_eq!(head_commit(&proj_dir), head_commit(&upstream));
+
+        // An interrupted clone has no HEAD to check out
+        let partial = projects.join("partial");
+        git(&projects, &["init", "-q", "partial"]);
+        assert_eq!(clone_action(&partial).unwrap(), CloneAction::Reclone);
+        assert_eq!(
+            prepare_project(&projects, &url, "partial", system_git).unwrap(),
+            CloneAction::Reclone
+        );
+        assert_eq!(head_commit(&partial), head_commit(&upstream));
+
+        create_dir_all(projects.join("empty")).unwrap();
+        assert_eq!(
+            clone_action(&projects.join("empty")).unwrap(),
+            CloneAction::Reclone
+        );
+
+        // Someone's checkout is never deleted
+        let checkout = projects.join("not-a-repo");
+        create_dir_all(checkout.join("src")).unwrap();
+        std::fs::write(checkout.join("src/lib.rs"), "").unwrap();
+        match prepare_project(&projects, &url, "not-a-repo", system_git) {
+            Err(GitError::Unusable(..)) => {}
+            res => panic!("expected the checkout to be unusable, got {:?}", res),
+        }
+        assert!(checkout.join("src/lib.rs").is_file());
+    }
+
+    #[test]
+    fn clones_reused_libgit2() {
+        clones_reused(false);
+    }
+
+    #[test]
+    fn clones_reused_system_git() {
+        clones_reused(true);
+    }
+
+    #[test]
+    fn submodules_cloned() {
+        let (root, git) = upstream("clone-submodules");
+        let upstream = root.join("upstream");
+        let library = root.join("library");
+        create_dir_all(&library).unwrap();
+        git(&library, &["init", "-q"]);
+        std::fs::write(library.join("lib.rs"), "").unwrap();
+        git(&library, &["add", "lib.rs"]);
+        git(&library, &["commit", "-q", "-m", "library"]);
+        git(
+            &upstream,
+            &[
+                "-c",
+                "protocol.file.allow=always",
+                "submodule",
+                "add",
+                "-q",
+                &format!("file://{}", library.display()),
+                "vendor/library",
+            ],
+        );
+        git(&upstream, &["commit", "-q", "-m", "add library"]);
+
+        let projects = root.join("projects");
+        create_dir_all(&projects).unwrap();
+        let url = format!("file://{}", upstream.display());
+        clone_project(&projects, &url, "foo", false).unwrap();
+        assert!(projects.join("foo/vendor/library/lib.rs").is_file());
+
+        let missing = format!("file://{}", root.join("missing").display());
+        match clone_project(&projects, &missing, "missing", false) {
+            Err(GitError::Libgit2 { .. }) => {}
+            res => panic!("expected a libgit2 error, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tater::report::{human_duration, rfc3339};
+use tracing::{info, warn};
+
+/// Name of the file in the output directory the heartbeat is written to for dashboards
+pub const STATUS_FILE: &str = "status.json";
+/// Log a heartbeat after this many crates
+const HEARTBEAT_CRATES: usize = 10;
+/// Log a heartbeat if a crate is taking longer than this since the last one
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Number of recent crates the average duration is taken from
+const AVERAGE_WINDOW: usize = 10;
+/// How often a crate that's still running is checked on for a heartbeat
+const TICK_INTERVAL: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    /// `None` until a run starts so ticks from anything else are ignored
+    static ref HEARTBEAT: Mutex<Option<Heartbeat>> = Mutex::new(None);
+}
+
+/// How far through the run we are, written to `status.json`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunStatus {
+    pub done: usize,
+    pub total: usize,
+    pub failed: usize,
+    /// Average time a crate takes over the recent crates
+    pub average_secs: Option<f64>,
+    /// When the run is expected to finish
+    pub eta: Option<String>,
+    pub updated: String,
+}
+
+impl RunStatus {
+    fn log(&self) {
+        let average = self
+            .average_secs
+            .map(|x| format!(", avg {}/crate", human_duration(Duration::from_secs_f64(x))))
+            .unwrap_or_default();
+        let eta = self
+            .eta
+            .as_deref()
+            .map(|x| format!(", ETA {} UTC", x[..16].replace('T', " ")))
+            .unwrap_or_default();
+        info!(
+            "{}/{} done, {} failed{}{}",
+            self.done, self.total, self.failed, average, eta
+        );
+    }
+}
+
+struct Heartbeat {
+    status_file: PathBuf,
+    total: usize,
+    done: usize,
+    failed: usize,
+    durations: Vec<Duration>,
+    last: Instant,
+}
+
+impl Heartbeat {
+    /// Average time a crate takes over the recent crates
+    fn average(&self) -> Option<Duration> {
+        let recent = &self.durations[self.durations.len().saturating_sub(AVERAGE_WINDOW)..];
+        if recent.is_empty() {
+            None
+        } else {
+            Some(recent.iter().sum::<Duration>() / recent.len() as u32)
+        }
+    }
+
+    /// How long the crates left are expected to take
+    fn remaining(&self) -> Option<Duration> {
+        let left = self.total.saturating_sub(self.done) as u32;
+        self.average().map(|x| x * left)
+    }
+
+    fn status(&self, now: SystemTime) -> RunStatus {
+        RunStatus {
+            done: self.done,
+            total: self.total,
+            failed: self.failed,
+            average_secs: self.average().map(|x| x.as_secs_f64()),
+            eta: self.remaining().map(|x| rfc3339(now + x)),
+            updated: rfc3339(now),
+        }
+    }
+
+    fn beat(&mut self) {
+        let status = self.status(SystemTime::now());
+        status.log();
+        let write = || -> std::io::Result<()> {
+            let writer = BufWriter::new(File::create(&self.status_file)?);
+            serde_json::to_writer_pretty(writer, &status)?;
+            Ok(())
+        };
+        if let Err(e) = write() {
+            warn!("Failed to write {}: {}", self.status_file.display(), e);
+        }
+        self.last = Instant::now();
+    }
+}
+
+/// Starts tracking a run of `total` crates in the output directory, `done` are already done from
+/// before the run was resumed. Long running crates get a heartbeat from a background thread
+pub fn start(output: &Path, total: usize, done: usize) {
+    static TICKING: Once = Once::new();
+    TICKING.call_once(|| {
+        thread::spawn(|| loop {
+            thread::sleep(TICK_INTERVAL);
+            tick();
+        });
+    });
+    let mut heartbeat = Heartbeat {
+        status_file: output.join(STATUS_FILE),
+        total,
+        done,
+        failed: 0,
+        durations: vec![],
+        last: Instant::now(),
+    };
+    heartbeat.beat();
+    *HEARTBEAT.lock().unwrap() = Some(heartbeat);
+}
+
+/// Records a crate finishing, `done` is how many crates are done including this one. Returns how
+/// long the rest of the run is expected to take, the same estimate the heartbeat's ETA is from
+pub fn finished_crate(done: usize, duration: Duration, failed: bool) -> Option<Duration> {
+    let mut heartbeat = HEARTBEAT.lock().unwrap();
+    let heartbeat = heartbeat.as_mut()?;
+    heartbeat.done = done;
+    heartbeat.durations.push(duration);
+    if failed {
+        heartbeat.failed += 1;
+    }
+    if heartbeat.durations.len() % HEARTBEAT_CRATES == 0 || done == heartbeat.total {
+        heartbeat.beat();
+    }
+    heartbeat.remaining()
+}
+
+/// Gives a heartbeat if there hasn't been one for a while, so long running crates still get one
+fn tick() {
+    if let Some(heartbeat) = HEARTBEAT.lock().unwrap().as_mut() {
+        if heartbeat.last.elapsed() >= HEARTBEAT_INTERVAL {
+            heartbeat.beat();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eta_estimated() {
+        let mut heartbeat = Heartbeat {
+            status_file: PathBuf::new(),
+            total: 10,
+            done: 4,
+            failed: 1,
+            durations: vec![Duration::from_secs(60), Duration::from_secs(180)],
+            last: Instant::now(),
+        };
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let status = heartbeat.status(now);
+        assert_eq!(status.average_secs, Some(120.0));
+        // 6 crates left at 2 minutes each
+        assert_eq!(status.eta.as_deref(), Some("2020-09-13T12:38:40Z"));
+        assert_eq!(status.updated, "2020-09-13T12:26:40Z");
+        assert_eq!(heartbeat.remaining(), Some(Duration::from_secs(720)));
+
+        heartbeat.durations.clear();
+        assert_eq!(heartbeat.status(now).eta, None);
+    }
+}
@@ -0,0 +1,28 @@
+//! Tater runs cargo tarpaulin over a list of projects to find regressions in tarpaulin. The
+//! `tater` binary is a thin wrapper over this library, other tools can use it to run projects
+//! and get the results back without going through the files the binary writes.
+pub mod bisect;
+pub mod cache;
+pub mod ci;
+pub mod clean;
+pub mod compare;
+pub mod dashboard;
+pub mod disk;
+pub mod docker;
+pub mod events;
+pub mod git;
+pub mod metadata;
+pub mod prefetch;
+pub mod report;
+pub mod runner;
+pub mod sccache;
+pub mod status;
+#[cfg(test)]
+mod testing;
+pub mod webhook;
+
+pub use crate::events::RunEvent;
+pub use crate::runner::{
+    load_context, run_all, run_all_with, run_test, run_test_with_events, Context, CrateRun,
+    CrateSpec, PlannedRun, RunError, RunHooks, RunOutcome, ShutdownSignal, Status,
+};
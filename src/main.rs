@@ -1,20 +1,34 @@
-use crate::runner::*;
+mod heartbeat;
+mod progress;
+#[cfg(test)]
+#[path = "testing.rs"]
+mod testing;
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
 use std::env;
-use std::fs::{create_dir, create_dir_all, File, OpenOptions};
-use std::io::prelude::*;
-use std::io::{self, BufReader, BufWriter};
+use std::fs::{create_dir, create_dir_all, read_to_string, remove_file, File};
+use std::io::{self, BufReader, IsTerminal};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::mpsc;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
+use tater::dashboard::Dashboard;
+use tater::events::{EventWriter, RunEvent};
+use tater::prefetch::Prefetcher;
+use tater::report::*;
+use tater::runner::*;
+use tater::{bisect, cache, ci, clean, compare, disk, report, status, webhook};
 use tracing::{error, info, warn};
 use tracing_subscriber::{EnvFilter, Layer, Registry};
 
-mod ci;
-mod runner;
-
-#[derive(Debug, Default, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, StructOpt)]
+#[derive(Debug, Default, Clone, PartialEq, StructOpt)]
 struct Args {
-    /// Location to the repos file
+    /// Location to the repos file, this can be a http(s) URL if built with the `remote` feature
     #[structopt(
         name = "input repos",
         short = "i",
@@ -31,19 +45,574 @@ struct Args {
     )]
     output: PathBuf,
     /// Limit the number of jobs, this will limit cargo build jobs and also the number of test
-    /// threads
+    /// threads. Defaults to the number of available CPUs, 0 leaves it to cargo and tarpaulin
     #[structopt(name = "jobs", short = "j", long = "jobs")]
     jobs: Option<usize>,
+    /// Fetch dependencies before running tarpaulin and run tarpaulin offline. Overrides the
+    /// `fetch` setting in the repos file
+    #[structopt(long = "fetch")]
+    fetch: bool,
+    /// Run tarpaulin without network access after fetching the dependencies, tests that fail
+    /// trying to use the network are reported as network dependent. Overrides the `no_network`
+    /// setting in the repos file
+    #[structopt(long = "no-network")]
+    no_network: bool,
+    /// Where to run tarpaulin, either `native` or `docker`. Overrides the `engine` setting in the
+    /// repos file
+    #[structopt(long = "engine")]
+    engine: Option<Engine>,
+    /// Skip any projects which passed in a previous run in this output directory
+    #[structopt(long = "incremental")]
+    incremental: bool,
+    /// Order to run the crates in, `repos` for the order in the repos file or `duration` for the
+    /// crates that took longest in the last run first. Crates without a previous result are ran
+    /// last in the repos file order. A paused run resumes in the order it was started with
+    #[structopt(long = "order-by", default_value = "repos")]
+    order_by: CrateOrder,
+    /// Projects to run even if they passed previously when running incrementally
+    #[structopt(long = "force")]
+    force: Vec<String>,
+    /// Clone up to this many projects in parallel in the background ahead of testing them
+    #[structopt(long = "prefetch")]
+    prefetch: Option<usize>,
+    /// Only run projects with this label. Can be given multiple times in which case projects
+    /// with any of the labels are ran, unless `--all-labels` is also passed
+    #[structopt(long = "label")]
+    labels: Vec<String>,
+    /// Only run projects that have every label passed via `--label`
+    #[structopt(long = "all-labels")]
+    all_labels: bool,
+    /// Clone the projects and print the tarpaulin command detected for each one without running
+    /// setup, teardown or tarpaulin
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+    /// With `--dry-run` don't clone projects, projects that aren't already cloned use the default
+    /// command
+    #[structopt(long = "no-clone")]
+    no_clone: bool,
+    /// Output directory of a previous run to compare the results against, differences are
+    /// written to `regressions.md` and regressions cause a non-zero exit code
+    #[structopt(long = "baseline")]
+    baseline: Option<PathBuf>,
+    /// Minimum change in coverage percentage to report when comparing against a baseline
+    #[structopt(long = "coverage-threshold", default_value = "1.0")]
+    coverage_threshold: f64,
+    /// When tarpaulin fails on a project run `cargo test` with the same arguments to check if the
+    /// project fails without tarpaulin. Overrides the `baseline_test` setting in the repos file
+    #[structopt(long = "baseline-test")]
+    baseline_test: bool,
+    /// Keep the projects' target directories instead of deleting them after each project.
+    /// Overrides the `keep_target` setting in the repos file
+    #[structopt(long = "keep-target")]
+    keep_target: bool,
+    /// Build every project into this target directory so shared dependencies are only compiled
+    /// once. Overrides the `shared_target_dir` setting in the repos file
+    #[structopt(long = "shared-target-dir")]
+    shared_target_dir: Option<PathBuf>,
+    /// Compile with sccache so dependencies are cached between projects and runs. Overrides the
+    /// `sccache` setting in the repos file
+    #[structopt(long = "sccache")]
+    sccache: bool,
+    /// Target triple to build every project for, such as `wasm32-unknown-unknown`. Overrides the
+    /// `target` setting in the repos file, including any set on individual crates
+    #[structopt(long = "target")]
+    target: Option<String>,
+    /// Run tarpaulin with `cross` for projects with a target. Overrides the `cross` setting in
+    /// the repos file
+    #[structopt(long = "cross")]
+    cross: bool,
+    /// Clone projects with the git binary instead of libgit2. Overrides the `use_system_git`
+    /// setting in the repos file
+    #[structopt(long = "use-system-git")]
+    use_system_git: bool,
+    /// Run every project with both tarpaulin's ptrace and llvm engines and compare the results in
+    /// `engines.md`, results for each engine are kept in separate directories
+    #[structopt(long = "compare-engines")]
+    compare_engines: bool,
+    /// Show a progress bar, ignored if stdout isn't a terminal
+    #[structopt(long = "progress-bar")]
+    progress_bar: bool,
+    /// Show a live table of the crates being ran instead of logging to stdout, the logs are
+    /// written to `tater.log` in the output directory. Needs tater built with the `dashboard`
+    /// feature, ignored if stdout isn't a terminal
+    #[structopt(long = "tui")]
+    tui: bool,
+    /// Append a newline delimited JSON event to this file whenever a crate starts or finishes
+    #[structopt(long = "events-file")]
+    events_file: Option<PathBuf>,
+    /// POST the run summary as JSON to this URL when the run finishes
+    #[structopt(long = "webhook-url")]
+    webhook_url: Option<String>,
+    /// Secret to sign webhook payloads with, the HMAC-SHA256 signature is sent in the
+    /// `X-Tater-Signature` header
+    #[structopt(long = "webhook-secret")]
+    webhook_secret: Option<String>,
+    /// Run this cargo-tarpaulin binary directly instead of `cargo tarpaulin`, e.g. a local build
+    /// of tarpaulin. Overrides the `tarpaulin_path` setting in the repos file
+    #[structopt(long = "tarpaulin-path")]
+    tarpaulin_path: Option<PathBuf>,
+    /// Put the results straight in `results` and the pass, fail and progress files in the output
+    /// directory like older versions did, instead of in a new `results/<timestamp>` directory for
+    /// each run
+    #[structopt(long = "flat-results")]
+    flat_results: bool,
+    /// Run projects which failed with a likely transient error, such as a git failure, stall or
+    /// timeout, once more at the end of the run. Only the final result is kept
+    #[structopt(long = "retry-failures-at-end")]
+    retry_failures_at_end: bool,
+    /// Write tarpaulin's output straight to `stdout.log` and `stderr.log` in the results instead of
+    /// `<name>.log`, so it's kept if tater is killed or tarpaulin stalls. Overrides the
+    /// `output_to_files` setting in the repos file
+    #[structopt(long = "output-to-files")]
+    output_to_files: bool,
+    /// Stop at the first project that fails, saving progress so the run can be resumed from it
+    /// once it's fixed. Exits with code 2
+    #[structopt(long = "fail-fast")]
+    fail_fast: bool,
+    /// Resume a paused run even if the crates in the repos file changed since it was paused
+    #[structopt(long = "force-resume")]
+    force_resume: bool,
+    /// Use this directory as `CARGO_HOME` for everything tater runs, so its registry and git
+    /// caches can be managed without touching the usual ones
+    #[structopt(long = "cargo-home")]
+    cargo_home: Option<PathBuf>,
+    /// Delete the oldest downloaded crates and sources from the cargo registry between projects
+    /// when the registry and git caches are over this many gigabytes
+    #[structopt(long = "prune-cache-gb")]
+    prune_cache_gb: Option<u64>,
+    /// Stop the run, saving progress so it can be resumed, when less than this many gigabytes are
+    /// free on the disk holding the projects. Project target directories are removed and the cargo
+    /// cache pruned to try and free space first. 0 disables the check
+    #[structopt(long = "min-free-disk-gb", default_value = "2")]
+    min_free_disk_gb: u64,
+    /// Stop the run once it's been going this long, e.g. `5h30m`, saving progress so it can be
+    /// resumed like a ctrl-c pause. A crate that took longer than the time left in a previous run
+    /// isn't started
+    #[structopt(long = "time-budget")]
+    time_budget: Option<TimeBudget>,
+    /// With `--time-budget`, run a later crate that previously took less than the time left
+    /// instead of stopping at a crate that won't fit
+    #[structopt(long = "reorder-to-fit")]
+    reorder_to_fit: bool,
+    /// Read default arguments from this config file instead of looking for `.tater.toml` in the
+    /// current directory and its parents then `$XDG_CONFIG_HOME/tater/config.toml`
+    #[structopt(long = "config")]
+    config: Option<PathBuf>,
+    #[structopt(subcommand)]
+    command: Option<TaterCommand>,
+}
+
+/// Order the crates are ran in
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CrateOrder {
+    /// As listed in the repos file
+    #[default]
+    Repos,
+    /// Longest first by their duration in the previous results
+    Duration,
+}
+
+impl FromStr for CrateOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "repos" => Ok(Self::Repos),
+            "duration" => Ok(Self::Duration),
+            e => Err(format!("Unsupported crate order: {}", e)),
+        }
+    }
+}
+
+/// How long a run can go on for, given like `5h30m`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+struct TimeBudget(Duration);
+
+impl FromStr for TimeBudget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_human_duration(s).map(Self)
+    }
+}
+
+impl TryFrom<String> for TimeBudget {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// The steps from `step` to the last one for the same crate, `crates` is the crate index of each
+/// step. A crate has a step for each engine when comparing engines
+fn crate_steps(crates: &[usize], step: usize) -> Range<usize> {
+    let end = crates[step..]
+        .iter()
+        .position(|x| *x != crates[step])
+        .map_or(crates.len(), |x| step + x);
+    step..end
+}
+
+/// Total previous duration of the steps, `None` if any of them hasn't been ran before
+fn steps_duration(durations: &[Option<f64>]) -> Option<f64> {
+    durations.iter().copied().sum()
+}
+
+/// Steps of the first crate starting at or after `from` that previously took no longer than
+/// `remaining` seconds. Crates without a previous duration might not fit so they're passed over
+fn find_fitting(
+    crates: &[usize],
+    durations: &[Option<f64>],
+    from: usize,
+    remaining: f64,
+) -> Option<Range<usize>> {
+    let mut step = from;
+    while step < crates.len() {
+        let steps = crate_steps(crates, step);
+        if matches!(steps_duration(&durations[steps.clone()]), Some(x) if x <= remaining) {
+            return Some(steps);
+        }
+        step = steps.end;
+    }
+    None
+}
+
+/// Names of the crates slowest first by their previous duration, crates without one keep their
+/// order at the end
+fn order_by_duration(crates: &[CrateSpec], duration: impl Fn(&str) -> Option<f64>) -> Vec<String> {
+    let names = crates
+        .iter()
+        .map(|x| x.name().unwrap_or("unnamed_project").to_string());
+    let (mut known, unknown): (Vec<_>, Vec<_>) = names
+        .map(|x| (duration(&x), x))
+        .partition(|(duration, _)| duration.is_some());
+    // Stable so crates taking the same time keep their order
+    known.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    known.into_iter().chain(unknown).map(|(_, x)| x).collect()
+}
+
+/// The crates in the given order, any not in it are added at the end in their original order
+fn ordered_crates(crates: &[CrateSpec], order: &[String]) -> Vec<CrateSpec> {
+    let name = |x: &CrateSpec| x.name().unwrap_or("unnamed_project").to_string();
+    let mut ordered = order
+        .iter()
+        .filter_map(|x| crates.iter().find(|c| name(c) == *x))
+        .cloned()
+        .collect::<Vec<_>>();
+    ordered.extend(crates.iter().filter(|x| !order.contains(&name(x))).cloned());
+    ordered
+}
+
+#[derive(Debug, Clone, PartialEq, StructOpt)]
+enum TaterCommand {
+    /// Find the first tarpaulin version a crate fails with by installing and running published
+    /// versions between a good and bad version
+    Bisect(bisect::BisectArgs),
+    /// Compare the results in two output directories, exits with a non-zero code if there are
+    /// regressions
+    Diff(compare::DiffArgs),
+    /// Regenerate the report from the results in the output directory without running anything
+    Report(report::ReportArgs),
+    /// Print a summary of the output directory, safe to run while tater is running in it
+    Status,
+    /// Remove cloned projects from the output directory keeping their results
+    Clean(clean::CleanArgs),
+    /// Clone the projects and print which CI config each tarpaulin command was detected from,
+    /// without running anything. The results are also written to `detected.json` in the output
+    /// directory
+    Detect,
+}
+
+impl Args {
+    /// The jobs limit to pass to tarpaulin, `None` if it shouldn't be passed
+    fn jobs(&self) -> Option<usize> {
+        match self.jobs {
+            Some(0) => None,
+            Some(jobs) => Some(jobs),
+            None => Some(
+                std::thread::available_parallelism()
+                    .map(|x| x.get())
+                    .unwrap_or(1),
+            ),
+        }
+    }
+
+    /// Whether the project matches the label filter, always true if no labels were given
+    fn matches_labels(&self, spec: &CrateSpec) -> bool {
+        let has = |label: &String| spec.labels.contains(label);
+        if self.labels.is_empty() {
+            true
+        } else if self.all_labels {
+            self.labels.iter().all(has)
+        } else {
+            self.labels.iter().any(has)
+        }
+    }
+
+    /// Applies the settings given on the command line or in the config file over the repos file
+    fn override_context(&self, context: &mut Context) {
+        context.fetch |= self.fetch;
+        context.no_network |= self.no_network;
+        context.baseline_test |= self.baseline_test;
+        context.keep_target |= self.keep_target;
+        context.sccache |= self.sccache;
+        if let Some(target) = self.target.as_ref() {
+            context.target = Some(target.clone());
+            // Everything is built for the target asked for, even crates with their own
+            for spec in context.crates.iter_mut() {
+                spec.target = None;
+            }
+        }
+        context.cross |= self.cross;
+        context.use_system_git |= self.use_system_git;
+        if self.shared_target_dir.is_some() {
+            context.shared_target_dir = self.shared_target_dir.clone();
+        }
+        context.output_to_files |= self.output_to_files;
+        if let Some(engine) = self.engine {
+            context.engine = engine;
+        }
+        if self.tarpaulin_path.is_some() {
+            context.tarpaulin_path = self.tarpaulin_path.clone();
+        }
+    }
+
+    /// Parses the command line, anything not given on it is taken from the config file
+    fn load() -> Result<Self, String> {
+        let matches = Self::clap().get_matches();
+        let mut args = Self::from_clap(&matches);
+        let path = match args.config.clone() {
+            Some(path) => Some(path),
+            None => {
+                let current = env::current_dir().map_err(|e| e.to_string())?;
+                find_config(&current, xdg_config_home())
+            }
+        };
+        if let Some(path) = path {
+            info!("Using config file {}", path.display());
+            ConfigFile::load(&path)?.apply(&mut args, &matches);
+        }
+        Ok(args)
+    }
+}
+
+/// Config file looked for in the current directory and its parents, hidden so it can't be
+/// mistaken for a crate's override file
+const CONFIG_FILE: &str = ".tater.toml";
+
+/// Default arguments read from a config file, keys are the names of the long flags with
+/// underscores instead of dashes. Flags given on the command line take precedence and relative
+/// paths are relative to the config file
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    jobs: Option<usize>,
+    fetch: Option<bool>,
+    no_network: Option<bool>,
+    engine: Option<Engine>,
+    incremental: Option<bool>,
+    order_by: Option<CrateOrder>,
+    force: Option<Vec<String>>,
+    prefetch: Option<usize>,
+    labels: Option<Vec<String>>,
+    all_labels: Option<bool>,
+    dry_run: Option<bool>,
+    no_clone: Option<bool>,
+    baseline: Option<PathBuf>,
+    coverage_threshold: Option<f64>,
+    baseline_test: Option<bool>,
+    keep_target: Option<bool>,
+    shared_target_dir: Option<PathBuf>,
+    sccache: Option<bool>,
+    target: Option<String>,
+    cross: Option<bool>,
+    use_system_git: Option<bool>,
+    compare_engines: Option<bool>,
+    progress_bar: Option<bool>,
+    tui: Option<bool>,
+    events_file: Option<PathBuf>,
+    webhook_url: Option<String>,
+    webhook_secret: Option<String>,
+    tarpaulin_path: Option<PathBuf>,
+    flat_results: Option<bool>,
+    retry_failures_at_end: Option<bool>,
+    output_to_files: Option<bool>,
+    fail_fast: Option<bool>,
+    force_resume: Option<bool>,
+    cargo_home: Option<PathBuf>,
+    prune_cache_gb: Option<u64>,
+    min_free_disk_gb: Option<u64>,
+    time_budget: Option<TimeBudget>,
+    reorder_to_fit: Option<bool>,
+}
+
+/// Sets each field of `Args` from the config file unless its flag was given on the command line
+macro_rules! apply_config {
+    ($config:expr, $args:expr, $matches:expr, $($field:ident <- $key:ident: $name:literal),* $(,)?) => {
+        $(
+            if let Some(value) = $config.$key {
+                if $matches.occurrences_of($name) == 0 {
+                    $args.$field = value.into();
+                }
+            }
+        )*
+    };
+}
+
+impl ConfigFile {
+    fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+        let mut config: Self = toml::from_str(&contents)
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+        if let Some(dir) = path.parent() {
+            config.resolve_paths(dir);
+        }
+        Ok(config)
+    }
+
+    /// Makes the relative paths relative to the config file's directory instead of wherever tater
+    /// is ran from, a repos file URL is left alone
+    fn resolve_paths(&mut self, dir: &Path) {
+        let is_url = |path: &PathBuf| {
+            let path = path.to_string_lossy();
+            path.starts_with("http://") || path.starts_with("https://")
+        };
+        let paths = vec![
+            self.input.as_mut().filter(|x| !is_url(x)),
+            self.output.as_mut(),
+            self.baseline.as_mut(),
+            self.events_file.as_mut(),
+            self.tarpaulin_path.as_mut(),
+            self.cargo_home.as_mut(),
+            self.shared_target_dir.as_mut(),
+        ];
+        for path in paths.into_iter().flatten() {
+            if path.is_relative() {
+                *path = dir.join(&*path);
+            }
+        }
+    }
+
+    fn apply(self, args: &mut Args, matches: &structopt::clap::ArgMatches) {
+        apply_config!(self, args, matches,
+            repos <- input: "input repos",
+            output <- output: "output folder",
+            jobs <- jobs: "jobs",
+            fetch <- fetch: "fetch",
+            no_network <- no_network: "no-network",
+            engine <- engine: "engine",
+            incremental <- incremental: "incremental",
+            order_by <- order_by: "order-by",
+            force <- force: "force",
+            prefetch <- prefetch: "prefetch",
+            labels <- labels: "labels",
+            all_labels <- all_labels: "all-labels",
+            dry_run <- dry_run: "dry-run",
+            no_clone <- no_clone: "no-clone",
+            baseline <- baseline: "baseline",
+            coverage_threshold <- coverage_threshold: "coverage-threshold",
+            baseline_test <- baseline_test: "baseline-test",
+            keep_target <- keep_target: "keep-target",
+            shared_target_dir <- shared_target_dir: "shared-target-dir",
+            sccache <- sccache: "sccache",
+            target <- target: "target",
+            cross <- cross: "cross",
+            use_system_git <- use_system_git: "use-system-git",
+            compare_engines <- compare_engines: "compare-engines",
+            progress_bar <- progress_bar: "progress-bar",
+            tui <- tui: "tui",
+            events_file <- events_file: "events-file",
+            webhook_url <- webhook_url: "webhook-url",
+            webhook_secret <- webhook_secret: "webhook-secret",
+            tarpaulin_path <- tarpaulin_path: "tarpaulin-path",
+            flat_results <- flat_results: "flat-results",
+            retry_failures_at_end <- retry_failures_at_end: "retry-failures-at-end",
+            output_to_files <- output_to_files: "output-to-files",
+            fail_fast <- fail_fast: "fail-fast",
+            force_resume <- force_resume: "force-resume",
+            cargo_home <- cargo_home: "cargo-home",
+            prune_cache_gb <- prune_cache_gb: "prune-cache-gb",
+            min_free_disk_gb <- min_free_disk_gb: "min-free-disk-gb",
+            time_budget <- time_budget: "time-budget",
+            reorder_to_fit <- reorder_to_fit: "reorder-to-fit",
+        );
+    }
+}
+
+/// `$XDG_CONFIG_HOME`, falling back to `~/.config` as the spec says
+fn xdg_config_home() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+}
+
+/// Looks for `.tater.toml` in `start` and its parents, then `tater/config.toml` in the config home
+fn find_config(start: &Path, config_home: Option<PathBuf>) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|x| x.join(CONFIG_FILE))
+        .chain(config_home.map(|x| x.join("tater").join("config.toml")))
+        .find(|x| x.is_file())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     setup_logging();
-    let ctrlc_events = ctrl_handler()?;
-    let args = Args::from_args();
-
-    if !args.repos.is_file() {
-        panic!("No repos file provided");
+    let shutdown = ShutdownSignal::default();
+    let ctrlc_events = ctrl_handler(shutdown.clone())?;
+    let args = match Args::load() {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(TaterCommand::Diff(diff_args)) = args.command.as_ref() {
+        match compare::diff(diff_args) {
+            Ok(0) => return Ok(()),
+            Ok(_) => std::process::exit(1),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(2);
+            }
+        }
     }
+    if let Some(TaterCommand::Clean(clean_args)) = args.command.as_ref() {
+        if let Err(e) = clean::clean(&args.output, clean_args) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(TaterCommand::Status) = args.command.as_ref() {
+        // Only a local repos file is used so this never touches the network
+        let context = File::open(&args.repos)
+            .ok()
+            .and_then(|file| load_context(BufReader::new(file)).ok());
+        status::print_status(&args.output, context.as_ref());
+        return Ok(());
+    }
+    if let Some(TaterCommand::Report(report_args)) = args.command.as_ref() {
+        if let Err(e) = regenerate_report(&args.output, report_args) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let (repos, repos_dir) = match read_repos_file(&args.repos) {
+        Ok(repos) => repos,
+        Err(e) => {
+            error!("Unable to get repos file: {}", e);
+            std::process::exit(1);
+        }
+    };
     if args.output.is_file() {
         panic!("Output directory is a file");
     }
@@ -51,18 +620,150 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         info!("Creating output directory: {}", args.output.display());
         create_dir_all(&args.output).unwrap();
     }
+    if let Some(cargo_home) = args.cargo_home.as_ref() {
+        if let Err(e) = create_dir_all(cargo_home) {
+            error!("Unable to create {}: {}", cargo_home.display(), e);
+            std::process::exit(1);
+        }
+        info!("Using {} as CARGO_HOME", cargo_home.display());
+        env::set_var("CARGO_HOME", cargo_home);
+    }
 
-    if let Ok(file) = File::open(args.repos) {
-        let reader = BufReader::new(file);
-        let context: Context = serde_json::from_reader(reader).expect("Unable to parse repos json");
-        run_tater(&context, &args.output, args.jobs, ctrlc_events);
+    let mut context = match load_context(repos.as_bytes()) {
+        Ok(context) => context,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    context.shutdown = shutdown;
+    args.override_context(&mut context);
+    // Overrides live with the repos file
+    context.overrides_dir = Some(
+        repos_dir.join(
+            context
+                .overrides_dir
+                .take()
+                .unwrap_or_else(|| PathBuf::from(OVERRIDES_DIR)),
+        ),
+    );
+    // Tarpaulin runs in the project directory, so a relative path would point somewhere else
+    if let Some(path) = context.tarpaulin_path.as_mut() {
+        match path.canonicalize() {
+            Ok(absolute) => *path = absolute,
+            Err(e) if context.engine != Engine::Docker => {
+                error!("Invalid tarpaulin path {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+            Err(_) => {}
+        }
+    }
+    let tarpaulin = match context.tarpaulin_path.as_deref() {
+        Some(_) if context.engine == Engine::Docker => {
+            error!("A tarpaulin path can't be used with the docker engine");
+            std::process::exit(1);
+        }
+        Some(path) => match TarpaulinBinary::inspect(path) {
+            Ok(binary) => {
+                info!("Using {} from {}", binary.version, path.display());
+                Some(binary)
+            }
+            Err(e) => {
+                error!("Invalid tarpaulin path: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    if args.dry_run {
+        dry_run(&context, &args);
+        return Ok(());
+    }
+    if let Some(TaterCommand::Detect) = args.command.as_ref() {
+        detect(&context, &args);
+        return Ok(());
+    }
+    // Only runs write tarpaulin configs, tarpaulin runs in the project so the path is absolute
+    context.config_dir = args
+        .output
+        .canonicalize()
+        .ok()
+        .map(|x| x.join(ci::TARPAULIN_CONFIGS_DIR));
+    if let Some(TaterCommand::Bisect(bisect_args)) = args.command.as_ref() {
+        match bisect::bisect(&context, bisect_args, args.jobs(), &args.output) {
+            Ok(report) => match report.first_bad {
+                Some(version) => info!("First bad version: {}", version),
+                None => warn!("Couldn't find a bad version"),
+            },
+            Err(e) => {
+                error!("Bisect failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    write_metadata(
+        &args.output.join(METADATA_FILE),
+        &RunMetadata {
+            toolchain: context.toolchain.clone(),
+            tarpaulin: tarpaulin.clone(),
+        },
+    );
+    let summary = run_tater(&context, &args, tarpaulin.as_ref(), ctrlc_events);
+    if let Some(url) = args.webhook_url.as_deref() {
+        webhook::post(url, args.webhook_secret.as_deref(), &summary);
+    }
+    let regressions = args
+        .baseline
+        .as_ref()
+        .map(|baseline| compare::compare_runs(baseline, &args.output, args.coverage_threshold))
+        .unwrap_or(0);
+    let code = summary.exit_code(regressions);
+    if code != 0 {
+        std::process::exit(code);
     }
     Ok(())
 }
 
-fn ctrl_handler() -> Result<mpsc::Receiver<()>, ctrlc::Error> {
+/// Reads the repos file, downloading it if it's a URL, along with the directory overrides are
+/// looked for in. That's next to a local file, or the current directory for a downloaded one
+fn read_repos_file(repos: &Path) -> io::Result<(String, PathBuf)> {
+    let location = repos.to_string_lossy();
+    if location.starts_with("http://") || location.starts_with("https://") {
+        Ok((download_repos(&location)?, PathBuf::from(".")))
+    } else {
+        let dir = repos.parent().unwrap_or_else(|| Path::new("."));
+        Ok((read_to_string(repos)?, dir.to_path_buf()))
+    }
+}
+
+/// Downloads the repos file straight into memory so there's no temporary file to clean up
+#[cfg(feature = "remote")]
+fn download_repos(url: &str) -> io::Result<String> {
+    use std::io::Read;
+
+    info!("Downloading repos file from {}", url);
+    let response = ureq::get(url).call().map_err(io::Error::other)?;
+    let mut repos = String::new();
+    response.into_reader().read_to_string(&mut repos)?;
+    Ok(repos)
+}
+
+#[cfg(not(feature = "remote"))]
+fn download_repos(url: &str) -> io::Result<String> {
+    Err(io::Error::other(format!(
+        "can't download {} as tater was built without the `remote` feature",
+        url
+    )))
+}
+
+fn ctrl_handler(shutdown: ShutdownSignal) -> Result<mpsc::Receiver<()>, ctrlc::Error> {
     let (sender, receiver) = mpsc::channel();
     ctrlc::set_handler(move || {
+        match shutdown.request() {
+            Shutdown::Now => warn!("Stopping the current crate and pausing"),
+            _ => info!("Pausing after the current crate, press ctrl-c again to stop it now"),
+        }
         let _e = sender.send(());
     })?;
     Ok(receiver)
@@ -73,110 +774,1371 @@ fn setup_logging() {
         Ok(_) => EnvFilter::from_default_env(),
         _ => EnvFilter::new("tater=info"),
     };
-    let fmt = tracing_subscriber::fmt::Layer::default();
+    let fmt = tracing_subscriber::fmt::Layer::default().with_writer(progress::LogWriter::default);
     let subscriber = filter.and_then(fmt).with_subscriber(Registry::default());
     tracing::subscriber::set_global_default(subscriber).unwrap();
 }
 
-/// Returns the next crate to process for resuming a workflow
-fn get_progress(progress_file: &Path) -> std::io::Result<usize> {
-    if progress_file.is_file() {
-        let reader = BufReader::new(File::open(&progress_file)?);
-        if let Some(line) = reader.lines().next() {
-            let line = line?;
-            match line.trim().parse::<usize>() {
-                Ok(n) => Ok(n),
-                Err(_) => {
-                    warn!("Invalid progress file contents: {}", line);
-                    Ok(0)
-                }
-            }
-        } else {
-            Ok(0)
+/// Where a paused run carries on from, written to the progress file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Progress {
+    /// Index of the next crate to process
+    next: usize,
+    /// Name of the next crate so it can be found again if the repos file changed
+    #[serde(default)]
+    name: Option<String>,
+    /// Hash of the crates in the repos file when the run was paused
+    #[serde(default)]
+    crates_hash: Option<String>,
+    /// Names of the crates in the order they're being ran if it isn't the repos file order
+    #[serde(default)]
+    order: Option<Vec<String>>,
+}
+
+/// Hash of the crates' repository URLs in order, so it changes if crates are added, removed or
+/// reordered. This is FNV-1a so it's the same whichever tater build resumes the run
+fn crates_hash(crates: &[CrateSpec]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in crates
+        .iter()
+        .flat_map(|x| x.repository_url.as_str().bytes().chain(Some(b'\n')))
+    {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// Reads the progress file if there is one, older versions of tater wrote just the index
+fn get_progress(progress_file: &Path) -> std::io::Result<Option<Progress>> {
+    if !progress_file.is_file() {
+        return Ok(None);
+    }
+    let contents = std::fs::read_to_string(progress_file)?;
+    let contents = contents.trim();
+    if let Ok(next) = contents.parse::<usize>() {
+        return Ok(Some(Progress {
+            next,
+            name: None,
+            crates_hash: None,
+            order: None,
+        }));
+    }
+    match serde_json::from_str(contents) {
+        Ok(progress) => Ok(Some(progress)),
+        Err(_) => {
+            warn!("Invalid progress file contents: {}", contents);
+            Ok(None)
         }
-    } else {
-        Ok(0)
     }
 }
 
-fn should_exit(progress_file: &Path, index: usize, rx: &mpsc::Receiver<()>) -> bool {
+/// Works out which crate to resume from. If the repos file changed since the run was paused this
+/// is an error unless forced, then the crate that was next is looked for by name in case it moved
+fn resume_point(
+    progress: &Progress,
+    names: &[String],
+    crates_hash: &str,
+    force: bool,
+) -> Result<usize, String> {
+    if progress.crates_hash.as_deref().unwrap_or(crates_hash) != crates_hash {
+        if !force {
+            return Err(
+                "The crates in the repos file changed since the run was paused, pass \
+                 --force-resume to resume anyway"
+                    .to_string(),
+            );
+        }
+        warn!("The crates in the repos file changed since the run was paused");
+    }
+    let name = match progress.name.as_ref() {
+        Some(name) if names.get(progress.next) != Some(name) => name,
+        _ => return Ok(progress.next),
+    };
+    match names.iter().position(|x| x == name) {
+        Some(index) => {
+            warn!(
+                "{} moved from {} to {}, resuming from it",
+                name, progress.next, index
+            );
+            Ok(index)
+        }
+        None => {
+            warn!(
+                "{} is no longer in the repos file, resuming from {}",
+                name, progress.next
+            );
+            Ok(progress.next)
+        }
+    }
+}
+
+/// Writes where to carry on from so the run can be resumed
+fn write_progress(progress_file: &Path, progress: &Progress) {
+    let progress_msg = "Unable to write progress file do it yourself";
+    let f = File::create(progress_file).expect(progress_msg);
+    serde_json::to_writer(f, progress).expect(progress_msg);
+}
+
+fn should_exit(progress_file: &Path, progress: &Progress, rx: &mpsc::Receiver<()>) -> bool {
     if rx.try_recv().is_ok() {
         info!("Pausing execution");
-        let progress_msg = "Unable to write progress file do it yourself";
-        let mut f = File::create(&progress_file).expect(progress_msg);
-        f.write_all(index.to_string().as_bytes())
-            .expect(progress_msg);
+        write_progress(progress_file, progress);
         true
     } else {
         false
     }
 }
 
-fn get_status_linewriter(path: &Path, start_iter: usize) -> io::Result<BufWriter<File>> {
-    let file = if start_iter == 0 {
-        File::create(path)
-    } else {
-        OpenOptions::new().append(true).create(true).open(path)
-    }?;
-    Ok(BufWriter::new(file))
+/// Exit code when projects didn't do what was expected of them or regressed from the baseline
+const EXIT_FAILURES: i32 = 1;
+/// Exit code when `--fail-fast` stopped the run at a failure
+const EXIT_FAIL_FAST: i32 = 2;
+/// Exit code when the run was stopped as the disk is nearly full
+const EXIT_DISK_FULL: i32 = 3;
+/// Exit code when the run was paused with ctrl-c, the usual code for being interrupted
+const EXIT_PAUSED: i32 = 130;
+
+/// Tally of how the projects ran compared to what was expected of them
+#[derive(Debug, Default, Clone, Serialize)]
+struct RunSummary {
+    expected_pass: usize,
+    expected_fail: usize,
+    unexpected_pass: usize,
+    unexpected_fail: usize,
+    skipped: usize,
+    /// Number of failures of each kind
+    failures: BTreeMap<String, usize>,
+    /// Failures where `cargo test` passes
+    tarpaulin_only: usize,
+    /// Failures where `cargo test` fails too
+    cargo_test_too: usize,
+    /// Failures which passed when retried at the end of the run
+    recovered: usize,
+    /// The run was paused with ctrl-c before every project was ran
+    paused: bool,
+    /// `--fail-fast` stopped the run at a failure
+    failed_fast: bool,
+    /// The run was stopped as the disk is nearly full
+    disk_full: bool,
+    /// The run was paused as `--time-budget` was used up
+    out_of_time: bool,
+    /// Projects left to run when the run stopped early, when comparing engines each engine
+    /// counts separately
+    remaining: usize,
+    /// Version of tarpaulin the projects were ran with, `None` if it's ran in docker
+    tarpaulin_version: Option<String>,
+    /// Features tarpaulin is meant to be built with from the repos file
+    tarpaulin_features: Vec<String>,
+    /// Projects killed by the OOM killer, these should be ran again with fewer jobs
+    out_of_memory: Vec<String>,
+    /// Projects skipped as there's no `Cargo.toml` in their repository
+    not_rust: Vec<String>,
+}
+
+impl RunSummary {
+    fn record(&mut self, expected: Expectation, status: Status) {
+        match (expected, status == Status::Pass) {
+            (Expectation::Fail, false) => self.expected_fail += 1,
+            (Expectation::Fail, true) => self.unexpected_pass += 1,
+            (_, true) => self.expected_pass += 1,
+            (_, false) => self.unexpected_fail += 1,
+        }
+    }
+
+    fn record_skip(&mut self, name: &str, reason: Option<SkipReason>) {
+        self.skipped += 1;
+        if reason == Some(SkipReason::NotRust) {
+            self.not_rust.push(name.to_string());
+        }
+    }
+
+    fn record_failure(&mut self, name: &str, label: String, baseline: Option<BaselineResult>) {
+        if label == RunError::OutOfMemory.label() {
+            self.out_of_memory.push(name.to_string());
+        }
+        *self.failures.entry(label).or_default() += 1;
+        match baseline {
+            Some(BaselineResult::TarpaulinOnly) => self.tarpaulin_only += 1,
+            Some(BaselineResult::CargoTestToo) => self.cargo_test_too += 1,
+            None => {}
+        }
+    }
+
+    fn unexpected(&self) -> usize {
+        self.unexpected_pass + self.unexpected_fail
+    }
+
+    /// What tater exits with so scripts can tell how the run went
+    fn exit_code(&self, regressions: usize) -> i32 {
+        if self.failed_fast {
+            EXIT_FAIL_FAST
+        } else if self.disk_full {
+            EXIT_DISK_FULL
+        } else if self.paused {
+            EXIT_PAUSED
+        } else if self.unexpected() > 0 || regressions > 0 {
+            EXIT_FAILURES
+        } else {
+            0
+        }
+    }
+
+    fn log(&self) {
+        info!(
+            "Expected passes: {}, expected failures: {}, skipped: {}",
+            self.expected_pass, self.expected_fail, self.skipped
+        );
+        for (kind, count) in &self.failures {
+            info!("{} failures: {}", kind, count);
+        }
+        if self.recovered > 0 {
+            info!("Failures recovered by retrying: {}", self.recovered);
+        }
+        if !self.out_of_memory.is_empty() {
+            warn!(
+                "Likely killed by the OOM killer, run these again with fewer jobs: {}",
+                self.out_of_memory.join(", ")
+            );
+        }
+        if !self.not_rust.is_empty() {
+            warn!(
+                "Skipped as they aren't rust projects, check their repository URLs: {}",
+                self.not_rust.join(", ")
+            );
+        }
+        if self.tarpaulin_only + self.cargo_test_too > 0 {
+            info!(
+                "Failing under tarpaulin only: {}, failing under cargo test too: {}",
+                self.tarpaulin_only, self.cargo_test_too
+            );
+        }
+        if self.unexpected() > 0 {
+            error!(
+                "Unexpected passes: {}, unexpected failures: {}",
+                self.unexpected_pass, self.unexpected_fail
+            );
+        }
+        if self.remaining > 0 {
+            info!(
+                "Stopped with {} projects remaining, resume the run to carry on",
+                self.remaining
+            );
+        }
+    }
+}
+
+/// A failure put off to be retried at the end of the run
+struct Retry {
+    step: usize,
+    run_name: String,
+    label: String,
+    baseline: Option<BaselineResult>,
+    /// How long the failed run took
+    duration: f64,
 }
 
-fn run_tater(context: &Context, output: &Path, jobs: Option<usize>, rx: mpsc::Receiver<()>) {
+/// The project's spec with the coverage engine it's being ran with applied
+fn engine_spec(proj: &CrateSpec, engine: Option<CoverageEngine>) -> Cow<'_, CrateSpec> {
+    match engine {
+        Some(engine) => {
+            let mut spec = proj.clone();
+            spec.tarpaulin_engine = Some(engine);
+            Cow::Owned(spec)
+        }
+        None => Cow::Borrowed(proj),
+    }
+}
+
+/// Prints a table of the command that would be ran for each project
+fn dry_run(context: &Context, args: &Args) {
+    let projects = args.output.join("projects");
+    if create_dir(&projects).is_err() {
+        warn!("Projects directory already exists");
+    }
+    let mut rows = vec![];
+    for proj in context
+        .crates
+        .iter()
+        .filter(|x| !x.skip && args.matches_labels(x))
+    {
+        let proj_name = proj.name().unwrap_or("unnamed_project");
+        let command = match resolve_command(context, proj, args.jobs(), &projects, !args.no_clone) {
+            Ok(cmd) => {
+                let dir = cmd
+                    .get_current_dir()
+                    .map(|x| x.display().to_string())
+                    .unwrap_or_default();
+                format!("(cd {} && {})", dir, command_line(&cmd).join(" "))
+            }
+            Err(e) => format!("error: {}", e),
+        };
+        rows.push((proj_name, command));
+    }
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, command) in &rows {
+        println!("{:width$}  {}", name, command, width = width);
+    }
+}
+
+/// Where a project's tarpaulin command came from, written by `tater detect`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct DetectedCommand {
+    name: String,
+    /// `None` if the command couldn't be resolved, such as when cloning failed
+    provider: Option<ci::CiProvider>,
+    command: Vec<String>,
+    dir: Option<PathBuf>,
+    /// Why each CI config tried before the one used couldn't be
+    failures: BTreeMap<String, String>,
+    error: Option<String>,
+}
+
+/// Name of the file `tater detect` writes its results to in the output directory
+const DETECTED_FILE: &str = "detected.json";
+
+/// Lines of the table `tater detect` prints, the CI config is `error` if the command couldn't be
+/// resolved
+fn detected_table(rows: &[DetectedCommand]) -> Vec<String> {
+    let provider = |row: &DetectedCommand| match row.provider {
+        Some(provider) => provider.to_string(),
+        None => "error".to_string(),
+    };
+    let name_width = rows.iter().map(|x| x.name.len()).fold(5, usize::max);
+    let provider_width = rows.iter().map(|x| provider(x).len()).fold(2, usize::max);
+    let line = |name: &str, provider: &str, command: &str| {
+        format!(
+            "{:name_width$}  {:provider_width$}  {}",
+            name,
+            provider,
+            command,
+            name_width = name_width,
+            provider_width = provider_width
+        )
+    };
+    let mut lines = vec![line("Crate", "CI", "Command")];
+    for row in rows {
+        let command = match (&row.error, &row.dir) {
+            (Some(e), _) => e.clone(),
+            (None, Some(dir)) => format!("(cd {} && {})", dir.display(), row.command.join(" ")),
+            (None, None) => row.command.join(" "),
+        };
+        lines.push(line(&row.name, &provider(row), &command));
+    }
+    lines
+}
+
+/// Prints the CI config each project's command was detected from and the command, then writes
+/// them to the output directory
+fn detect(context: &Context, args: &Args) {
+    let projects = args.output.join("projects");
+    let _ = create_dir_all(&projects);
+    let mut rows = vec![];
+    for proj in context
+        .crates
+        .iter()
+        .filter(|x| !x.skip && args.matches_labels(x))
+    {
+        let name = proj.name().unwrap_or("unnamed_project").to_string();
+        let detection =
+            detect_project_command(context, proj, args.jobs(), &projects, !args.no_clone);
+        rows.push(match detection {
+            Ok(detection) => DetectedCommand {
+                name,
+                provider: Some(detection.provider),
+                command: command_line(&detection.command),
+                dir: detection.command.get_current_dir().map(Path::to_path_buf),
+                failures: detection
+                    .failures
+                    .into_iter()
+                    .map(|(provider, e)| (provider.to_string(), e))
+                    .collect(),
+                error: None,
+            },
+            Err(e) => DetectedCommand {
+                name,
+                provider: None,
+                command: vec![],
+                dir: None,
+                failures: BTreeMap::new(),
+                error: Some(e.to_string()),
+            },
+        });
+    }
+    for line in detected_table(&rows) {
+        println!("{}", line);
+    }
+    let path = args.output.join(DETECTED_FILE);
+    match File::create(&path) {
+        Ok(f) => {
+            if let Err(e) = serde_json::to_writer_pretty(f, &rows) {
+                error!("Failed to write {}: {}", path.display(), e);
+            }
+        }
+        Err(e) => error!("Failed to create {}: {}", path.display(), e),
+    }
+}
+
+/// Where logs are written in the output directory while the dashboard is shown
+const TUI_LOG_FILE: &str = "tater.log";
+
+/// The version of tarpaulin projects will be ran with, exits if tarpaulin isn't installed rather
+/// than failing every project. Docker images bring their own tarpaulin so this isn't checked.
+/// `tarpaulin` is the already inspected `tarpaulin_path` binary
+fn tarpaulin_version(context: &Context, tarpaulin: Option<&TarpaulinBinary>) -> Option<String> {
+    if context.engine == Engine::Docker {
+        return None;
+    }
+    let version = match tarpaulin {
+        Some(binary) => ci::parse_tarpaulin_version(&binary.version),
+        None => ci::detect_tarpaulin_version(context),
+    };
+    match version {
+        Some(version) => {
+            info!("Running with tarpaulin {}", version);
+            Some(version)
+        }
+        None if context.tarpaulin_path.is_some() => {
+            warn!("Couldn't get the version of the tarpaulin binary");
+            None
+        }
+        None => std::process::exit(1),
+    }
+}
+
+/// Takes over the terminal with the dashboard, logs go to a file in the output until it finishes
+fn start_dashboard(total: usize, output: &Path) -> Option<Dashboard> {
+    let log_file = output.join(TUI_LOG_FILE);
+    match File::create(&log_file) {
+        Ok(file) => progress::redirect_logs(Some(file)),
+        Err(e) => {
+            error!("Unable to create {}: {}", log_file.display(), e);
+            return None;
+        }
+    }
+    Dashboard::start(total)
+        .map_err(|e| {
+            progress::redirect_logs(None);
+            error!("Unable to start the dashboard: {}", e);
+        })
+        .ok()
+}
+
+fn finish_dashboard(dashboard: Option<Dashboard>) {
+    if let Some(dashboard) = dashboard {
+        dashboard.finish();
+        progress::redirect_logs(None);
+    }
+}
+
+// Only ever ran after a project's run returns so tarpaulin and anything it spawned is dead
+fn prune_cache(args: &Args) {
+    if let (Some(gb), Some(cargo_home)) = (args.prune_cache_gb, cache::cargo_home()) {
+        cache::prune_cache(&cargo_home, gb * cache::GIGABYTE);
+    }
+}
+
+/// A full disk fails every project in ways that look like the project's fault, so space is freed
+/// if possible and otherwise the run stops
+fn check_disk(args: &Args, projects: &Path) -> Result<(), disk::DiskFull> {
+    let min_free = args.min_free_disk_gb * cache::GIGABYTE;
+    if min_free == 0 {
+        return Ok(());
+    }
+    disk::check_space(projects, min_free, disk::available_space).or_else(|e| {
+        warn!("{}, removing project target directories", e);
+        disk::remove_targets(projects);
+        prune_cache(args);
+        disk::check_space(projects, min_free, disk::available_space)
+    })
+}
+
+/// A run of a project, each project is ran once per engine when comparing engines
+struct Step<'a> {
+    /// Index of the project in the repos file
+    index: usize,
+    proj: &'a CrateSpec,
+    engine: Option<CoverageEngine>,
+    results: PathBuf,
+    name: String,
+}
+
+/// Everything the binary keeps track of on top of running the projects: resuming, the time
+/// budget, retries, status files, events and the progress shown while running
+struct TaterRun<'a> {
+    args: &'a Args,
+    context: &'a Context,
+    projects: PathBuf,
+    results: PathBuf,
+    /// Results of the last run, incremental runs skip what passed in it
+    previous_results: PathBuf,
+    progress_file: PathBuf,
+    crates_hash: String,
+    order: Option<Vec<String>>,
+    steps: Vec<Step<'a>>,
+    /// How long each step took last time, skipped steps take no time
+    previous_durations: Vec<Option<f64>>,
+    /// The step being ran or the next one to look at
+    step: usize,
+    run_start: Instant,
+    /// When the project being ran was started
+    started: Instant,
+    prefetcher: Option<Prefetcher>,
+    dashboard: Option<Dashboard>,
+    events: EventWriter,
+    status_files: StatusFiles,
+    summary: RunSummary,
+    failures: usize,
+    project_timings: Vec<(String, Timings)>,
+    retries: VecDeque<Retry>,
+    retrying: bool,
+    /// Name of the project the run stopped at if it stopped early
+    stopped_at: Option<String>,
+    rx: mpsc::Receiver<()>,
+}
+
+impl<'a> TaterRun<'a> {
+    fn previous_result(&self, proj_res: &Path) -> PathBuf {
+        self.previous_results
+            .join(proj_res.strip_prefix(&self.results).unwrap_or(proj_res))
+            .join(RESULT_FILE)
+    }
+
+    fn skip_reason(&self, proj: &CrateSpec, proj_res: &Path) -> Option<&'static str> {
+        let proj_name = proj.name().unwrap_or("unnamed_project");
+        if !self.args.matches_labels(proj) {
+            Some("it doesn't match the label filter")
+        } else if proj.expected == Expectation::Skip {
+            Some("it's expected to be skipped")
+        } else if self.args.incremental
+            && !self.args.force.iter().any(|x| x == proj_name)
+            && previously_passed(&self.previous_result(proj_res))
+        {
+            Some("it passed in a previous run")
+        } else {
+            None
+        }
+    }
+
+    fn progress_at(&self, next: usize) -> Progress {
+        Progress {
+            next,
+            name: self.steps.get(next).map(|x| x.name.clone()),
+            crates_hash: Some(self.crates_hash.clone()),
+            order: self.order.clone(),
+        }
+    }
+
+    /// Whether the step fits in what's left of the time budget, moving a later crate that does
+    /// fit in front of it if allowed. If nothing fits the run is paused
+    fn fits_time_budget(&mut self, step: usize) -> bool {
+        let budget = match self.args.time_budget {
+            Some(TimeBudget(budget)) => budget,
+            None => return true,
+        };
+        let remaining = budget
+            .saturating_sub(self.run_start.elapsed())
+            .as_secs_f64();
+        let crates = self.steps.iter().map(|x| x.index).collect::<Vec<_>>();
+        let steps = crate_steps(&crates, step);
+        let needed = steps_duration(&self.previous_durations[steps.clone()]);
+        let fits = remaining > 0.0 && !matches!(needed, Some(x) if x > remaining);
+        // Only whole crates are moved so a resumed run rebuilds the same steps from the order
+        let starts_crate = step == 0 || crates[step - 1] != crates[step];
+        let fitting = if !fits && remaining > 0.0 && self.args.reorder_to_fit && starts_crate {
+            find_fitting(&crates, &self.previous_durations, steps.end, remaining)
+        } else {
+            None
+        };
+        if let Some(fitting) = fitting {
+            info!(
+                "Running {} before {} to fit in the time budget",
+                self.steps[fitting.start].name, self.steps[step].name
+            );
+            let moved = fitting.len();
+            self.steps[step..fitting.end].rotate_right(moved);
+            self.previous_durations[step..fitting.end].rotate_right(moved);
+            let mut names = self
+                .steps
+                .iter()
+                .map(|x| x.proj.name().unwrap_or("unnamed_project").to_string())
+                .collect::<Vec<_>>();
+            names.dedup();
+            self.order = Some(names);
+        } else if !fits {
+            if remaining > 0.0 {
+                warn!(
+                    "{} took longer than the {} left last time",
+                    self.steps[step].name,
+                    human_duration(Duration::from_secs_f64(remaining))
+                );
+            }
+            info!(
+                "Time budget of {} used up, pausing the run",
+                human_duration(budget)
+            );
+            write_progress(&self.progress_file, &self.progress_at(step));
+            self.summary.paused = true;
+            self.summary.out_of_time = true;
+            self.summary.remaining = self.steps.len() - step;
+            self.stopped_at = Some(self.steps[step].name.clone());
+            return false;
+        }
+        true
+    }
+
+    /// Whether the step is skipped, skipping it if so
+    fn skip(&mut self, step: usize) -> bool {
+        let Step {
+            proj,
+            results: proj_res,
+            name: run_name,
+            ..
+        } = &self.steps[step];
+        if !self.args.matches_labels(proj) {
+            true
+        } else if proj.skip {
+            info!("Skipping {} (marked skip=true)", run_name);
+            true
+        } else if let Some(reason) = self.skip_reason(proj, proj_res) {
+            info!("Skipping {} as {}", run_name, reason);
+            self.summary.skipped += 1;
+            if proj.expected == Expectation::Skip {
+                let result = ProjectResult::skipped(self.context, proj);
+                write_result(&proj_res.join(RESULT_FILE), &result);
+            } else if let Some(result) = read_result(&self.previous_result(proj_res)) {
+                // Carry the passing result into this run so the reports include it
+                write_result(&proj_res.join(RESULT_FILE), &result);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn next_retry(&mut self) -> Option<PlannedRun<'a>> {
+        if !self.retrying {
+            self.retrying = true;
+            if !self.retries.is_empty() {
+                info!("Retrying {} failed projects", self.retries.len());
+            }
+        }
+        let next = self.retries.front()?.step;
+        // Resuming starts from the first project still to be retried
+        if should_exit(&self.progress_file, &self.progress_at(next), &self.rx) {
+            let run_name = self.steps[next].name.clone();
+            self.pause_retries(next, run_name);
+            return None;
+        }
+        let retry = self.retries.pop_front()?;
+        let step = &self.steps[retry.step];
+        Some(PlannedRun {
+            index: step.index,
+            spec: engine_spec(step.proj, step.engine),
+            name: retry.run_name,
+            results: step.results.clone(),
+            prepared: false,
+        })
+    }
+
+    /// Stops the retries part way through, resuming from `step`
+    fn pause_retries(&mut self, step: usize, run_name: String) {
+        self.summary.paused = true;
+        self.summary.remaining = self.steps.len() - step;
+        self.stopped_at = Some(run_name);
+    }
+
+    fn finished_retry(&mut self, run: PlannedRun<'a>, outcome: RunOutcome) {
+        let run_name = run.name;
+        let duration = outcome.timings.total;
+        let status = outcome.status();
+        let interrupted = matches!(outcome.error, Some(RunError::Interrupted));
+        if interrupted {
+            // Stopped part way through so it's ran again when resuming
+        } else if status == Status::Skipped {
+            self.summary.record_skip(&run_name, outcome.skip_reason());
+        } else {
+            self.summary.record(run.spec.expected, status);
+        }
+        let result = ProjectResult::new(self.context, &run.spec, &outcome);
+        write_result(&run.results.join(RESULT_FILE), &result);
+        if let Some(timings) = self
+            .project_timings
+            .iter_mut()
+            .find(|(x, _)| *x == run_name)
+        {
+            timings.1 = outcome.timings;
+        }
+        if interrupted {
+            info!("{} was stopped, it'll be ran again when resuming", run_name);
+            let step = self
+                .steps
+                .iter()
+                .position(|x| x.name == run_name)
+                .unwrap_or(self.steps.len());
+            write_progress(&self.progress_file, &self.progress_at(step));
+            self.status_files.paused(&run_name);
+            self.pause_retries(step, run_name);
+        } else if let Some(e) = outcome.error {
+            self.failures += 1;
+            error!("Tarpaulin failed on {} again: {:?}", run_name, e);
+            let label = e.label();
+            self.status_files.failed(&run_name, &label, duration);
+            self.summary
+                .record_failure(&run_name, label, outcome.baseline);
+        } else if status == Status::Skipped {
+            self.status_files.skipped(&run_name, duration);
+        } else {
+            info!("{} passed when retried", run_name);
+            self.summary.recovered += 1;
+            self.status_files.passed(&run_name, duration);
+        }
+    }
+}
+
+impl<'a> RunHooks<'a> for TaterRun<'a> {
+    fn next_run(&mut self) -> Option<PlannedRun<'a>> {
+        if self.stopped_at.is_some() {
+            return None;
+        }
+        while self.step < self.steps.len() {
+            let step = self.step;
+            if !self.fits_time_budget(step) {
+                return None;
+            }
+            progress::start_crate(step, &self.steps[step].name);
+            if self.skip(step) {
+                self.step += 1;
+                continue;
+            }
+            let Step {
+                index,
+                proj,
+                engine,
+                results,
+                name,
+            } = &self.steps[step];
+            let prepared = self
+                .prefetcher
+                .as_ref()
+                .is_some_and(|x| x.wait_for(proj.name().unwrap_or("unnamed_project")));
+            self.started = Instant::now();
+            return Some(PlannedRun {
+                index: *index,
+                spec: engine_spec(proj, *engine),
+                name: name.clone(),
+                results: results.clone(),
+                prepared,
+            });
+        }
+        self.next_retry()
+    }
+
+    fn event(&self, event: RunEvent) {
+        self.events.event(&event);
+        if let Some(dashboard) = self.dashboard.as_ref() {
+            dashboard.event(&event);
+        }
+    }
+
+    fn finished(&mut self, run: PlannedRun<'a>, outcome: RunOutcome) {
+        prune_cache(self.args);
+        if self.retrying {
+            self.finished_retry(run, outcome);
+            return;
+        }
+        let step = self.step;
+        let total = self.steps.len();
+        let run_name = run.name;
+        let duration = outcome.timings.total;
+        let elapsed = self.started.elapsed();
+        let remaining = heartbeat::finished_crate(step + 1, elapsed, outcome.error.is_some());
+        info!(
+            "{}: completed in {}s ({}/{} done, ~{}s remaining)",
+            run_name,
+            elapsed.as_secs(),
+            step + 1,
+            total,
+            remaining.unwrap_or_default().as_secs()
+        );
+        let status = outcome.status();
+        let skip_reason = outcome.skip_reason();
+        let result = ProjectResult::new(self.context, &run.spec, &outcome);
+        write_result(&run.results.join(RESULT_FILE), &result);
+        self.project_timings
+            .push((run_name.clone(), outcome.timings));
+        // Failed projects are ran again when resuming
+        let exit_index = if outcome.error.is_some() {
+            step
+        } else {
+            step + 1
+        };
+        self.step += 1;
+        let paused = should_exit(&self.progress_file, &self.progress_at(exit_index), &self.rx);
+        let fail_fast = self.args.fail_fast && !paused && outcome.error.is_some();
+        if fail_fast {
+            write_progress(&self.progress_file, &self.progress_at(exit_index));
+        }
+        let disk_full = !paused && !fail_fast && step + 1 < total && {
+            match check_disk(self.args, &self.projects) {
+                Ok(()) => false,
+                Err(e) => {
+                    error!("Stopping the run, {}. Resume it once space is freed", e);
+                    write_progress(&self.progress_file, &self.progress_at(exit_index));
+                    true
+                }
+            }
+        };
+        let stopping = paused || fail_fast || disk_full;
+        match outcome.error {
+            Some(e) if self.args.retry_failures_at_end && !stopping && e.retryable() => {
+                warn!("{} failed with {}, retrying at the end", run_name, e);
+                self.retries.push_back(Retry {
+                    step,
+                    run_name,
+                    label: e.label(),
+                    baseline: outcome.baseline,
+                    duration,
+                });
+                return;
+            }
+            // Stopped part way through so it's ran again when resuming
+            Some(RunError::Interrupted) => {}
+            None if status == Status::Skipped => self.summary.record_skip(&run_name, skip_reason),
+            _ => self.summary.record(run.spec.expected, status),
+        }
+        if let Some(RunError::Interrupted) = outcome.error {
+            info!("{} was stopped, it'll be ran again when resuming", run_name);
+            self.status_files.paused(&run_name);
+        } else if let Some(e) = outcome.error {
+            self.failures += 1;
+            error!("Tarpaulin failed on {}: {:?}", run_name, e);
+            let label = e.label();
+            if stopping {
+                self.status_files.paused(&run_name);
+            } else {
+                self.status_files.failed(&run_name, &label, duration);
+            }
+            self.summary
+                .record_failure(&run_name, label, outcome.baseline);
+        } else if status == Status::Skipped {
+            self.status_files.skipped(&run_name, duration);
+        } else {
+            self.status_files.passed(&run_name, duration);
+        }
+
+        if stopping {
+            self.summary.paused = paused;
+            self.summary.failed_fast = fail_fast;
+            self.summary.disk_full = disk_full;
+            self.summary.remaining = total - exit_index;
+            self.stopped_at = Some(run_name);
+        }
+    }
+}
+
+fn run_tater(
+    context: &Context,
+    args: &Args,
+    tarpaulin: Option<&TarpaulinBinary>,
+    rx: mpsc::Receiver<()>,
+) -> RunSummary {
+    let run_start = Instant::now();
     info!("Processing {} projects", context.crates.len());
+    let tarpaulin_version = tarpaulin_version(context, tarpaulin);
+    let output = &args.output;
     let projects = output.join("projects");
-    let results = output.join("results");
-    let progress_file = output.join("progress");
-    let pass_file = output.join("pass");
-    let fail_file = output.join("fail");
     if create_dir(&projects).is_err() {
         warn!("Projects directory already exists");
     }
-    if create_dir(&results).is_err() {
-        warn!("Results directory already exists");
-    }
-    let start_from = match get_progress(&progress_file) {
-        Ok(s) => s,
+    let previous = RunDirs::latest(output);
+    let run = if args.flat_results {
+        let run = RunDirs::flat(output);
+        if create_dir(&run.results).is_err() {
+            warn!("Results directory already exists");
+        }
+        if !run.resumable() {
+            StatusFiles::clear(&run.status);
+        }
+        run
+    } else {
+        match RunDirs::latest_run(output).filter(RunDirs::resumable) {
+            Some(run) => run,
+            None => RunDirs::start(output).unwrap_or_else(|e| {
+                error!("Unable to create the results directory: {}", e);
+                std::process::exit(1);
+            }),
+        }
+    };
+    let results = &run.results;
+    let progress_file = run.progress_file();
+    let resumed = run.resumable();
+    let saved_progress = get_progress(&progress_file);
+    // Hashed in the repos file order so a reordered run can still be resumed
+    let crates_hash = crates_hash(&context.crates);
+    let saved_order = match saved_progress.as_ref() {
+        Ok(Some(progress)) => progress.order.clone(),
+        _ => None,
+    };
+    let order = saved_order.or_else(|| match args.order_by {
+        CrateOrder::Repos => None,
+        CrateOrder::Duration => Some(order_by_duration(&context.crates, |name| {
+            read_result(&result_file(&previous.results, name)).map(|x| x.timings.total)
+        })),
+    });
+    let ordered;
+    let context = match order.as_ref() {
+        Some(order) => {
+            info!("Running crates in the order: {}", order.join(", "));
+            ordered = Context {
+                crates: ordered_crates(&context.crates, order),
+                ..context.clone()
+            };
+            &ordered
+        }
+        None => context,
+    };
+    // When comparing engines each crate is ran once per engine, progress counts these runs
+    let engines = if args.compare_engines {
+        vec![Some(CoverageEngine::Ptrace), Some(CoverageEngine::Llvm)]
+    } else {
+        vec![None]
+    };
+    let steps = context
+        .crates
+        .iter()
+        .enumerate()
+        .flat_map(|(index, proj)| {
+            let proj_name = proj.name().unwrap_or("unnamed_project");
+            let proj_res = results.join(proj_name);
+            engines.iter().map(move |engine| match engine {
+                Some(engine) => Step {
+                    index,
+                    proj,
+                    engine: Some(*engine),
+                    results: proj_res.join(engine.to_string()),
+                    name: format!("{}/{}", proj_name, engine),
+                },
+                None => Step {
+                    index,
+                    proj,
+                    engine: None,
+                    results: proj_res.clone(),
+                    name: proj_name.to_string(),
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+    let run_names = steps.iter().map(|x| x.name.clone()).collect::<Vec<_>>();
+    let start_from = match saved_progress {
+        Ok(Some(progress)) => resume_point(&progress, &run_names, &crates_hash, args.force_resume)
+            .unwrap_or_else(|e| {
+                error!("{}", e);
+                std::process::exit(1);
+            }),
+        Ok(None) => 0,
         Err(e) => {
             error!("Invalid progress file: {}", e);
             0
         }
     };
-    if start_from > 0 {
-        info!("Resuming execution from {}", start_from);
+    if resumed {
+        info!(
+            "Resuming execution from {} in {}",
+            start_from,
+            results.display()
+        );
+    } else {
+        info!("Writing results to {}", results.display());
+    }
+    let jobs = args.jobs();
+    if let Some(jobs) = jobs {
+        info!("Limiting to {} jobs", jobs);
+    }
+    if let Err(e) = check_disk(args, &projects) {
+        error!("Not starting the run, {}", e);
+        return RunSummary {
+            disk_full: true,
+            ..Default::default()
+        };
+    }
+    let events = EventWriter::new(args.events_file.as_deref()).unwrap_or_else(|e| {
+        error!("Unable to open events file: {}", e);
+        EventWriter::default()
+    });
+    let status_files = StatusFiles::open(&run.status).unwrap_or_else(|e| {
+        error!(
+            "Unable to open the status files in {}: {}",
+            run.status.display(),
+            e
+        );
+        std::process::exit(1);
+    });
+    let total = steps.len();
+    let mut hooks = TaterRun {
+        args,
+        context,
+        projects,
+        results: results.clone(),
+        previous_results: previous.results,
+        progress_file,
+        crates_hash,
+        order,
+        steps,
+        previous_durations: vec![],
+        step: start_from,
+        run_start,
+        started: Instant::now(),
+        prefetcher: None,
+        dashboard: None,
+        events,
+        status_files,
+        summary: RunSummary {
+            tarpaulin_version,
+            tarpaulin_features: context.tarpaulin_features.clone(),
+            ..Default::default()
+        },
+        failures: 0,
+        project_timings: vec![],
+        retries: VecDeque::new(),
+        retrying: false,
+        stopped_at: None,
+        rx,
+    };
+    // Skipped projects take no time so only the ones that'll run can blow the time budget
+    hooks.previous_durations = hooks
+        .steps
+        .iter()
+        .map(|x| {
+            if x.proj.skip || hooks.skip_reason(x.proj, &x.results).is_some() {
+                Some(0.0)
+            } else {
+                read_result(&hooks.previous_result(&x.results)).map(|x| x.timings.total)
+            }
+        })
+        .collect();
+    hooks.prefetcher = args.prefetch.filter(|n| *n > 0).map(|n| {
+        let mut to_clone = vec![];
+        for x in hooks.steps.iter().skip(start_from) {
+            if !x.proj.skip
+                && hooks.skip_reason(x.proj, &x.results).is_none()
+                && !to_clone.contains(x.proj)
+            {
+                to_clone.push(x.proj.clone());
+            }
+        }
+        Prefetcher::start(&hooks.projects, to_clone, n, context.use_system_git)
+    });
+    if args.tui && io::stdout().is_terminal() {
+        hooks.dashboard = start_dashboard(total, output);
+    }
+    if args.progress_bar && hooks.dashboard.is_none() {
+        progress::enable(total);
     }
-    let mut fail_writer = get_status_linewriter(&fail_file, start_from).unwrap();
-    let mut pass_writer = get_status_linewriter(&pass_file, start_from).unwrap();
-    let mut failures = 0;
-    for (i, proj) in context.crates.iter().enumerate().skip(start_from) {
-        let proj_name = proj.name().unwrap_or_else(|| "unnamed_project");
-        let res = run_test(i, context, proj, jobs.as_ref(), &projects, &results);
-        let exit_index = if let Err(e) = res {
-            failures += 1;
-            error!("Tarpaulin failed on {}: {:?}", proj_name, e);
-            i
+    heartbeat::start(output, total, start_from);
+    let projects = hooks.projects.clone();
+    if let Err(e) = run_all_with(context, jobs, &projects, &mut hooks) {
+        progress::finish();
+        finish_dashboard(hooks.dashboard.take());
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    let TaterRun {
+        mut summary,
+        mut status_files,
+        events,
+        dashboard,
+        steps,
+        retries,
+        failures,
+        project_timings,
+        progress_file,
+        stopped_at,
+        ..
+    } = hooks;
+    progress::finish();
+    finish_dashboard(dashboard);
+    if let Some(run_name) = stopped_at.as_ref() {
+        // Projects waiting to be retried stay failures, resuming only runs them again if the
+        // retries were paused
+        for retry in retries {
+            summary.record(steps[retry.step].proj.expected, Status::Fail);
+            status_files.failed(&retry.run_name, &retry.label, retry.duration);
+            summary.record_failure(&retry.run_name, retry.label, retry.baseline);
+        }
+        if summary.failed_fast {
+            error!(
+                "Stopping at {} as --fail-fast was given, resume once it's fixed",
+                run_name
+            );
         } else {
-            let _ = pass_writer.write_all(proj_name.as_bytes());
-            let _ = pass_writer.write_all(b"\n");
-            let _ = pass_writer.flush();
-            i + 1
+            events.interrupted(run_name);
+        }
+    } else {
+        if failures > 0 {
+            error!(
+                "Tarpaulin failed on {}/{} projects",
+                failures,
+                context.crates.iter().filter(|x| !x.skip).count()
+            );
+        }
+        let _ = remove_file(&progress_file);
+    }
+    write_run_reports(output, context, results, project_timings, resumed);
+    if args.compare_engines {
+        compare::compare_engines(output, args.coverage_threshold);
+    }
+    summary.log();
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_project;
+    use std::fs;
+
+    #[test]
+    fn resume_from_progress() {
+        let dir = test_project("progress", &[]);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("progress");
+        fs::write(&path, "3\n").unwrap();
+        let legacy = get_progress(&path).unwrap().unwrap();
+        assert_eq!(legacy.next, 3);
+        assert_eq!(legacy.name, None);
+
+        let names = ["a", "b", "c", "d"].map(String::from);
+        let progress = Progress {
+            next: 2,
+            name: Some("c".to_string()),
+            crates_hash: Some("1234".to_string()),
+            order: Some(vec!["c".to_string(), "a".to_string()]),
         };
+        write_progress(&path, &progress);
+        assert_eq!(get_progress(&path).unwrap(), Some(progress.clone()));
+        assert_eq!(resume_point(&legacy, &names, "1234", false), Ok(3));
+        assert_eq!(resume_point(&progress, &names, "1234", false), Ok(2));
 
-        if should_exit(&progress_file, exit_index, &rx) {
-            let _ = fail_writer.write_all(proj_name.as_bytes());
-            let _ = fail_writer.write_all(b"\n");
-            let _ = fail_writer.flush();
-            return;
-        } else if i == exit_index {
-            let _ = fail_writer.write_all(proj_name.as_bytes());
-            let _ = fail_writer.write_all(b"\n");
-            let _ = fail_writer.flush();
+        // A crate was added before the next one
+        let names = ["new", "a", "b", "c", "d"].map(String::from);
+        assert!(resume_point(&progress, &names, "5678", false).is_err());
+        assert_eq!(resume_point(&progress, &names, "5678", true), Ok(3));
+    }
+
+    #[test]
+    fn exit_codes() {
+        let mut summary = RunSummary {
+            expected_pass: 3,
+            expected_fail: 1,
+            ..Default::default()
+        };
+        assert_eq!(summary.exit_code(0), 0);
+        assert_eq!(summary.exit_code(1), EXIT_FAILURES);
+        summary.unexpected_fail = 1;
+        assert_eq!(summary.exit_code(0), EXIT_FAILURES);
+        summary.paused = true;
+        assert_eq!(summary.exit_code(0), EXIT_PAUSED);
+        summary.disk_full = true;
+        assert_eq!(summary.exit_code(0), EXIT_DISK_FULL);
+        summary.failed_fast = true;
+        assert_eq!(summary.exit_code(0), EXIT_FAIL_FAST);
+    }
+
+    #[test]
+    fn default_jobs() {
+        let jobs = Args::from_iter(["tater"]).jobs();
+        assert!(matches!(jobs, Some(j) if j >= 1));
+        assert_eq!(Args::from_iter(["tater", "-j", "3"]).jobs(), Some(3));
+        assert_eq!(Args::from_iter(["tater", "-j", "0"]).jobs(), None);
+    }
+
+    #[test]
+    fn crate_order() {
+        let spec = |name: &str| -> CrateSpec {
+            serde_json::from_value(serde_json::json!({
+                "repository_url": format!("https://github.com/xd009642/{}", name)
+            }))
+            .unwrap()
+        };
+        let crates = ["a", "b", "c", "d"].map(spec);
+        let durations = |name: &str| match name {
+            "b" => Some(10.0),
+            "d" => Some(60.0),
+            _ => None,
+        };
+        let order = order_by_duration(&crates, durations);
+        assert_eq!(order, ["d", "b", "a", "c"]);
+        let names = |crates: Vec<CrateSpec>| {
+            crates
+                .iter()
+                .map(|x| x.name().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(ordered_crates(&crates, &order)), order);
+        // A crate added to the repos file since the order was saved goes at the end
+        let saved = ["d", "b", "removed", "a"].map(String::from);
+        assert_eq!(names(ordered_crates(&crates, &saved)), ["d", "b", "a", "c"]);
+        assert_eq!("Duration".parse(), Ok(CrateOrder::Duration));
+        assert!("shuffle".parse::<CrateOrder>().is_err());
+    }
+
+    #[test]
+    fn time_budget_fitting() {
+        // Crate 1 is ran with both engines
+        let crates = [0, 1, 1, 2, 3];
+        let durations = [Some(100.0), Some(30.0), Some(40.0), None, Some(20.0)];
+        assert_eq!(crate_steps(&crates, 0), 0..1);
+        assert_eq!(crate_steps(&crates, 1), 1..3);
+        assert_eq!(crate_steps(&crates, 2), 2..3);
+        assert_eq!(crate_steps(&crates, 4), 4..5);
+        assert_eq!(steps_duration(&durations[1..3]), Some(70.0));
+        assert_eq!(steps_duration(&durations[2..4]), None);
+
+        assert_eq!(find_fitting(&crates, &durations, 1, 80.0), Some(1..3));
+        // Crates never ran before are passed over
+        assert_eq!(find_fitting(&crates, &durations, 1, 50.0), Some(4..5));
+        assert_eq!(find_fitting(&crates, &durations, 1, 10.0), None);
+        assert_eq!("1h".parse(), Ok(TimeBudget(Duration::from_secs(3600))));
+        assert!("soon".parse::<TimeBudget>().is_err());
+    }
+
+    #[test]
+    fn config_file() {
+        let dir = test_project("config", &[]);
+        let nested = dir.join("a/b");
+        let config_home = dir.join("xdg");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(config_home.join("tater")).unwrap();
+        assert_eq!(find_config(&nested, Some(config_home.clone())), None);
+        let global = config_home.join("tater/config.toml");
+        fs::write(&global, "").unwrap();
+        assert_eq!(
+            find_config(&nested, Some(config_home.clone())),
+            Some(global)
+        );
+        let local = dir.join("a").join(CONFIG_FILE);
+        fs::write(
+            &local,
+            "input = \"crates.json\"\noutput = \"out\"\njobs = 4\nlabels = [\"ci\"]\n\
+             fail_fast = true\nprune_cache_gb = 10\nengine = \"docker\"\ntime_budget = \"5h30m\"\n",
+        )
+        .unwrap();
+        assert_eq!(find_config(&nested, Some(config_home)), Some(local.clone()));
+
+        let cli = [
+            "tater",
+            "-j",
+            "2",
+            "--prune-cache-gb",
+            "5",
+            "--label",
+            "slow",
+            "--target",
+            "wasm32-unknown-unknown",
+        ];
+        let matches = Args::clap().get_matches_from(cli);
+        let mut args = Args::from_clap(&matches);
+        ConfigFile::load(&local).unwrap().apply(&mut args, &matches);
+        // Relative to the config file rather than the current directory
+        assert_eq!(args.repos, dir.join("a/crates.json"));
+        assert_eq!(args.output, dir.join("a/out"));
+        assert_eq!(args.engine, Some(Engine::Docker));
+        assert!(args.fail_fast);
+        assert_eq!(
+            args.time_budget,
+            Some(TimeBudget(Duration::from_secs(5 * 3600 + 30 * 60)))
+        );
+        // Flags on the command line win
+        assert_eq!(args.jobs, Some(2));
+        assert_eq!(args.prune_cache_gb, Some(5));
+        assert_eq!(args.labels, vec!["slow".to_string()]);
+        assert_eq!(args.target.as_deref(), Some("wasm32-unknown-unknown"));
+
+        fs::write(
+            &local,
+            "input = \"https://example.com/repos.json\"\nbaseline = \"/tmp/baseline\"\n",
+        )
+        .unwrap();
+        let config = ConfigFile::load(&local).unwrap();
+        assert_eq!(
+            config.input.as_deref(),
+            Some(Path::new("https://example.com/repos.json"))
+        );
+        assert_eq!(config.baseline.as_deref(), Some(Path::new("/tmp/baseline")));
+
+        fs::write(&local, "output_dir = \"out\"").unwrap();
+        assert!(ConfigFile::load(&local).is_err());
+    }
+
+    #[test]
+    fn target_flag() {
+        let repos = r#"{
+            "toolchain": "",
+            "target": "x86_64-unknown-linux-musl",
+            "crates": [
+                { "repository_url": "https://github.com/foo/bar" },
+                { "repository_url": "https://github.com/foo/baz", "target": "thumbv7em-none-eabi" }
+            ]
+        }"#;
+        let mut context = load_context(repos.as_bytes()).unwrap();
+        let args = Args::from_iter(["tater", "--target", "wasm32-unknown-unknown"]);
+        args.override_context(&mut context);
+        for spec in &context.crates {
+            let mut cmd = std::process::Command::new("cargo");
+            ci::init_command(env::temp_dir(), None, &context, spec, &mut cmd);
+            let cmd_args = cmd.get_args().collect::<Vec<_>>();
+            let target = cmd_args.iter().position(|x| *x == "--target").unwrap();
+            assert_eq!(cmd_args[target + 1], "wasm32-unknown-unknown");
+            assert_eq!(cmd_args.iter().filter(|x| **x == "--target").count(), 1);
         }
     }
-    if failures > 0 {
-        error!(
-            "Tarpaulin failed on {}/{} projects",
-            failures,
-            context.crates.len()
+
+    #[test]
+    fn detected_commands_table() {
+        let rows = vec![
+            DetectedCommand {
+                name: "tater".to_string(),
+                provider: Some(ci::CiProvider::Github),
+                command: vec!["cargo".to_string(), "tarpaulin".to_string()],
+                dir: Some(PathBuf::from("projects/tater")),
+                failures: BTreeMap::new(),
+                error: None,
+            },
+            DetectedCommand {
+                name: "foo".to_string(),
+                provider: Some(ci::CiProvider::Default),
+                command: vec!["cargo".to_string(), "tarpaulin".to_string()],
+                dir: None,
+                failures: [("github", "No such file or directory (os error 2)")]
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                error: None,
+            },
+            DetectedCommand {
+                name: "bar".to_string(),
+                provider: None,
+                command: vec![],
+                dir: None,
+                failures: BTreeMap::new(),
+                error: Some("Issue cloning repo: not found".to_string()),
+            },
+        ];
+        assert_eq!(
+            detected_table(&rows),
+            [
+                "Crate  CI       Command",
+                "tater  github   (cd projects/tater && cargo tarpaulin)",
+                "foo    default  cargo tarpaulin",
+                "bar    error    Issue cloning repo: not found",
+            ]
+        );
+        let json = serde_json::to_value(&rows[1]).unwrap();
+        assert_eq!(json["provider"], "default");
+        assert_eq!(
+            json["failures"]["github"],
+            "No such file or directory (os error 2)"
         );
     }
 }
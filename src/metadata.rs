@@ -0,0 +1,151 @@
+use crate::runner::new_command;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// The parts of `cargo metadata --no-deps` needed to tell if a repo is a virtual workspace
+#[derive(Debug, Clone, Deserialize)]
+pub struct Metadata {
+    pub packages: Vec<Package>,
+    pub workspace_root: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Package {
+    pub name: String,
+    pub manifest_path: PathBuf,
+}
+
+impl Metadata {
+    /// Runs `cargo metadata` in the directory, without dependencies so nothing is downloaded
+    pub fn read(dir: &Path) -> Result<Self, String> {
+        let output = new_command("cargo")
+            .args(["metadata", "--no-deps", "--format-version", "1"])
+            .current_dir(dir)
+            .output()
+            .map_err(|e| format!("Failed to run cargo metadata: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "cargo metadata failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        serde_json::from_slice(&output.stdout)
+            .map_err(|e| format!("Invalid cargo metadata output: {}", e))
+    }
+
+    /// Whether there's a package at the root of the workspace, if not it's a virtual workspace
+    pub fn has_root_package(&self) -> bool {
+        let root_manifest = self.workspace_root.join("Cargo.toml");
+        self.packages
+            .iter()
+            .any(|x| x.manifest_path == root_manifest)
+    }
+
+    /// Args to pick what tarpaulin should test in a workspace. In a virtual workspace the package
+    /// named after the crate if there is one, otherwise the whole workspace
+    pub fn package_args(&self, crate_name: &str) -> Vec<String> {
+        if self.has_root_package() {
+            return vec!["--workspace".to_string()];
+        }
+        let normalise = |name: &str| name.to_ascii_lowercase().replace('_', "-");
+        match self
+            .packages
+            .iter()
+            .find(|x| normalise(&x.name) == normalise(crate_name))
+        {
+            Some(package) => vec!["--packages".to_string(), package.name.clone()],
+            None => vec!["--workspace".to_string()],
+        }
+    }
+}
+
+/// Whether the `Cargo.toml` in `dir` declares a workspace. The manifest is read directly so it's
+/// cheap enough to check for every project
+pub fn is_workspace(dir: &Path) -> bool {
+    let manifest = match std::fs::read_to_string(dir.join("Cargo.toml")) {
+        Ok(manifest) => manifest,
+        Err(_) => return false,
+    };
+    match manifest.parse::<toml::Value>() {
+        Ok(manifest) => manifest.get("workspace").is_some(),
+        Err(e) => {
+            warn!("Invalid Cargo.toml in {}: {}", dir.display(), e);
+            false
+        }
+    }
+}
+
+/// Args to add to the tarpaulin command ran in `dir` so it tests the packages of a workspace
+/// rather than just the root package, see [`Metadata::package_args`]. Nothing is added if it
+/// isn't a workspace, if cargo metadata can't be read the whole workspace is tested
+pub fn workspace_args(dir: &Path, crate_name: &str) -> Vec<String> {
+    if !is_workspace(dir) {
+        return vec![];
+    }
+    let args = Metadata::read(dir)
+        .map(|x| x.package_args(crate_name))
+        .unwrap_or_else(|e| {
+            warn!("Couldn't read the workspace packages: {}", e);
+            vec!["--workspace".to_string()]
+        });
+    info!("Workspace found, adding {:?}", args);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(json: &str) -> Metadata {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn workspace_packages() {
+        let virtual_workspace = metadata(
+            r#"{
+                "packages": [
+                    {
+                        "name": "foo-core",
+                        "version": "0.1.0",
+                        "id": "foo-core 0.1.0 (path+file:///projects/foo/core)",
+                        "manifest_path": "/projects/foo/core/Cargo.toml"
+                    },
+                    {
+                        "name": "foo-cli",
+                        "version": "0.1.0",
+                        "id": "foo-cli 0.1.0 (path+file:///projects/foo/cli)",
+                        "manifest_path": "/projects/foo/cli/Cargo.toml"
+                    }
+                ],
+                "workspace_members": [
+                    "foo-core 0.1.0 (path+file:///projects/foo/core)",
+                    "foo-cli 0.1.0 (path+file:///projects/foo/cli)"
+                ],
+                "resolve": null,
+                "target_directory": "/projects/foo/target",
+                "version": 1,
+                "workspace_root": "/projects/foo"
+            }"#,
+        );
+        assert!(!virtual_workspace.has_root_package());
+        assert_eq!(virtual_workspace.package_args("foo"), ["--workspace"]);
+        assert_eq!(
+            virtual_workspace.package_args("foo_core"),
+            ["--packages", "foo-core"]
+        );
+
+        let root_package = metadata(
+            r#"{
+                "packages": [
+                    { "name": "foo", "manifest_path": "/projects/foo/Cargo.toml" },
+                    { "name": "foo-derive", "manifest_path": "/projects/foo/derive/Cargo.toml" }
+                ],
+                "workspace_root": "/projects/foo"
+            }"#,
+        );
+        assert!(root_package.has_root_package());
+        assert_eq!(root_package.package_args("foo"), ["--workspace"]);
+    }
+}
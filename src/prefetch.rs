@@ -0,0 +1,189 @@
+use crate::git::prepare_project;
+use crate::runner::CrateSpec;
+use std::collections::HashSet;
+use std::mem;
+use std::path::Path;
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use tracing::{info, info_span, warn};
+
+/// What the workers have got through, waiters are woken whenever it changes
+#[derive(Default)]
+struct Progress {
+    /// Projects where the clone has been attempted
+    attempted: HashSet<String>,
+    /// Projects where the clone or update worked
+    prepared: HashSet<String>,
+    /// Workers still taking clones, once there's none nothing else will be attempted
+    workers: usize,
+}
+
+#[derive(Default)]
+struct Shared {
+    progress: Mutex<Progress>,
+    changed: Condvar,
+}
+
+impl Shared {
+    /// A worker panicking mid-update doesn't leave the progress unusable
+    fn progress(&self) -> MutexGuard<'_, Progress> {
+        self.progress.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Marks the clone as attempted when dropped, so waiters are woken even if the clone panics
+struct Attempt<'a> {
+    shared: &'a Shared,
+    name: String,
+    prepared: bool,
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        let name = mem::take(&mut self.name);
+        let mut progress = self.shared.progress();
+        if self.prepared {
+            progress.prepared.insert(name.clone());
+        }
+        progress.attempted.insert(name);
+        drop(progress);
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Counts the worker as stopped when dropped, however it stops
+struct Worker(Arc<Shared>);
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.0.progress().workers -= 1;
+        self.0.changed.notify_all();
+    }
+}
+
+/// Clones projects in background threads ahead of them being tested so the test phase doesn't
+/// have to wait on git
+pub struct Prefetcher {
+    /// Projects we're going to clone
+    scheduled: HashSet<String>,
+    shared: Arc<Shared>,
+}
+
+impl Prefetcher {
+    /// Starts cloning the crates in order using `workers` threads, with the git binary if
+    /// `system_git` is set
+    pub fn start(
+        projects: &Path,
+        crates: Vec<CrateSpec>,
+        workers: usize,
+        system_git: bool,
+    ) -> Self {
+        info!(
+            "Prefetching {} projects with {} workers",
+            crates.len(),
+            workers
+        );
+        let jobs = crates
+            .into_iter()
+            .filter_map(|x| {
+                x.name()
+                    .map(|name| (name.to_string(), x.repository_url.to_string()))
+            })
+            .collect::<Vec<_>>();
+        let scheduled = jobs.iter().map(|(name, _)| name.clone()).collect();
+
+        // Bounded so we only queue up as many clones as there are workers to take them
+        let (tx, rx) = mpsc::sync_channel::<(String, String)>(workers);
+        thread::spawn(move || {
+            for job in jobs {
+                if tx.send(job).is_err() {
+                    break;
+                }
+            }
+        });
+        let shared = Arc::new(Shared::default());
+        shared.progress().workers = workers;
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..workers {
+            let rx = Arc::clone(&rx);
+            let worker = Worker(Arc::clone(&shared));
+            let projects = projects.to_path_buf();
+            thread::spawn(move || loop {
+                let job = rx.lock().unwrap_or_else(PoisonError::into_inner).recv();
+                let (name, url) = match job {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                let mut attempt = Attempt {
+                    shared: &worker.0,
+                    name: name.clone(),
+                    prepared: false,
+                };
+                // Spans don't cross threads so the clone gets its own
+                let span = info_span!("prefetch", name = name.as_str());
+                let _enter = span.enter();
+                match prepare_project(&projects, &url, &name, system_git) {
+                    Ok(_) => attempt.prepared = true,
+                    Err(e) => warn!("Prefetch failed: {}", e),
+                }
+            });
+        }
+        Self { scheduled, shared }
+    }
+
+    /// Blocks until the clone for the project has been attempted, returning whether it's ready
+    /// to run without cloning it again. Returns immediately if the project wasn't going to be
+    /// prefetched, or once there are no workers left to clone it
+    pub fn wait_for(&self, name: &str) -> bool {
+        if !self.scheduled.contains(name) {
+            return false;
+        }
+        let mut progress = self.shared.progress();
+        while !progress.attempted.contains(name) && progress.workers > 0 {
+            progress = self
+                .shared
+                .changed
+                .wait(progress)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        progress.prepared.contains(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_project;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn waits_for_clones() {
+        let root = test_project("prefetch", &[]);
+        let upstream = root.join("upstream/baz");
+        std::fs::create_dir_all(&upstream).unwrap();
+        // Only baz exists upstream so the other clones fail
+        let status = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(
+                "git init -q && git -c user.name=tater -c user.email=tater@example.com \
+                 commit -q --allow-empty -m first",
+            )
+            .current_dir(&upstream)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let spec = |name: &str| {
+            let url = format!("file://{}", root.join("upstream").join(name).display());
+            serde_json::from_value::<CrateSpec>(serde_json::json!({ "repository_url": url }))
+                .unwrap()
+        };
+        let prefetcher =
+            Prefetcher::start(&root, vec![spec("foo"), spec("bar"), spec("baz")], 1, false);
+        let start = Instant::now();
+        assert!(!prefetcher.wait_for("bar"));
+        assert!(!prefetcher.wait_for("foo"));
+        assert!(prefetcher.wait_for("baz"));
+        assert!(!prefetcher.wait_for("not-scheduled"));
+        assert!(start.elapsed() < Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,68 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use lazy_static::lazy_static;
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+
+lazy_static! {
+    /// Hidden unless enabled, so the logging can always go through it
+    static ref BAR: ProgressBar = ProgressBar::hidden();
+    /// Logs go here instead of stdout while something else is using the terminal
+    static ref LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+}
+
+/// Writes logs to the file instead of stdout, `None` goes back to stdout
+pub fn redirect_logs(file: Option<File>) {
+    *LOG_FILE.lock().unwrap() = file;
+}
+
+/// Shows the progress bar if stdout is a terminal
+pub fn enable(len: usize) {
+    if !io::stdout().is_terminal() {
+        return;
+    }
+    BAR.set_style(
+        ProgressStyle::with_template("[{elapsed}] {pos}/{len} crates | {msg}")
+            .expect("Invalid progress bar template"),
+    );
+    BAR.set_length(len as u64);
+    BAR.set_draw_target(ProgressDrawTarget::stdout());
+}
+
+/// Updates the bar for starting the crate at `index` in the repos file
+pub fn start_crate(index: usize, name: &str) {
+    BAR.set_position(index as u64);
+    BAR.set_message(name.to_string());
+}
+
+pub fn finish() {
+    BAR.finish_and_clear();
+}
+
+/// Writes log lines to stdout above the progress bar, each line is buffered until the writer is
+/// dropped so the bar can be cleared and redrawn around it
+#[derive(Default)]
+pub struct LogWriter(Vec<u8>);
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+            let _ = file.write_all(&self.0);
+            return;
+        }
+        BAR.suspend(|| {
+            let _ = io::stdout().write_all(&self.0);
+        });
+    }
+}
@@ -0,0 +1,1105 @@
+use crate::runner::*;
+use crate::sccache::SccacheStats;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{
+    create_dir, create_dir_all, read_dir, read_to_string, remove_dir, remove_file, rename, File,
+};
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use structopt::StructOpt;
+use tracing::{error, info, warn};
+
+#[derive(Debug, Clone, PartialEq, StructOpt)]
+pub struct ReportArgs {
+    /// Where to write the HTML report, defaults to `report.html` in the output directory
+    #[structopt(long = "output-html")]
+    pub output_html: Option<PathBuf>,
+    /// Report format, html or json. JSON is printed to stdout
+    #[structopt(long = "format", default_value = "html")]
+    pub format: ReportFormat,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ReportFormat {
+    Html,
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "html" => Ok(Self::Html),
+            "json" => Ok(Self::Json),
+            e => Err(format!("Unsupported format: {}", e)),
+        }
+    }
+}
+
+/// A `RunError` in a form that can be stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorInfo {
+    pub kind: String,
+    /// Why tarpaulin failed if it ran to completion
+    #[serde(default)]
+    pub failure: Option<FailureKind>,
+    pub message: String,
+}
+
+impl From<&RunError> for ErrorInfo {
+    fn from(e: &RunError) -> Self {
+        Self {
+            kind: e.kind().to_string(),
+            failure: match e {
+                RunError::Failed(kind) => Some(*kind),
+                _ => None,
+            },
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Name of the symlink in the results directory pointing at the newest run
+pub const LATEST_RUN: &str = "latest";
+
+/// Directory in a run's directory holding the results for each crate, kept apart from the status
+/// files so a crate can have any name
+pub const CRATES_DIR: &str = "crates";
+
+/// Where the files for a run go. Each run gets a directory in `results` named by when it started
+/// holding everything with the project results in `crates`, with the flat layout the project
+/// results are in `results` and the rest is in the output directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunDirs {
+    /// Holds the pass, fail, paused and progress files
+    pub status: PathBuf,
+    /// Holds a directory of results for each project
+    pub results: PathBuf,
+}
+
+impl RunDirs {
+    pub fn flat(output: &Path) -> Self {
+        Self {
+            status: output.to_path_buf(),
+            results: output.join("results"),
+        }
+    }
+
+    fn run(dir: PathBuf) -> Self {
+        Self {
+            results: dir.join(CRATES_DIR),
+            status: dir,
+        }
+    }
+
+    /// The newest run in the output directory if there is one
+    pub fn latest_run(output: &Path) -> Option<Self> {
+        let results = output.join("results");
+        let latest = results.join(LATEST_RUN);
+        // Without symlinks `latest` is a file with the run's name in
+        let dir = if latest.is_file() {
+            results.join(read_to_string(&latest).ok()?.trim())
+        } else {
+            latest
+        };
+        dir.is_dir().then(|| Self::run(dir))
+    }
+
+    /// The newest run in the output directory, falling back to the flat layout for output
+    /// directories from before runs had their own directories
+    pub fn latest(output: &Path) -> Self {
+        Self::latest_run(output).unwrap_or_else(|| Self::flat(output))
+    }
+
+    /// Creates the directory for a new run and points the latest symlink at it. Runs started in
+    /// the same second get a numbered suffix
+    pub fn start(output: &Path) -> io::Result<Self> {
+        let results = output.join("results");
+        create_dir_all(&results)?;
+        let started = run_name(SystemTime::now());
+        let mut name = started.clone();
+        let mut n = 1;
+        loop {
+            match create_dir(results.join(&name)) {
+                Ok(()) => break,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    n += 1;
+                    name = format!("{}-{}", started, n);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let run = Self::run(results.join(&name));
+        create_dir(&run.results)?;
+        let latest = results.join(LATEST_RUN);
+        if latest.symlink_metadata().is_ok() {
+            // A directory symlink on windows is removed as a directory
+            remove_file(&latest).or_else(|_| remove_dir(&latest))?;
+        }
+        #[cfg(unix)]
+        let linked = std::os::unix::fs::symlink(&name, &latest);
+        #[cfg(windows)]
+        let linked = std::os::windows::fs::symlink_dir(&name, &latest);
+        #[cfg(not(any(unix, windows)))]
+        let linked: io::Result<()> = Err(io::ErrorKind::Unsupported.into());
+        if let Err(e) = linked {
+            // Windows only allows symlinks in developer mode or as an administrator
+            warn!("Unable to link {}: {}", latest.display(), e);
+            std::fs::write(&latest, &name)?;
+        }
+        Ok(run)
+    }
+
+    /// Written when a run is paused with the index to resume from
+    pub fn progress_file(&self) -> PathBuf {
+        self.status.join("progress")
+    }
+
+    /// Whether this is a paused run that can be resumed
+    pub fn resumable(&self) -> bool {
+        self.progress_file().is_file()
+    }
+}
+
+/// Name of the directory for a run started at the time. This is the RFC 3339 timestamp with `-`
+/// instead of `:` as windows doesn't allow `:` in paths
+fn run_name(time: SystemTime) -> String {
+    rfc3339(time).replace(':', "-")
+}
+
+/// Formats the time as a RFC 3339 UTC timestamp to the second
+pub fn rfc3339(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Days since the epoch to a civil date, from Howard Hinnant's date algorithms
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Formats a duration like `1h5m`, `4m12s` or `30s`
+pub fn human_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h{}m", secs / 3600, secs / 60 % 60)
+    } else if secs >= 60 {
+        format!("{}m{}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Parses a duration like `5h30m`, `90m` or `45s`, days are `d`. A number on its own is seconds
+pub fn parse_human_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+    let mut secs = 0u64;
+    let mut number = String::new();
+    for c in s.chars().filter(|x| !x.is_whitespace()) {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'd' => 24 * 3600,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            c => return Err(format!("Invalid duration {}: unknown unit '{}'", s, c)),
+        };
+        let value = number
+            .parse::<u64>()
+            .map_err(|_| format!("Invalid duration {}: no number before '{}'", s, c))?;
+        secs = value
+            .checked_mul(unit)
+            .and_then(|x| secs.checked_add(x))
+            .ok_or_else(|| format!("Invalid duration {}: too long", s))?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(format!("Invalid duration {}: {} has no unit", s, number));
+    }
+    if s.is_empty() {
+        return Err("Empty duration".to_string());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// A line in the pass, fail or skipped file
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusEntry {
+    pub name: String,
+    /// `pass` or `skipped`, or the label for why the project failed
+    pub status: String,
+    /// When the entry was written, missing from files written by older versions of tater
+    pub time: Option<String>,
+    /// How long running the project took in seconds
+    pub duration: Option<f64>,
+}
+
+impl StatusEntry {
+    /// Parses a `name<TAB>status<TAB>time<TAB>duration` line. Older versions of tater wrote just
+    /// the name, with the failure label after a tab in the fail file, so missing columns are
+    /// allowed and the status defaults to the one for the file
+    pub fn parse(line: &str, default_status: &str) -> Option<Self> {
+        let mut columns = line.split('\t').map(str::trim);
+        let name = columns.next().filter(|x| !x.is_empty())?.to_string();
+        let mut next = || columns.next().filter(|x| !x.is_empty());
+        Some(Self {
+            name,
+            status: next().unwrap_or(default_status).to_string(),
+            time: next().map(String::from),
+            duration: next().and_then(|x| x.parse().ok()),
+        })
+    }
+
+    fn line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}",
+            self.name,
+            self.status,
+            self.time.as_deref().unwrap_or_default(),
+            self.duration
+                .map(|x| format!("{:.1}", x))
+                .unwrap_or_default()
+        )
+    }
+}
+
+/// Reads the entries of a pass, fail or skipped file, a missing file has none
+pub fn read_status_file(path: &Path, default_status: &str) -> Vec<StatusEntry> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|x| StatusEntry::parse(x, default_status))
+        .collect()
+}
+
+const STATUS_FILES: [(&str, &str); 3] =
+    [("pass", "pass"), ("fail", "fail"), ("skipped", "skipped")];
+
+/// The `pass`, `fail`, `skipped` and `paused` files for a run, every project ran ends up in
+/// exactly one of them. A project written again, e.g. when ran again after resuming, replaces its
+/// earlier entry wherever it was
+pub struct StatusFiles {
+    dir: PathBuf,
+    /// Entries of the pass, fail and skipped files in the order of `STATUS_FILES`
+    entries: [Vec<StatusEntry>; 3],
+    paused: BufWriter<File>,
+}
+
+impl StatusFiles {
+    /// Opens the files in the directory, the existing pass, fail and skipped entries are kept so
+    /// a resumed run carries on from where it was paused. The paused file is always cleared as the
+    /// paused project is ran again on resume
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let mut files = Self {
+            dir: dir.to_path_buf(),
+            entries: Default::default(),
+            paused: BufWriter::new(File::create(dir.join("paused"))?),
+        };
+        for (i, (name, default_status)) in STATUS_FILES.iter().enumerate() {
+            for entry in read_status_file(&dir.join(name), default_status) {
+                files.insert(i, entry);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Removes the pass, fail and skipped files so a new run can reuse the directory
+    pub fn clear(dir: &Path) {
+        for (name, _) in &STATUS_FILES {
+            let _ = remove_file(dir.join(name));
+        }
+    }
+
+    /// Adds the entry to a file removing any older entry for the project, returns which files
+    /// changed. If there's already a newer entry the new one is dropped
+    fn insert(&mut self, file: usize, entry: StatusEntry) -> Vec<usize> {
+        let mut changed = vec![];
+        for (i, entries) in self.entries.iter_mut().enumerate() {
+            if let Some(pos) = entries.iter().position(|x| x.name == entry.name) {
+                if entries[pos].time > entry.time {
+                    return changed;
+                }
+                entries.remove(pos);
+                changed.push(i);
+            }
+        }
+        self.entries[file].push(entry);
+        if !changed.contains(&file) {
+            changed.push(file);
+        }
+        changed
+    }
+
+    fn write(&mut self, file: usize, name: &str, status: &str, duration: f64) {
+        let entry = StatusEntry {
+            name: name.to_string(),
+            status: status.to_string(),
+            time: Some(rfc3339(SystemTime::now())),
+            duration: Some(duration),
+        };
+        for i in self.insert(file, entry) {
+            let path = self.dir.join(STATUS_FILES[i].0);
+            // Written in full then moved into place so anything reading the file mid-run, such as
+            // `tater status`, never sees it empty or half written
+            let temp = self.dir.join(format!(".{}.tmp", STATUS_FILES[i].0));
+            let res = File::create(&temp)
+                .and_then(|f| {
+                    let mut writer = BufWriter::new(f);
+                    for entry in &self.entries[i] {
+                        writeln!(writer, "{}", entry.line())?;
+                    }
+                    writer.flush()
+                })
+                .and_then(|_| rename(&temp, &path));
+            if let Err(e) = res {
+                error!("Failed to write {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    pub fn passed(&mut self, name: &str, duration: f64) {
+        self.write(0, name, "pass", duration);
+    }
+
+    /// Writes the project with the label for why it failed as its status
+    pub fn failed(&mut self, name: &str, label: &str, duration: f64) {
+        self.write(1, name, label, duration);
+    }
+
+    /// A project which ran but has no tests so neither passed nor failed
+    pub fn skipped(&mut self, name: &str, duration: f64) {
+        self.write(2, name, "skipped", duration);
+    }
+
+    /// A project which failed when the run was paused so will be ran again when resuming
+    pub fn paused(&mut self, name: &str) {
+        let _ = writeln!(self.paused, "{}", name);
+        let _ = self.paused.flush();
+    }
+}
+
+pub const METADATA_FILE: &str = "metadata.json";
+
+/// Information about the run as a whole, written to the output directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunMetadata {
+    pub toolchain: String,
+    /// The tarpaulin binary if one was given instead of `cargo tarpaulin`
+    #[serde(default)]
+    pub tarpaulin: Option<TarpaulinBinary>,
+}
+
+pub fn write_metadata(path: &Path, metadata: &RunMetadata) {
+    let write = || -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, metadata)?;
+        Ok(())
+    };
+    if let Err(e) = write() {
+        error!("Failed to write {}: {}", path.display(), e);
+    }
+}
+
+/// Result file for a project
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectResult {
+    #[serde(default)]
+    pub name: String,
+    pub status: Status,
+    #[serde(default)]
+    pub error: Option<ErrorInfo>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// The target the project was built for if not the host
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Program and arguments tarpaulin was spawned with
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Environment variables set for tarpaulin
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub timings: Timings,
+    /// Commit of the project that was tested
+    #[serde(default)]
+    pub commit: Option<String>,
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// Signal that killed tarpaulin, only known on unix
+    #[serde(default)]
+    pub signal: Option<i32>,
+    /// Whether tarpaulin's debug log was found
+    #[serde(default)]
+    pub found_log: bool,
+    /// `None` if tarpaulin never finished
+    #[serde(default)]
+    pub coverage: Option<CoverageReport>,
+    /// Lines covered according to tarpaulin's JSON report
+    #[serde(default)]
+    pub report_coverage: Option<Coverage>,
+    /// Source files in tarpaulin's JSON report relative to the project
+    #[serde(default)]
+    pub report_files: Option<Vec<String>>,
+    /// sccache's hits and misses while tarpaulin built the project
+    #[serde(default)]
+    pub sccache: Option<SccacheStats>,
+    /// Set if tarpaulin itself panicked
+    #[serde(default)]
+    pub panic: Option<PanicInfo>,
+    /// The teardown failing doesn't fail the project but may cause later projects to fail
+    #[serde(default)]
+    pub teardown_error: Option<ErrorInfo>,
+    /// Whether `cargo test` also fails if it was ran after tarpaulin failed
+    #[serde(default)]
+    pub baseline: Option<BaselineResult>,
+    /// Why the project was skipped if it was
+    #[serde(default)]
+    pub skip_reason: Option<SkipReason>,
+}
+
+impl ProjectResult {
+    pub fn new(context: &Context, spec: &CrateSpec, outcome: &RunOutcome) -> Self {
+        Self {
+            name: spec.name().unwrap_or("unnamed_project").to_string(),
+            status: outcome.status(),
+            error: outcome.error.as_ref().map(ErrorInfo::from),
+            labels: spec.labels.clone(),
+            target: context.target_for(spec).map(String::from),
+            command: outcome.command.clone(),
+            env: outcome.env.clone(),
+            timings: outcome.timings.clone(),
+            commit: outcome.commit.clone(),
+            exit_code: outcome.exit_code,
+            signal: outcome.signal,
+            found_log: outcome.found_log,
+            coverage: outcome.coverage.clone(),
+            report_coverage: outcome.report_coverage.clone(),
+            report_files: outcome.report_files.clone(),
+            sccache: outcome.sccache,
+            panic: outcome.panic.clone(),
+            teardown_error: outcome.teardown_error.as_ref().map(ErrorInfo::from),
+            baseline: outcome.baseline,
+            skip_reason: outcome.skip_reason(),
+        }
+    }
+
+    /// Result for a project which wasn't ran
+    pub fn skipped(context: &Context, spec: &CrateSpec) -> Self {
+        let mut result = Self::new(context, spec, &RunOutcome::default());
+        result.status = Status::Skipped;
+        result
+    }
+}
+
+/// Report of the results for every project in the run
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Report {
+    pub projects: Vec<ProjectResult>,
+}
+
+/// Name of the result file in a project's results directory
+pub const RESULT_FILE: &str = "result.json";
+
+pub fn result_file(results: &Path, name: &str) -> PathBuf {
+    results.join(name).join(RESULT_FILE)
+}
+
+pub fn read_result(result_file: &Path) -> Option<ProjectResult> {
+    File::open(result_file)
+        .ok()
+        .and_then(|f| serde_json::from_reader(BufReader::new(f)).ok())
+}
+
+pub fn previously_passed(result_file: &Path) -> bool {
+    read_result(result_file)
+        .map(|x| x.status == Status::Pass)
+        .unwrap_or(false)
+}
+
+pub fn write_result(result_file: &Path, result: &ProjectResult) {
+    if let Some(parent) = result_file.parent() {
+        let _ = create_dir_all(parent);
+    }
+    match File::create(result_file) {
+        Ok(f) => {
+            if let Err(e) = serde_json::to_writer_pretty(f, result) {
+                warn!("Failed to write {}: {}", result_file.display(), e);
+            }
+        }
+        Err(e) => warn!("Failed to create {}: {}", result_file.display(), e),
+    }
+}
+
+const TIMINGS_HEADER: &str = "name,total,clone,fetch,setup,tarpaulin,teardown";
+
+fn read_timings(path: &Path) -> io::Result<Vec<(String, Timings)>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut rows = vec![];
+    for line in reader.lines().skip(1) {
+        let line = line?;
+        let mut parts = line.split(',');
+        let name = match parts.next() {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => continue,
+        };
+        let values = parts
+            .map(|x| x.parse().unwrap_or(0.0))
+            .collect::<Vec<f64>>();
+        if values.len() != 6 {
+            warn!("Skipping invalid timings row: {}", line);
+            continue;
+        }
+        let timings = Timings {
+            total: values[0],
+            clone: values[1],
+            fetch: values[2],
+            setup: values[3],
+            tarpaulin: values[4],
+            teardown: values[5],
+        };
+        rows.push((name, timings));
+    }
+    Ok(rows)
+}
+
+/// Writes out the timings sorted by total duration. When resuming the existing rows are kept and
+/// only replaced for projects ran again
+pub fn write_timings(path: &Path, mut rows: Vec<(String, Timings)>, resumed: bool) {
+    if resumed {
+        match read_timings(path) {
+            Ok(existing) => {
+                let mut existing = existing
+                    .into_iter()
+                    .filter(|(name, _)| !rows.iter().any(|(x, _)| x == name))
+                    .collect::<Vec<_>>();
+                rows.append(&mut existing);
+            }
+            Err(e) => warn!("Unable to read existing timings: {}", e),
+        }
+    }
+    rows.sort_by(|a, b| b.1.total.total_cmp(&a.1.total));
+
+    let write = || -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", TIMINGS_HEADER)?;
+        for (name, t) in &rows {
+            writeln!(
+                writer,
+                "{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}",
+                name, t.total, t.clone, t.fetch, t.setup, t.tarpaulin, t.teardown
+            )?;
+        }
+        writer.flush()
+    };
+    if let Err(e) = write() {
+        error!("Failed to write timings: {}", e);
+    }
+    if !rows.is_empty() {
+        info!("Slowest projects:");
+        for (name, t) in rows.iter().take(5) {
+            info!(
+                "  {}: {:.1}s (clone {:.1}s, fetch {:.1}s, setup {:.1}s, tarpaulin {:.1}s, teardown {:.1}s)",
+                name, t.total, t.clone, t.fetch, t.setup, t.tarpaulin, t.teardown
+            );
+        }
+    }
+}
+
+/// Writes the coverage for every project in the context that has a result, the percentage is left
+/// empty when tarpaulin didn't report any coverage
+pub fn write_coverage(path: &Path, context: &Context, results: &Path) {
+    let write = || -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "name,percentage,covered,coverable")?;
+        for result in context_results(context, results) {
+            match result.coverage {
+                Some(CoverageReport::Found(c)) => writeln!(
+                    writer,
+                    "{},{:.2},{},{}",
+                    result.name, c.percentage, c.covered, c.coverable
+                )?,
+                _ => writeln!(writer, "{},,,", result.name)?,
+            }
+        }
+        writer.flush()
+    };
+    if let Err(e) = write() {
+        error!("Failed to write coverage: {}", e);
+    }
+}
+
+/// The crate's results named after the run. A run comparing engines has a result for each engine
+/// named `crate/engine`, like the status files
+pub fn crate_results(results: &Path, name: &str) -> Vec<ProjectResult> {
+    if let Some(mut result) = read_result(&result_file(results, name)) {
+        result.name = name.to_string();
+        return vec![result];
+    }
+    [CoverageEngine::Ptrace, CoverageEngine::Llvm]
+        .iter()
+        .filter_map(|engine| {
+            let path = results
+                .join(name)
+                .join(engine.to_string())
+                .join(RESULT_FILE);
+            read_result(&path).map(|mut result| {
+                result.name = format!("{}/{}", name, engine);
+                result
+            })
+        })
+        .collect()
+}
+
+/// Results of the projects in the context that have been ran, in the repos file order
+fn context_results(context: &Context, results: &Path) -> Vec<ProjectResult> {
+    context
+        .crates
+        .iter()
+        .filter_map(|x| x.name())
+        .flat_map(|name| crate_results(results, name))
+        .collect()
+}
+
+/// Reads every result in a results directory sorted by name, for when there's no repos file
+pub fn read_all_results(results: &Path) -> io::Result<Vec<ProjectResult>> {
+    let mut projects = vec![];
+    for entry in read_dir(results)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        projects.extend(crate_results(results, &name));
+    }
+    projects.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(projects)
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writes the results as a HTML page with a table of the projects
+pub fn write_html(mut writer: impl Write, projects: &[ProjectResult]) -> io::Result<()> {
+    let passed = projects.iter().filter(|x| x.status == Status::Pass).count();
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html>\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>Tater report</title>\n</head>\n<body>")?;
+    writeln!(
+        writer,
+        "<h1>Tater report</h1>\n<p>{} of {} projects passed</p>",
+        passed,
+        projects.len()
+    )?;
+    writeln!(writer, "<table>")?;
+    writeln!(
+        writer,
+        "<tr><th>Crate</th><th>Status</th><th>Coverage</th><th>Commit</th><th>Error</th></tr>"
+    )?;
+    for project in projects {
+        let coverage = match &project.coverage {
+            Some(CoverageReport::Found(c)) => format!("{:.2}%", c.percentage),
+            _ => String::new(),
+        };
+        writeln!(
+            writer,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&project.name),
+            project.status,
+            coverage,
+            escape_html(project.commit.as_deref().unwrap_or_default()),
+            escape_html(
+                project
+                    .error
+                    .as_ref()
+                    .map(|x| x.message.as_str())
+                    .unwrap_or_default()
+            )
+        )?;
+    }
+    writeln!(writer, "</table>\n</body>\n</html>")
+}
+
+/// Writes the HTML report for the projects
+pub fn generate_report(path: &Path, projects: &[ProjectResult]) {
+    let write = || -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_html(&mut writer, projects)?;
+        writer.flush()
+    };
+    if let Err(e) = write() {
+        error!("Failed to write {}: {}", path.display(), e);
+    }
+}
+
+/// Regenerates the report from the results in the output directory without running anything
+pub fn regenerate_report(output: &Path, args: &ReportArgs) -> Result<(), String> {
+    let results = RunDirs::latest(output).results;
+    let projects = read_all_results(&results)
+        .map_err(|e| format!("Unable to read results in {}: {}", results.display(), e))?;
+    match args.format {
+        ReportFormat::Html => {
+            let path = args
+                .output_html
+                .clone()
+                .unwrap_or_else(|| output.join("report.html"));
+            generate_report(&path, &projects);
+            info!(
+                "Wrote report for {} projects to {}",
+                projects.len(),
+                path.display()
+            );
+        }
+        ReportFormat::Json => {
+            let stdout = io::stdout();
+            let mut writer = stdout.lock();
+            serde_json::to_writer_pretty(&mut writer, &Report { projects })
+                .map_err(io::Error::from)
+                .and_then(|_| writeln!(writer))
+                .map_err(|e| format!("Failed to write report: {}", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Gathers the results of every project in the context that has been ran into a single report
+pub fn write_report(path: &Path, context: &Context, results: &Path) {
+    let projects = context_results(context, results);
+    let write = || -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, &Report { projects })?;
+        Ok(())
+    };
+    if let Err(e) = write() {
+        error!("Failed to write report: {}", e);
+    }
+}
+
+/// Projects which hit the same tarpaulin panic
+struct PanicGroup {
+    crates: Vec<String>,
+    /// Backtrace from the first project to hit the panic
+    frames: Vec<String>,
+}
+
+/// Writes the tarpaulin panics from every project grouped by where they happened, the most
+/// common panics first
+pub fn write_panics(path: &Path, context: &Context, results: &Path) {
+    let mut groups = BTreeMap::new();
+    for result in context_results(context, results) {
+        if let Some(panic) = result.panic {
+            let PanicInfo {
+                message,
+                location,
+                frames,
+            } = panic;
+            let group = groups
+                .entry((message, location))
+                .or_insert_with(|| PanicGroup {
+                    crates: vec![],
+                    frames,
+                });
+            group.crates.push(result.name);
+        }
+    }
+    let mut groups = groups.into_iter().collect::<Vec<_>>();
+    groups.sort_by_key(|(_, group)| Reverse(group.crates.len()));
+    let write = || -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for ((message, location), PanicGroup { crates, frames }) in &groups {
+            writeln!(
+                writer,
+                "{} crate(s) panicked with: {}",
+                crates.len(),
+                message
+            )?;
+            if let Some(location) = location {
+                writeln!(writer, "  at {}", location)?;
+            }
+            writeln!(writer, "  crates: {}", crates.join(", "))?;
+            for (i, frame) in frames.iter().enumerate() {
+                writeln!(writer, "  {:>3}: {}", i, frame)?;
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()
+    };
+    if groups.is_empty() {
+        let _ = remove_file(path);
+    } else if let Err(e) = write() {
+        error!("Failed to write panics: {}", e);
+    } else {
+        warn!("Tarpaulin panicked in {} different places", groups.len());
+    }
+}
+
+/// Writes all the reports covering the whole run to the output directory
+pub fn write_run_reports(
+    output: &Path,
+    context: &Context,
+    results: &Path,
+    timings: Vec<(String, Timings)>,
+    resumed: bool,
+) {
+    write_timings(&output.join("timings.csv"), timings, resumed);
+    write_coverage(&output.join("coverage.csv"), context, results);
+    write_report(&output.join("report.json"), context, results);
+    write_panics(&output.join("panics.txt"), context, results);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_project;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(rfc3339(SystemTime::UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(951_827_696);
+        assert_eq!(rfc3339(time), "2000-02-29T12:34:56Z");
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_798_761_599);
+        assert_eq!(rfc3339(time), "2026-12-31T23:59:59Z");
+        assert_eq!(run_name(time), "2026-12-31T23-59-59Z");
+    }
+
+    #[test]
+    fn run_dirs() {
+        let output = test_project("run-dirs", &[]);
+        assert_eq!(RunDirs::latest(&output), RunDirs::flat(&output));
+
+        let run = RunDirs::start(&output).unwrap();
+        assert_eq!(run.results, run.status.join(CRATES_DIR));
+        assert!(run.results.is_dir());
+        assert!(run.status.starts_with(output.join("results")));
+        let name = run.status.file_name().unwrap().to_string_lossy();
+        assert!(!name.contains(':'));
+        let latest = RunDirs::latest_run(&output).unwrap();
+        assert!(!latest.resumable());
+        fs::write(run.progress_file(), "3").unwrap();
+        assert!(latest.resumable());
+        assert_eq!(
+            fs::canonicalize(latest.results).unwrap(),
+            fs::canonicalize(&run.results).unwrap()
+        );
+
+        // Runs in the same second still get their own directory
+        let next = RunDirs::start(&output).unwrap();
+        let again = RunDirs::start(&output).unwrap();
+        assert_ne!(next.status, again.status);
+        assert_eq!(
+            fs::canonicalize(RunDirs::latest(&output).status).unwrap(),
+            fs::canonicalize(&again.status).unwrap()
+        );
+
+        // Where symlinks can't be made the latest run is named in a file
+        let latest = output.join("results").join(LATEST_RUN);
+        fs::remove_file(&latest).unwrap();
+        fs::write(
+            &latest,
+            run.status.file_name().unwrap().to_string_lossy().as_ref(),
+        )
+        .unwrap();
+        assert_eq!(RunDirs::latest(&output), run);
+    }
+
+    #[test]
+    fn html_report() {
+        let project = |name: &str, status| ProjectResult {
+            name: name.to_string(),
+            ..serde_json::from_value(serde_json::json!({ "status": status })).unwrap()
+        };
+        let mut failed = project("<b>", "fail");
+        failed.error = Some(ErrorInfo {
+            kind: "tarpaulin".to_string(),
+            failure: None,
+            message: "a & b".to_string(),
+        });
+        let mut html = vec![];
+        write_html(&mut html, &[project("tater", "pass"), failed]).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("<p>1 of 2 projects passed</p>"));
+        assert!(html.contains("<tr><td>tater</td><td>pass</td>"));
+        assert!(html.contains("<td>&lt;b&gt;</td><td>fail</td>"));
+        assert!(html.contains("<td>a &amp; b</td>"));
+    }
+
+    #[test]
+    fn engine_results_reported() {
+        let output = test_project("engine-report", &[]);
+        let results = output.join("results");
+        let spec: CrateSpec =
+            serde_json::from_str(r#"{ "repository_url": "https://github.com/foo/bar" }"#).unwrap();
+        let context = Context {
+            crates: vec![spec],
+            ..Default::default()
+        };
+        let result = |status: &str, percentage: f64, panicked: bool| {
+            let mut result: ProjectResult =
+                serde_json::from_value(serde_json::json!({ "status": status })).unwrap();
+            result.coverage = Some(CoverageReport::Found(Coverage {
+                percentage,
+                covered: 1,
+                coverable: 2,
+            }));
+            if panicked {
+                result.panic = Some(PanicInfo {
+                    message: "oops".to_string(),
+                    location: None,
+                    frames: vec![],
+                });
+            }
+            result
+        };
+        write_result(
+            &results.join("bar/ptrace").join(RESULT_FILE),
+            &result("pass", 50.0, false),
+        );
+        write_result(
+            &results.join("bar/llvm").join(RESULT_FILE),
+            &result("fail", 25.0, true),
+        );
+
+        write_run_reports(&output, &context, &results, vec![], false);
+        let report: Report =
+            serde_json::from_str(&fs::read_to_string(output.join("report.json")).unwrap()).unwrap();
+        let names = report
+            .projects
+            .iter()
+            .map(|x| (x.name.as_str(), x.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [("bar/ptrace", Status::Pass), ("bar/llvm", Status::Fail)]
+        );
+        let coverage = fs::read_to_string(output.join("coverage.csv")).unwrap();
+        assert!(coverage.contains("bar/ptrace,50.00,1,2"));
+        assert!(coverage.contains("bar/llvm,25.00,1,2"));
+        let panics = fs::read_to_string(output.join("panics.txt")).unwrap();
+        assert!(panics.contains("crates: bar/llvm"));
+        assert_eq!(read_all_results(&results).unwrap().len(), 2);
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|x| x.split('\t').next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn status_files_partition() {
+        let output = test_project("status", &[]);
+        fs::create_dir_all(&output).unwrap();
+
+        let mut files = StatusFiles::open(&output).unwrap();
+        files.passed("a", 1.0);
+        files.failed("b", "compile_error", 2.0);
+        files.passed("c", 3.0);
+        files.paused("d");
+        files.skipped("f", 4.0);
+        drop(files);
+        assert_eq!(lines(&output.join("pass")), ["a", "c"]);
+        assert_eq!(lines(&output.join("skipped")), ["f"]);
+        assert_eq!(lines(&output.join("fail")), ["b"]);
+        assert_eq!(lines(&output.join("paused")), ["d"]);
+
+        // Resuming runs the paused project again
+        let mut files = StatusFiles::open(&output).unwrap();
+        files.failed("d", "stalled", 5.0);
+        files.passed("e", 6.0);
+        // Ran again and passed this time
+        files.passed("b", 7.0);
+        drop(files);
+        let pass = lines(&output.join("pass"));
+        let fail = lines(&output.join("fail"));
+        assert_eq!(pass, ["a", "c", "e", "b"]);
+        assert_eq!(fail, ["d"]);
+        assert!(lines(&output.join("paused")).is_empty());
+        assert!(pass.iter().all(|x| !fail.contains(x)));
+
+        let entry = &read_status_file(&output.join("fail"), "fail")[0];
+        assert_eq!(entry.status, "stalled");
+        assert!(entry.time.is_some());
+        assert_eq!(entry.duration, Some(5.0));
+    }
+
+    #[test]
+    fn old_status_files() {
+        let output = test_project("old-status", &[]);
+        fs::create_dir_all(&output).unwrap();
+        fs::write(output.join("pass"), "a\nb\na\n").unwrap();
+        fs::write(output.join("fail"), "c\tcompile_error\nb\tstalled\n").unwrap();
+        assert_eq!(
+            read_status_file(&output.join("fail"), "fail")[0],
+            StatusEntry {
+                name: "c".to_string(),
+                status: "compile_error".to_string(),
+                time: None,
+                duration: None,
+            }
+        );
+
+        let mut files = StatusFiles::open(&output).unwrap();
+        files.passed("c", 1.0);
+        drop(files);
+        // Without timestamps the fail file is taken as newer than the pass file
+        assert_eq!(lines(&output.join("pass")), ["a", "c"]);
+        assert_eq!(lines(&output.join("fail")), ["b"]);
+        assert!(!output.join(".pass.tmp").exists());
+    }
+
+    #[test]
+    fn durations_formatted() {
+        assert_eq!(human_duration(Duration::from_secs(252)), "4m12s");
+        assert_eq!(human_duration(Duration::from_secs(3900)), "1h5m");
+        assert_eq!(human_duration(Duration::from_secs(30)), "30s");
+    }
+
+    #[test]
+    fn durations_parsed() {
+        let parse = |x| parse_human_duration(x).map(|x| x.as_secs());
+        assert_eq!(parse("5h30m"), Ok(5 * 3600 + 30 * 60));
+        assert_eq!(parse("90m"), Ok(5400));
+        assert_eq!(parse("1d 2h"), Ok(26 * 3600));
+        assert_eq!(parse("4m12s"), Ok(252));
+        assert_eq!(parse("600"), Ok(600));
+        assert!(parse("5h30").is_err());
+        assert!(parse("h").is_err());
+        assert!(parse("5w").is_err());
+        assert!(parse("").is_err());
+        assert!(parse("18446744073709551615d").is_err());
+        assert!(parse("18446744073709551615s1s").is_err());
+    }
+}
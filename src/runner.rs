@@ -1,16 +1,29 @@
 use crate::ci;
+use crate::docker;
+use crate::events::RunEvent;
+use crate::git::{self, GitError};
+use crate::metadata;
+use crate::sccache::{self, SccacheStats};
+use lazy_static::lazy_static;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs::{copy, create_dir, read_dir, remove_dir_all, remove_file, File};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{copy, create_dir_all, read_dir, remove_dir_all, remove_file, File};
 use std::io::prelude::*;
-use std::io::{self, BufWriter};
-use std::path::Path;
-use std::process::{Command, Stdio};
+use std::io::{self, BufWriter, Read};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use sysinfo::{ProcessExt, System, SystemExt};
 use thiserror::Error;
-use tracing::{error, info, instrument, warn};
+use tracing::{debug, error, info, info_span, instrument, warn};
 use url::Url;
 
 #[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -24,149 +37,2449 @@ pub struct Context {
     /// Env vars for every tarpaulin evocation
     #[serde(default)]
     pub env: HashMap<String, String>,
+    /// Fetch the dependencies with `cargo fetch` before running and then run tarpaulin offline.
+    /// This separates network failures from tarpaulin failures
+    #[serde(default)]
+    pub fetch: bool,
+    /// Run tarpaulin without network access so tests that call out to the internet fail every
+    /// time rather than depending on the host. Implies `fetch` so the build still gets its
+    /// dependencies
+    #[serde(default)]
+    pub no_network: bool,
+    /// How to run tarpaulin on the projects
+    #[serde(default)]
+    pub engine: Engine,
+    /// Clone projects with the git binary instead of libgit2, for servers needing something
+    /// libgit2 doesn't support yet
+    #[serde(default)]
+    pub use_system_git: bool,
+    /// Run tarpaulin with `cross` for projects with a target, for targets that can only be built
+    /// in cross's containers. The cross image for the target needs cargo-tarpaulin installed
+    #[serde(default)]
+    pub cross: bool,
+    /// Docker image to run tarpaulin in when using the docker engine
+    #[serde(default)]
+    pub image: Option<String>,
+    /// Free text for people reading the repos file, not used when running
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// Shell program and arguments setup and teardown scripts are ran with e.g. `["bash", "-c"]`.
+    /// Defaults to `sh -c`, or PowerShell on Windows falling back to `cmd /C` if it's not installed
+    #[serde(default)]
+    pub shell: Option<Vec<String>>,
+    /// How long to wait for setup and teardown scripts for crates which don't set their own
+    /// timeouts. If not set we wait forever
+    #[serde(default)]
+    pub script_timeout_seconds: Option<u64>,
+    /// When tarpaulin fails run `cargo test` to see if the tests fail without tarpaulin too
+    #[serde(default)]
+    pub baseline_test: bool,
+    /// Don't delete the projects' target directories after running them
+    #[serde(default)]
+    pub keep_target: bool,
+    /// Build every project into this target directory so shared dependencies are compiled once
+    #[serde(default)]
+    pub shared_target_dir: Option<PathBuf>,
+    /// Compile with `RUSTC_WRAPPER=sccache` so dependencies are cached between projects and runs
+    #[serde(default)]
+    pub sccache: bool,
+    /// A cargo-tarpaulin binary to run directly instead of `cargo tarpaulin`, for testing a local
+    /// build of tarpaulin. Not supported with the docker engine
+    #[serde(default)]
+    pub tarpaulin_path: Option<PathBuf>,
+    /// Features cargo-tarpaulin is installed with, such as `vendored-openssl`, when tater installs
+    /// it or says how to
+    #[serde(default)]
+    pub tarpaulin_features: Vec<String>,
+    /// Write tarpaulin's output straight to `stdout.log` and `stderr.log` in the project's results
+    #[serde(default)]
+    pub output_to_files: bool,
+    /// Flags removed from tarpaulin commands found in CI configs
+    #[serde(default)]
+    pub strip_args: Vec<String>,
+    /// Options removed along with their value from tarpaulin commands found in CI configs
+    #[serde(default)]
+    pub strip_options: Vec<String>,
+    /// Args added to every tarpaulin command after any found in CI configs
+    #[serde(default)]
+    pub force_args: Vec<String>,
+    /// Warn when an arg references a `$VAR` that isn't set, it's left as written either way
+    #[serde(default)]
+    pub warn_undefined_vars: bool,
+    /// Directory of override files named after the crates such as `foo.toml`, see the README
+    #[serde(default)]
+    pub overrides_dir: Option<PathBuf>,
+    /// Where tater writes the tarpaulin config for a crate whose CI runs several test commands,
+    /// set to a directory in the output for runs. Without one only the first command is ran
+    #[serde(skip)]
+    pub config_dir: Option<PathBuf>,
+    /// Tells the run to stop, shared with whatever handles ctrl-c
+    #[serde(skip)]
+    pub shutdown: ShutdownSignal,
+}
+
+/// Where tarpaulin is ran
+#[derive(
+    Debug, Default, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    /// Directly on the host
+    #[default]
+    Native,
+    /// Inside a docker container with the project mounted in
+    Docker,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "native" => Ok(Self::Native),
+            "docker" => Ok(Self::Docker),
+            e => Err(format!("Unsupported engine: {}", e)),
+        }
+    }
+}
+
+/// Tarpaulin's coverage engine, passed to tarpaulin as `--engine`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverageEngine {
+    Ptrace,
+    Llvm,
+}
+
+impl fmt::Display for CoverageEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ptrace => f.write_str("ptrace"),
+            Self::Llvm => f.write_str("llvm"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CrateSpec {
+    #[serde(with = "url_serde")]
+    pub repository_url: Url,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// For anything that requires something like another server to be up and running. Ran with
+    /// the crate's `shell` or the context's, which defaults to `sh -c`, or PowerShell on Windows
+    /// falling back to `cmd /C`
+    #[serde(default)]
+    pub setup: Option<String>,
+    /// How long to wait for the setup script to finish before giving up on the project. If not
+    /// set we wait forever
+    #[serde(default)]
+    pub setup_timeout_seconds: Option<u64>,
+    /// To tear down any addition things that need running. Ran with the same shell as `setup`
+    #[serde(default)]
+    pub teardown: Option<String>,
+    /// How long to wait for the teardown script to finish before killing it. If not set we wait
+    /// forever
+    #[serde(default)]
+    pub teardown_timeout_seconds: Option<u64>,
+    /// Docker image to use for this project instead of the one in the context
+    #[serde(default)]
+    pub image: Option<String>,
+    /// Target to build the project for instead of the one in the context
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Run tarpaulin with `cross` for this project, the same as `cross` in the context
+    #[serde(default)]
+    pub cross: bool,
+    /// Whether tarpaulin is expected to pass or fail on this project
+    #[serde(default)]
+    pub expected: Expectation,
+    /// Directory in the repository to run tarpaulin in, for when only one crate in a larger
+    /// repository is of interest
+    #[serde(default)]
+    pub subdir: Option<String>,
+    /// Labels to categorise projects, runs can be filtered to only projects with given labels
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Temporarily disable the project without removing it from the repos file
+    #[serde(default)]
+    pub skip: bool,
+    /// Free text for people reading the repos file such as why the project was added or known
+    /// issues, not used when running
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// Shell to run the setup and teardown scripts with instead of the one in the context
+    #[serde(default)]
+    pub shell: Option<Vec<String>>,
+    /// Build without sccache even when the context uses it, for crates whose build scripts
+    /// misbehave under a `RUSTC_WRAPPER`
+    #[serde(default)]
+    pub no_cache: bool,
+    /// Don't pick the packages to test when the project is a workspace. Otherwise the whole
+    /// workspace is tested, or in a virtual workspace the package named after the project if
+    /// there is one. For workspaces with members such as fuzz targets or examples that don't build
+    #[serde(default)]
+    pub no_workspace: bool,
+    /// Coverage engine for tarpaulin to use, if not set tarpaulin picks
+    #[serde(default)]
+    pub tarpaulin_engine: Option<CoverageEngine>,
+}
+
+/// Directory next to the repos file the override files are in if the repos file doesn't say
+pub const OVERRIDES_DIR: &str = "overrides";
+
+/// Settings overridden for a project by `<name>.toml` in the overrides directory, so quirks of a
+/// project can live outside the repos file
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CrateOverride {
+    /// Replaces the crate's args
+    #[serde(default)]
+    pub args: Option<Vec<String>>,
+    /// Added to the crate's env, replacing any variables already set
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Replaces the crate's setup script
+    #[serde(default)]
+    pub setup: Option<String>,
+    /// Replaces the crate's subdir
+    #[serde(default)]
+    pub subdir: Option<String>,
+}
+
+impl CrateOverride {
+    /// Reads the crate's override file from the overrides directory, `None` if there isn't one
+    pub fn load(overrides_dir: &Path, crate_name: &str) -> Result<Option<Self>, String> {
+        let path = overrides_dir.join(format!("{}.toml", crate_name));
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+        let res: Self = toml::from_str(&contents).map_err(|e| e.to_string())?;
+        if let Some(subdir) = res.subdir.as_deref().filter(|x| subdir_escapes(x)) {
+            return Err(format!(
+                "subdir {} must be relative and inside the project",
+                subdir
+            ));
+        }
+        if let Some(e) = res.setup.as_deref().and_then(|x| check_script(x).err()) {
+            return Err(format!("setup script {}", e));
+        }
+        Ok(Some(res))
+    }
+
+    /// The spec with the overrides applied
+    pub fn apply(&self, spec: &CrateSpec) -> CrateSpec {
+        let mut spec = spec.clone();
+        if let Some(args) = self.args.as_ref() {
+            spec.args = args.clone();
+        }
+        spec.env
+            .extend(self.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+        if self.setup.is_some() {
+            spec.setup = self.setup.clone();
+        }
+        if self.subdir.is_some() {
+            spec.subdir = self.subdir.clone();
+        }
+        spec
+    }
+}
+
+/// The project's spec with its override file applied if it has one
+pub fn with_override<'a>(
+    context: &Context,
+    spec: &'a CrateSpec,
+) -> Result<Cow<'a, CrateSpec>, RunError> {
+    let (dir, name) = match (context.overrides_dir.as_deref(), spec.name()) {
+        (Some(dir), Some(name)) => (dir, name),
+        _ => return Ok(Cow::Borrowed(spec)),
+    };
+    match CrateOverride::load(dir, name).map_err(RunError::Override)? {
+        Some(overrides) => {
+            info!("Applying overrides from {}", dir.display());
+            Ok(Cow::Owned(overrides.apply(spec)))
+        }
+        None => Ok(Cow::Borrowed(spec)),
+    }
+}
+
+/// Finds the directory with the project's manifest. If `dir` has no `Cargo.toml` the directories
+/// directly inside it are searched for a workspace manifest. `None` if neither is found, so it
+/// isn't a rust project
+pub fn find_manifest_dir(dir: &Path) -> Option<PathBuf> {
+    if dir.join("Cargo.toml").is_file() {
+        return Some(dir.to_path_buf());
+    }
+    let mut found = read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|x| x.path())
+        .filter(|x| metadata::is_workspace(x))
+        .collect::<Vec<_>>();
+    // Sorted so the same one is picked each time if there's more than one
+    found.sort();
+    found.into_iter().next()
+}
+
+/// Points the spec at the directory with the project's manifest, `None` if there isn't one
+pub fn with_manifest_dir<'a>(
+    proj_dir: &Path,
+    spec: Cow<'a, CrateSpec>,
+) -> Option<Cow<'a, CrateSpec>> {
+    let working_dir = spec.working_dir(proj_dir);
+    let manifest_dir = find_manifest_dir(&working_dir)?;
+    if manifest_dir == working_dir {
+        return Some(spec);
+    }
+    let subdir = manifest_dir
+        .strip_prefix(proj_dir)
+        .ok()?
+        .components()
+        .map(|x| x.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    info!(
+        "No Cargo.toml in {}, using the one in {}",
+        working_dir.display(),
+        subdir
+    );
+    let mut spec = spec.into_owned();
+    spec.subdir = Some(subdir);
+    Some(Cow::Owned(spec))
+}
+
+#[derive(Error, Debug)]
+pub enum RunError {
+    #[error("Issue cloning repo: {0}")]
+    Git(GitError),
+    #[error("Failed to fetch dependencies: {0}")]
+    Fetch(String),
+    #[error("Failed to run setup script: {0}")]
+    Setup(io::Error),
+    #[error("Failed to run tarpaulin: {0}")]
+    Tarpaulin(String),
+    #[error("Teardown script failed: {0}")]
+    Teardown(String),
+    #[error("Tarpaulin seems to have stalled")]
+    Stalled,
+    #[error("Tarpaulin exited with a failure: {0}")]
+    Failed(FailureKind),
+    #[error("Tarpaulin or something it ran was killed, likely by the OOM killer")]
+    OutOfMemory,
+    /// Tarpaulin was killed by a signal other than the OOM killer's, the signal is only known on
+    /// unix
+    #[error("Tarpaulin crashed, killed by {}", describe_signal(*.0))]
+    Crashed(Option<i32>),
+    #[error("Stopped by ctrl-c")]
+    Interrupted,
+    #[error("Invalid override file: {0}")]
+    Override(String),
+}
+
+/// Why tarpaulin exited with a failure, worked out from its output
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// The project didn't compile
+    CompileError,
+    /// The project compiled but some tests failed
+    TestFailure,
+    /// Tarpaulin itself panicked, this is a tarpaulin bug
+    TarpaulinPanic,
+    /// Linker errors or compiler crashes, problems with the toolchain rather than the project
+    Infra,
+    /// The tests ran but tarpaulin couldn't collect or report the coverage, or it was below the
+    /// `--fail-under` threshold
+    CoverageError,
+    /// Tests failed trying to reach the network while it was disabled with `no_network`
+    NetworkDependent,
+    /// Nothing in the output matched
+    Unknown,
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::CompileError => "compile_error",
+            Self::TestFailure => "test_failure",
+            Self::TarpaulinPanic => "tarpaulin_panic",
+            Self::Infra => "infra",
+            Self::CoverageError => "coverage_error",
+            Self::NetworkDependent => "network_dependent",
+            Self::Unknown => "unknown",
+        };
+        f.write_str(s)
+    }
+}
+
+impl RunError {
+    /// Name of the error variant for use in reports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Git(_) => "git",
+            Self::Fetch(_) => "fetch",
+            Self::Setup(_) => "setup",
+            Self::Teardown(_) => "teardown",
+            Self::Tarpaulin(_) => "tarpaulin",
+            Self::Stalled => "stalled",
+            Self::Failed(_) => "failed",
+            Self::OutOfMemory => "out_of_memory",
+            Self::Crashed(_) => "crashed",
+            Self::Interrupted => "interrupted",
+            Self::Override(_) => "override",
+        }
+    }
+
+    /// Whether the error is likely transient so running the project again might pass
+    pub fn retryable(&self) -> bool {
+        match self {
+            Self::Git(_) | Self::Fetch(_) | Self::Stalled => true,
+            Self::Setup(e) => e.kind() == io::ErrorKind::TimedOut,
+            _ => false,
+        }
+    }
+
+    /// Label for the failure, the failure kind if tarpaulin ran otherwise the error kind
+    pub fn label(&self) -> String {
+        match self {
+            Self::Failed(kind) => kind.to_string(),
+            e => e.kind().to_string(),
+        }
+    }
+}
+
+/// All the problems found in a repos file
+#[derive(Error, Debug)]
+#[error("Invalid repos file:\n  {}", .0.join("\n  "))]
+pub struct ValidationError(pub Vec<String>);
+
+/// Removes the project's target directory however we exit from running the test, this is the
+/// only place the target directory is cleaned up
+struct ProjectCleanupGuard<'a> {
+    proj_dir: &'a Path,
+    /// Leave the target directory in place
+    keep: bool,
+    /// Target directory shared between projects, it's left alone apart from tarpaulin's
+    /// directory in it which is specific to the project
+    shared_target: Option<&'a Path>,
+}
+
+impl<'a> Drop for ProjectCleanupGuard<'a> {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = remove_dir_all(self.proj_dir.join("target"));
+            if let Some(shared) = self.shared_target {
+                let _ = remove_dir_all(shared.join("tarpaulin"));
+            }
+        }
+    }
+}
+
+/// Runs the teardown script when dropped so any services started in the setup are stopped no
+/// matter how we exit from running the test
+struct TeardownGuard<'a> {
+    shell: &'a [String],
+    timeout: Option<Duration>,
+    proj_dir: &'a Path,
+    proj_res: &'a Path,
+    /// `None` once the teardown has been ran
+    teardown: Option<&'a str>,
+}
+
+impl<'a> TeardownGuard<'a> {
+    /// Runs the teardown now so the result can be recorded
+    fn run(mut self) -> Result<(), RunError> {
+        self.run_teardown()
+    }
+
+    fn run_teardown(&mut self) -> Result<(), RunError> {
+        let teardown = match self.teardown.take() {
+            Some(teardown) => teardown,
+            None => return Ok(()),
+        };
+        let res = run_script(self.shell, teardown, self.proj_dir, self.timeout, None);
+        write_script_log(&self.proj_res.join("teardown.log"), &res);
+        match res {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => Err(RunError::Teardown(format!(
+                "exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+            Err(e) => Err(RunError::Teardown(e.to_string())),
+        }
+    }
+}
+
+impl<'a> Drop for TeardownGuard<'a> {
+    fn drop(&mut self) {
+        if let Err(e) = self.run_teardown() {
+            warn!("{}", e);
+        }
+    }
+}
+
+/// Shell used for setup and teardown scripts if none is set
+pub fn default_shell() -> Vec<String> {
+    if cfg!(windows) {
+        windows_shell(on_path)
+    } else {
+        vec!["sh".to_string(), "-c".to_string()]
+    }
+}
+
+/// PowerShell if it's installed, preferring the cross platform `pwsh`, otherwise `cmd`
+fn windows_shell(installed: impl Fn(&str) -> bool) -> Vec<String> {
+    let shell = match ["pwsh", "powershell"].iter().find(|x| installed(x)) {
+        Some(powershell) => vec![*powershell, "-NoProfile", "-NonInteractive", "-Command"],
+        None => vec!["cmd", "/C"],
+    };
+    shell.into_iter().map(String::from).collect()
+}
+
+/// Whether the program is in one of the directories in `PATH`
+fn on_path(program: &str) -> bool {
+    let program = Path::new(program).with_extension(std::env::consts::EXE_EXTENSION);
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(&program).is_file()))
+        .unwrap_or(false)
+}
+
+/// Whether the shell has no program to run scripts with
+fn empty_shell(shell: &[String]) -> bool {
+    shell.first().map(|x| x.trim().is_empty()).unwrap_or(true)
+}
+
+/// Command running a setup or teardown script with the given shell
+fn script_command(shell: &[String], script: &str) -> io::Result<Command> {
+    match shell.split_first() {
+        Some((program, args)) if !empty_shell(shell) => {
+            let mut cmd = new_command(program);
+            cmd.args(args).arg(script);
+            Ok(cmd)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "shell can't be empty",
+        )),
+    }
+}
+
+/// Runs a setup or teardown script in the project directory, stopping it if `shutdown` asks to
+/// stop now. Teardown isn't given one so whatever setup started is always cleaned up
+fn run_script(
+    shell: &[String],
+    script: &str,
+    proj_dir: &Path,
+    timeout: Option<Duration>,
+    shutdown: Option<&ShutdownSignal>,
+) -> io::Result<Output> {
+    script_command(shell, script)?
+        .current_dir(proj_dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|child| wait_with_timeout(child, timeout, shutdown))
+}
+
+/// Writes the exit status and output of a setup or teardown script to the results
+fn write_script_log(path: &Path, res: &io::Result<Output>) {
+    let write = || -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match res {
+            Ok(output) => {
+                writeln!(writer, "status: {}", output.status)?;
+                writer.write_all(b"\nstdout:\n")?;
+                writer.write_all(&output.stdout)?;
+                writer.write_all(b"\n\nstderr:\n")?;
+                writer.write_all(&output.stderr)?;
+            }
+            Err(e) => writeln!(writer, "error: {}", e)?,
+        }
+        writer.flush()
+    };
+    if let Err(e) = write() {
+        warn!("Failed to write {}: {}", path.display(), e);
+    }
+}
+
+/// The final status of running tarpaulin on a project
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Fail,
+    Stalled,
+    Timeout,
+    Skipped,
+}
+
+/// Why a project was skipped rather than passing or failing
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Tarpaulin ran but found no tests
+    NoTests,
+    /// The repository has no `Cargo.toml` so there's nothing for tarpaulin to run
+    NotRust,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::NoTests => "no_tests",
+            Self::NotRust => "not_rust",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Pass => "pass",
+            Self::Fail => "fail",
+            Self::Stalled => "stalled",
+            Self::Timeout => "timeout",
+            Self::Skipped => "skipped",
+        };
+        f.write_str(s)
+    }
+}
+
+/// What we expect to happen when running tarpaulin on a project
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Expectation {
+    #[default]
+    Pass,
+    Fail,
+    /// Don't even try to run it
+    Skip,
+}
+
+/// How the dependency fetching phase went for a project
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FetchOutcome {
+    pub success: bool,
+    pub attempts: usize,
+    pub duration_secs: f64,
+    pub error: Option<String>,
+}
+
+const FETCH_ATTEMPTS: usize = 3;
+
+/// Notes longer than this probably belong in an issue rather than the repos file
+const MAX_NOTES_LEN: usize = 500;
+
+/// How long each phase of running a project took in seconds
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Timings {
+    pub total: f64,
+    pub clone: f64,
+    pub fetch: f64,
+    pub setup: f64,
+    pub tarpaulin: f64,
+    pub teardown: f64,
+}
+
+/// Coverage statistics reported by tarpaulin
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Coverage {
+    pub percentage: f64,
+    pub covered: u64,
+    pub coverable: u64,
+}
+
+/// Whether tarpaulin reported coverage for a project
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoverageReport {
+    Found(Coverage),
+    /// Tarpaulin finished without printing a coverage line, likely it crashed before reporting
+    NoCoverageLineFound,
+}
+
+/// A panic in tarpaulin itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PanicInfo {
+    pub message: String,
+    /// Where in the source the panic happened
+    pub location: Option<String>,
+    /// The start of the backtrace if one was printed
+    #[serde(default)]
+    pub frames: Vec<String>,
+}
+
+/// Whether a project failing under tarpaulin also fails under `cargo test`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BaselineResult {
+    /// `cargo test` passes so the failure is likely tarpaulin's fault
+    TarpaulinOnly,
+    /// `cargo test` fails too so the project is broken regardless of tarpaulin
+    CargoTestToo,
+}
+
+/// Longest we'll wait for `cargo test` when checking a failure against it
+const BASELINE_TEST_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// How many backtrace frames to keep from a tarpaulin panic
+const PANIC_FRAMES: usize = 12;
+
+/// Everything recorded while running tarpaulin on a project
+#[derive(Debug, Default)]
+pub struct RunOutcome {
+    /// Why the run failed if it did
+    pub error: Option<RunError>,
+    /// Program and arguments tarpaulin was spawned with
+    pub command: Vec<String>,
+    /// Environment variables set for tarpaulin
+    pub env: HashMap<String, String>,
+    pub timings: Timings,
+    /// Commit of the project that was tested
+    pub commit: Option<String>,
+    pub exit_code: Option<i32>,
+    /// Signal that killed tarpaulin, only known on unix
+    pub signal: Option<i32>,
+    /// Whether the tarpaulin debug log was found and copied into the results
+    pub found_log: bool,
+    /// `None` if tarpaulin never finished running
+    pub coverage: Option<CoverageReport>,
+    /// Coverage from tarpaulin's JSON report if it wrote one
+    pub report_coverage: Option<Coverage>,
+    /// Source files in tarpaulin's JSON report if it wrote one
+    pub report_files: Option<Vec<String>>,
+    /// How much of tarpaulin's build was cached if it was built with sccache
+    pub sccache: Option<SccacheStats>,
+    /// Set if tarpaulin failed because it panicked
+    pub panic: Option<PanicInfo>,
+    /// Teardown failures don't fail the project but may affect later projects
+    pub teardown_error: Option<RunError>,
+    /// Set if tarpaulin failed and `cargo test` was ran to compare
+    pub baseline: Option<BaselineResult>,
+    /// Tarpaulin ran but the project has no tests so there's nothing to judge it on
+    pub no_tests: bool,
+    /// There's no `Cargo.toml` in the repository so tarpaulin wasn't ran
+    pub not_rust: bool,
+}
+
+impl RunOutcome {
+    pub fn status(&self) -> Status {
+        match self.error.as_ref() {
+            None if self.skip_reason().is_some() => Status::Skipped,
+            None => Status::Pass,
+            Some(RunError::Stalled) => Status::Stalled,
+            Some(RunError::Setup(e)) if e.kind() == io::ErrorKind::TimedOut => Status::Timeout,
+            Some(_) => Status::Fail,
+        }
+    }
+
+    /// Why the project was skipped, `None` if it failed or there was something to judge it on
+    pub fn skip_reason(&self) -> Option<SkipReason> {
+        if self.error.is_some() {
+            None
+        } else if self.not_rust {
+            Some(SkipReason::NotRust)
+        } else if self.no_tests {
+            Some(SkipReason::NoTests)
+        } else {
+            None
+        }
+    }
+}
+
+/// Finds the final coverage line printed by tarpaulin, for workspaces there can be multiple in
+/// which case the last is the overall coverage
+pub fn parse_coverage(output: &str) -> Option<Coverage> {
+    lazy_static! {
+        static ref COVERAGE: Regex =
+            Regex::new(r"(\d+(?:\.\d+)?)% coverage, (\d+)/(\d+) lines covered").unwrap();
+    }
+    COVERAGE.captures_iter(output).last().and_then(|cap| {
+        Some(Coverage {
+            percentage: cap[1].parse().ok()?,
+            covered: cap[2].parse().ok()?,
+            coverable: cap[3].parse().ok()?,
+        })
+    })
+}
+
+/// Name of the JSON coverage report tarpaulin writes with `--out Json`
+pub const JSON_REPORT: &str = "tarpaulin-report.json";
+
+/// The parts of tarpaulin's JSON report tater uses. Older tarpaulin versions only have the totals
+/// for each file
+#[derive(Debug, Deserialize)]
+struct JsonReport {
+    #[serde(default)]
+    files: Vec<JsonReportFile>,
+    covered: Option<u64>,
+    coverable: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonReportFile {
+    /// Components of the file's path
+    #[serde(default)]
+    path: Vec<String>,
+    covered: u64,
+    coverable: u64,
+}
+
+/// Gets the lines covered and coverable from tarpaulin's JSON report
+pub fn parse_json_report(report: &str) -> Option<Coverage> {
+    let report: JsonReport = serde_json::from_str(report).ok()?;
+    let (covered, coverable) = match (report.covered, report.coverable) {
+        (Some(covered), Some(coverable)) => (covered, coverable),
+        _ => report.files.iter().fold((0, 0), |(covered, coverable), x| {
+            (covered + x.covered, coverable + x.coverable)
+        }),
+    };
+    let percentage = if coverable == 0 {
+        0.0
+    } else {
+        covered as f64 * 100.0 / coverable as f64
+    };
+    Some(Coverage {
+        percentage,
+        covered,
+        coverable,
+    })
+}
+
+/// The source files in tarpaulin's JSON report, sorted and relative to `root` if they're in it so
+/// they can be compared between runs in different directories
+pub fn json_report_files(report: &str, root: &Path) -> Option<Vec<String>> {
+    let report: JsonReport = serde_json::from_str(report).ok()?;
+    // Tarpaulin writes absolute paths but the projects directory may be given relative, in a
+    // container they're under wherever the project is mounted
+    let canonical = root.canonicalize().ok();
+    let mut files = report
+        .files
+        .iter()
+        .filter(|x| !x.path.is_empty())
+        .map(|x| {
+            let path = x.path.iter().collect::<PathBuf>();
+            let relative = canonical
+                .iter()
+                .map(PathBuf::as_path)
+                .chain([root, Path::new(docker::PROJECT_MOUNT)])
+                .find_map(|root| path.strip_prefix(root).ok());
+            relative
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/")
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files.dedup();
+    Some(files)
+}
+
+/// Whether tarpaulin's output shows the project has no tests, either nothing was coverable or
+/// every test binary ran zero tests
+pub fn found_no_tests(stdout: &str, coverage: Option<&Coverage>) -> bool {
+    lazy_static! {
+        static ref RUNNING: Regex = Regex::new(r"(?m)^running (\d+) tests?$").unwrap();
+    }
+    if coverage.map(|x| x.coverable == 0).unwrap_or(false) {
+        return true;
+    }
+    let mut counts = RUNNING.captures_iter(stdout).map(|cap| cap[1].to_string());
+    match counts.next() {
+        Some(first) => std::iter::once(first).chain(counts).all(|x| x == "0"),
+        None => false,
+    }
+}
+
+/// Finds the first panic in the output that came from tarpaulin, this is either a panic located in
+/// tarpaulin's source or one with `cargo_tarpaulin` in the backtrace
+pub fn find_tarpaulin_panic(stderr: &str) -> Option<PanicInfo> {
+    lazy_static! {
+        static ref FRAME: Regex = Regex::new(r"^\s*\d+: (.*)$").unwrap();
+    }
+    let lines = stderr.lines().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < lines.len() {
+        let rest = match lines[i].split_once("thread 'main' panicked at ") {
+            Some((_, rest)) => rest,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        i += 1;
+        let is_end = |l: &str| l.starts_with("note:") || l.starts_with("stack backtrace:");
+        // Older rust versions put the message first in quotes, newer ones put the location first
+        // and the message on the following lines
+        let (message, location) = match rest.strip_prefix('\'').and_then(|x| x.rsplit_once("', ")) {
+            Some((message, location)) => (message.to_string(), location.to_string()),
+            None => {
+                let mut message = vec![];
+                while i < lines.len() && !is_end(lines[i]) {
+                    message.push(lines[i]);
+                    i += 1;
+                }
+                (message.join("\n"), rest.trim_end_matches(':').to_string())
+            }
+        };
+        while i < lines.len() && lines[i].starts_with("note:") {
+            i += 1;
+        }
+        let mut frames = vec![];
+        if lines.get(i) == Some(&"stack backtrace:") {
+            i += 1;
+            while i < lines.len() {
+                if let Some(c) = FRAME.captures(lines[i]) {
+                    frames.push(c[1].to_string());
+                } else if let Some(at) = lines[i].trim_start().strip_prefix("at ") {
+                    if let Some(last) = frames.last_mut() {
+                        last.push_str(" at ");
+                        last.push_str(at);
+                    }
+                } else {
+                    break;
+                }
+                i += 1;
+            }
+        }
+        let from_tarpaulin =
+            location.contains("tarpaulin") || frames.iter().any(|x| x.contains("cargo_tarpaulin"));
+        if from_tarpaulin {
+            frames.truncate(PANIC_FRAMES);
+            return Some(PanicInfo {
+                message,
+                location: Some(location),
+                frames,
+            });
+        }
+    }
+    None
+}
+
+impl FailureKind {
+    /// What the failure means for someone reading the logs
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::CompileError => "the project failed to compile",
+            Self::TestFailure => "tests failed under tarpaulin",
+            Self::TarpaulinPanic => "tarpaulin panicked, this is a tarpaulin bug",
+            Self::Infra => "a toolchain problem rather than the project",
+            Self::CoverageError => "tarpaulin ran the tests but failed on the coverage",
+            Self::NetworkDependent => "tests need network access, tag the crate as such",
+            Self::Unknown => "nothing recognised in the output",
+        }
+    }
+}
+
+/// What tarpaulin's exit status says about how it finished
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TarpaulinExitCode {
+    Success,
+    /// Exit code 1, some tests failed
+    TestFailure,
+    /// Exit code 2, the project failed to build
+    BuildFailure,
+    /// Exit code 101, tarpaulin panicked
+    Panic,
+    Other(i32),
+    /// Killed by a signal so there's no exit code, the signal is only known on unix
+    Signal(Option<i32>),
+}
+
+impl TarpaulinExitCode {
+    /// The failure the exit code points to, `Unknown` if it doesn't say
+    pub fn failure_kind(self) -> FailureKind {
+        match self {
+            Self::TestFailure => FailureKind::TestFailure,
+            Self::BuildFailure => FailureKind::CompileError,
+            Self::Panic => FailureKind::TarpaulinPanic,
+            Self::Success | Self::Other(_) | Self::Signal(_) => FailureKind::Unknown,
+        }
+    }
+}
+
+pub fn interpret_exit_status(status: &ExitStatus) -> TarpaulinExitCode {
+    match status.code() {
+        Some(0) => TarpaulinExitCode::Success,
+        Some(1) => TarpaulinExitCode::TestFailure,
+        Some(2) => TarpaulinExitCode::BuildFailure,
+        Some(101) => TarpaulinExitCode::Panic,
+        Some(code) => TarpaulinExitCode::Other(code),
+        None => TarpaulinExitCode::Signal(exit_signal(status)),
+    }
+}
+
+/// The signal that killed the process, always `None` on platforms without signals
+pub fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        None
+    }
+}
+
+fn describe_signal(signal: Option<i32>) -> String {
+    match signal {
+        Some(signal) => format!("signal {}", signal),
+        None => "a signal".to_string(),
+    }
+}
+
+/// The error for tarpaulin exiting with a failure. Being killed by a signal is kept apart from
+/// tarpaulin reporting a failure as it points to a crash rather than a problem with the project
+pub fn exit_error(status: &ExitStatus, stdout: &str, stderr: &str) -> RunError {
+    match interpret_exit_status(status) {
+        TarpaulinExitCode::Signal(signal) => RunError::Crashed(signal),
+        exit => RunError::Failed(failure_kind(exit, stdout, stderr)),
+    }
+}
+
+/// Whether tarpaulin or something it ran looks to have been killed with SIGKILL, which is what
+/// the OOM killer uses. Either tarpaulin itself was killed or cargo reports a child was
+pub fn looks_oom_killed(status: &ExitStatus, stderr: &str) -> bool {
+    #[cfg(unix)]
+    if exit_signal(status) == Some(libc::SIGKILL) {
+        return true;
+    }
+    #[cfg(not(unix))]
+    let _ = status;
+    stderr
+        .lines()
+        .any(|l| l.contains("signal: 9") || l.trim() == "Killed")
+}
+
+/// Looks in the kernel log for the OOM killer killing any of the processes, `None` if the log can't
+/// be read
+fn kernel_log_oom(pids: &[impl fmt::Display]) -> Option<bool> {
+    let log = new_command("dmesg")
+        .arg("--ctime")
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|x| x.status.success())
+        .map(|x| String::from_utf8_lossy(&x.stdout).to_string())
+        .or_else(|| std::fs::read_to_string("/var/log/kern.log").ok())?;
+    Some(oom_killed_any(&log, pids))
+}
+
+/// Whether the kernel log has the OOM killer killing any of the processes
+fn oom_killed_any(log: &str, pids: &[impl fmt::Display]) -> bool {
+    let pids = pids.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+    log.lines()
+        .filter_map(|l| l.split("Killed process ").nth(1))
+        .filter_map(|l| l.split_whitespace().next())
+        .any(|killed| pids.iter().any(|x| x == killed))
+}
+
+/// Works out why tarpaulin failed from its output, falling back to the exit code when nothing in
+/// the output is recognised
+pub fn failure_kind(exit: TarpaulinExitCode, stdout: &str, stderr: &str) -> FailureKind {
+    match classify_failure(stdout, stderr) {
+        FailureKind::Unknown => exit.failure_kind(),
+        kind => kind,
+    }
+}
+
+/// Whether failing tests look to have tried to use the network, only meaningful when tarpaulin was
+/// ran without it
+pub fn looks_network_dependent(stdout: &str, stderr: &str) -> bool {
+    const NETWORK_ERRORS: &[&str] = &[
+        "Connection refused",
+        "Network is unreachable",
+        "failed to lookup address information",
+        "Temporary failure in name resolution",
+        "dns error",
+        "os error 101",
+        "os error 111",
+    ];
+    stdout
+        .lines()
+        .chain(stderr.lines())
+        .any(|l| NETWORK_ERRORS.iter().any(|e| l.contains(e)))
+}
+
+/// Works out why tarpaulin failed from its output. Tarpaulin panics are checked first as they
+/// can follow any of the other errors
+pub fn classify_failure(stdout: &str, stderr: &str) -> FailureKind {
+    let lines = || stdout.lines().chain(stderr.lines());
+    let tarpaulin_panic = lines().any(|l| {
+        l.contains("thread 'main' panicked")
+            && (l.contains("cargo-tarpaulin") || l.contains("cargo_tarpaulin"))
+    });
+    if tarpaulin_panic {
+        FailureKind::TarpaulinPanic
+    } else if lines().any(|l| {
+        l.contains("internal compiler error")
+            || l.contains("error: linking with")
+            || l.contains("linker `")
+    }) {
+        FailureKind::Infra
+    } else if lines().any(|l| l.contains("error[E") || l.contains("error: could not compile")) {
+        FailureKind::CompileError
+    } else if lines().any(|l| l.contains("test result: FAILED")) {
+        FailureKind::TestFailure
+    } else if lines().any(|l| {
+        l.contains("Failed to report coverage")
+            || l.contains("Coverage is below the failure threshold")
+    }) {
+        FailureKind::CoverageError
+    } else {
+        FailureKind::Unknown
+    }
+}
+
+impl Context {
+    /// Whether dependencies are fetched before tarpaulin is ran, which then has to run offline
+    pub fn fetches(&self) -> bool {
+        self.fetch || self.no_network
+    }
+
+    /// The name of the toolchain to use if one is specified
+    pub fn toolchain_name(&self) -> Option<&str> {
+        let toolchain = self.toolchain.trim().trim_start_matches('+');
+        if toolchain.is_empty() {
+            None
+        } else {
+            Some(toolchain)
+        }
+    }
+
+    /// The `+toolchain` argument to pass to cargo if a toolchain is specified
+    pub fn toolchain_arg(&self) -> Option<String> {
+        self.toolchain_name().map(|x| format!("+{}", x))
+    }
+
+    /// The target a project should be built for, the crate's own target takes precedence
+    pub fn target_for<'a>(&'a self, spec: &'a CrateSpec) -> Option<&'a str> {
+        spec.target.as_deref().or(self.target.as_deref())
+    }
+
+    /// Whether the project is built with sccache
+    pub fn sccache_for(&self, spec: &CrateSpec) -> bool {
+        self.sccache && self.engine == Engine::Native && !spec.no_cache && !self.cross_for(spec)
+    }
+
+    /// Whether the project is ran with `cross`. That needs a target to build for and tarpaulin
+    /// ran natively through cargo
+    pub fn cross_for(&self, spec: &CrateSpec) -> bool {
+        (self.cross || spec.cross)
+            && self.target_for(spec).is_some()
+            && self.engine == Engine::Native
+            && self.tarpaulin_path.is_none()
+    }
+
+    /// Whether tarpaulin runs in a container named after the project, either with the docker
+    /// engine or through cross
+    pub fn in_container(&self, spec: &CrateSpec) -> bool {
+        self.engine == Engine::Docker || self.cross_for(spec)
+    }
+
+    /// The absolute path of the target directory shared between projects if there is one, the
+    /// docker engine builds in the container so there's never one there
+    pub fn shared_target_dir(&self) -> Option<PathBuf> {
+        if self.engine == Engine::Docker {
+            return None;
+        }
+        let dir = self.shared_target_dir.as_ref()?;
+        Some(
+            std::env::current_dir()
+                .map(|x| x.join(dir))
+                .unwrap_or_else(|_| dir.clone()),
+        )
+    }
+
+    /// The shell to run a project's scripts with, the crate's own shell takes precedence
+    pub fn shell_for(&self, spec: &CrateSpec) -> Vec<String> {
+        spec.shell
+            .clone()
+            .or_else(|| self.shell.clone())
+            .unwrap_or_else(default_shell)
+    }
+}
+
+impl CrateSpec {
+    pub fn name(&self) -> Option<&str> {
+        self.repository_url.path().split('/').next_back()
+    }
+
+    /// The directory tarpaulin should be ran in given the root of the cloned project
+    pub fn working_dir(&self, root: &Path) -> PathBuf {
+        match self.subdir.as_ref() {
+            Some(subdir) => subdir_parts(subdir).fold(root.to_path_buf(), |dir, x| dir.join(x)),
+            None => root.to_path_buf(),
+        }
+    }
+}
+
+/// Loads the context from a repos file, reporting every problem found rather than just the first
+pub fn load_context(reader: impl Read) -> Result<Context, ValidationError> {
+    let value: serde_json::Value =
+        serde_json::from_reader(reader).map_err(|e| ValidationError(vec![e.to_string()]))?;
+    match serde_json::from_value::<Context>(value.clone()) {
+        Ok(context) => {
+            context.validate()?;
+            Ok(context)
+        }
+        Err(e) => {
+            let specs = match value.get("crates").and_then(|x| x.as_array()) {
+                Some(crates) => crates
+                    .iter()
+                    .map(|x| serde_json::from_value::<CrateSpec>(x.clone()))
+                    .collect::<Vec<_>>(),
+                None => return Err(ValidationError(vec![e.to_string()])),
+            };
+            // Check the crates that did parse as well so every problem is reported in one go
+            let mut settings = value.clone();
+            settings["crates"] = serde_json::json!([]);
+            let (context, settings_error) = match serde_json::from_value::<Context>(settings) {
+                Ok(context) => (context, None),
+                Err(e) => (Context::default(), Some(e.to_string())),
+            };
+            let mut problems = context.problems(specs.iter().map(Result::as_ref).enumerate());
+            if let Some(settings_error) = settings_error {
+                problems.insert(0, settings_error);
+            }
+            if problems.is_empty() {
+                problems.push(e.to_string());
+            }
+            Err(ValidationError(problems))
+        }
+    }
+}
+
+/// Whether the subdir isn't a relative path inside the project
+pub(crate) fn subdir_escapes(subdir: &str) -> bool {
+    Path::new(subdir)
+        .components()
+        .any(|x| !matches!(x, Component::Normal(_) | Component::CurDir))
+        || subdir.starts_with('\\')
+        || subdir_parts(subdir).any(|x| x == "..")
+}
+
+/// The directories in a subdir, either separator can be used so repos files work on every
+/// platform
+pub(crate) fn subdir_parts(subdir: &str) -> impl Iterator<Item = &str> {
+    subdir
+        .split(['/', '\\'])
+        .filter(|x| !x.is_empty() && *x != ".")
+}
+
+/// Checks a shell script isn't empty and has its quotes closed
+fn check_script(script: &str) -> Result<(), &'static str> {
+    if script.trim().is_empty() {
+        return Err("is empty");
+    }
+    let mut quote = None;
+    let mut escaped = false;
+    for c in script.chars() {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some('"'), '"') => quote = None,
+            _ => {}
+        }
+    }
+    if quote.is_some() {
+        Err("has an unclosed quote")
+    } else {
+        Ok(())
+    }
+}
+
+impl Context {
+    /// Checks for problems that would cause projects to be ran incorrectly
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let problems = self.problems(self.crates.iter().map(Ok).enumerate());
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError(problems))
+        }
+    }
+
+    /// Problems with the settings and the given crates, crates that failed to parse are reported
+    /// with their error and the rest are still checked
+    fn problems<'a>(
+        &self,
+        crates: impl Iterator<Item = (usize, Result<&'a CrateSpec, &'a serde_json::Error>)>,
+    ) -> Vec<String> {
+        let mut problems = vec![];
+        let long_notes = |notes: &Option<String>| {
+            notes
+                .as_ref()
+                .map(|x| x.chars().count() > MAX_NOTES_LEN)
+                .unwrap_or(false)
+        };
+        if self.shell.as_deref().map(empty_shell).unwrap_or(false) {
+            problems.push("shell can't be empty".to_string());
+        }
+        if long_notes(&self.notes) {
+            warn!("Repos file notes are over {} characters", MAX_NOTES_LEN);
+        }
+        let mut urls = HashMap::new();
+        let mut names = HashMap::new();
+        let mut listed = false;
+        for (i, spec) in crates {
+            listed = true;
+            let spec = match spec {
+                Ok(spec) => spec,
+                Err(e) => {
+                    problems.push(format!("crate {}: {}", i, e));
+                    continue;
+                }
+            };
+            let url = spec.repository_url.as_str().trim_end_matches('/');
+            if let Some(first) = urls.insert(url, i) {
+                problems.push(format!(
+                    "crate {}: duplicate of crate {} repository {}",
+                    i, first, url
+                ));
+                continue;
+            }
+            match spec.name() {
+                Some(name) if !name.is_empty() => {
+                    if let Some(first) = names.insert(name, i) {
+                        problems.push(format!(
+                            "crate {}: same project directory '{}' as crate {}",
+                            i, name, first
+                        ));
+                    }
+                }
+                _ => problems.push(format!(
+                    "crate {}: can't get a project name from {}",
+                    i, spec.repository_url
+                )),
+            }
+            if let Some(subdir) = spec.subdir.as_ref() {
+                if subdir_escapes(subdir) {
+                    problems.push(format!(
+                        "crate {}: subdir {} must be relative and inside the project",
+                        i, subdir
+                    ));
+                }
+            }
+            if spec.shell.as_deref().map(empty_shell).unwrap_or(false) {
+                problems.push(format!("crate {}: shell can't be empty", i));
+            }
+            if long_notes(&spec.notes) {
+                warn!("crate {}: notes are over {} characters", i, MAX_NOTES_LEN);
+            }
+            let scripts = [("setup", &spec.setup), ("teardown", &spec.teardown)];
+            for (kind, script) in scripts.iter() {
+                if let Some(Err(e)) = script.as_deref().map(check_script) {
+                    problems.push(format!("crate {}: {} script {}", i, kind, e));
+                }
+            }
+        }
+        if !listed {
+            problems.insert(0, "No crates listed".to_string());
+        }
+        problems
+    }
+}
+
+/// Waits for a child process to finish collecting its output. If a timeout is provided and the
+/// process hasn't finished within it the process is killed and a `TimedOut` error is returned.
+/// If a shutdown signal is given the process is killed with an `Interrupted` error once it asks to
+/// stop now.
+fn wait_with_timeout(
+    mut child: Child,
+    timeout: Option<Duration>,
+    shutdown: Option<&ShutdownSignal>,
+) -> io::Result<Output> {
+    let (stdout_tx, stdout_rx) = mpsc::channel();
+    let (stderr_tx, stderr_rx) = mpsc::channel();
+    if let Some(mut stdout) = child.stdout.take() {
+        thread::spawn(move || {
+            let mut output = vec![];
+            let _ = stdout.read_to_end(&mut output);
+            let _ = stdout_tx.send(output);
+        });
+    }
+    if let Some(mut stderr) = child.stderr.take() {
+        thread::spawn(move || {
+            let mut output = vec![];
+            let _ = stderr.read_to_end(&mut output);
+            let _ = stderr_tx.send(output);
+        });
+    }
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if shutdown.map(ShutdownSignal::requested) == Some(Shutdown::Now) {
+            kill_group(&mut child);
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "Stopped by ctrl-c",
+            ));
+        }
+        if let Some(timeout) = timeout {
+            if start.elapsed() > timeout {
+                kill_group(&mut child);
+                let _ = child.wait();
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Timed out after {}s", timeout.as_secs()),
+                ));
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    // Anything left running in the background by a script can keep the pipes open, so don't wait
+    // on them forever
+    let grace = Duration::from_secs(1);
+    Ok(Output {
+        status,
+        stdout: stdout_rx.recv_timeout(grace).unwrap_or_default(),
+        stderr: stderr_rx.recv_timeout(grace).unwrap_or_default(),
+    })
+}
+
+fn is_network_error(stderr: &str) -> bool {
+    const NETWORK_ERRORS: &[&str] = &[
+        "spurious network error",
+        "failed to download",
+        "failed to fetch",
+        "failed to get",
+        "Couldn't resolve host",
+        "Could not resolve host",
+        "Timeout was reached",
+        "Connection reset",
+        "Connection refused",
+    ];
+    NETWORK_ERRORS.iter().any(|e| stderr.contains(e))
+}
+
+/// Makes sure every target used in the context is installed for the toolchain
+pub fn install_targets(context: &Context) {
+    let mut targets = context
+        .crates
+        .iter()
+        .filter_map(|x| context.target_for(x))
+        .collect::<Vec<_>>();
+    targets.sort_unstable();
+    targets.dedup();
+    if targets.is_empty() {
+        return;
+    }
+    let mut list = new_command("rustup");
+    list.args(["target", "list", "--installed"]);
+    if let Some(toolchain) = context.toolchain_name() {
+        list.args(["--toolchain", toolchain]);
+    }
+    let installed = match list.output() {
+        Ok(out) => String::from_utf8_lossy(&out.stdout).to_string(),
+        Err(e) => {
+            warn!("Unable to list installed targets: {}", e);
+            return;
+        }
+    };
+    for target in targets
+        .iter()
+        .filter(|t| !installed.lines().any(|x| x.trim() == **t))
+    {
+        info!("Installing target {}", target);
+        let mut add = new_command("rustup");
+        add.args(["target", "add", target]);
+        if let Some(toolchain) = context.toolchain_name() {
+            add.args(["--toolchain", toolchain]);
+        }
+        match add.output() {
+            Ok(out) if out.status.success() => {}
+            _ => error!("Failed to install target {}", target),
+        }
+    }
+}
+
+fn fetch_dependencies(proj_dir: &Path, context: &Context, spec: &CrateSpec) -> FetchOutcome {
+    let start = Instant::now();
+    let mut attempts = 0;
+    let mut error = None;
+    while attempts < FETCH_ATTEMPTS {
+        attempts += 1;
+        let mut cmd = new_command("cargo");
+        if let Some(toolchain) = context.toolchain_arg() {
+            cmd.arg(toolchain);
+        }
+        cmd.arg("fetch");
+        if let Some(target) = context.target_for(spec) {
+            cmd.args(["--target", target]);
+        }
+        match cmd.current_dir(spec.working_dir(proj_dir)).output() {
+            Ok(out) if out.status.success() => {
+                error = None;
+                break;
+            }
+            Ok(out) => {
+                let stderr = String::from_utf8_lossy(&out.stderr);
+                error = Some(stderr.trim().to_string());
+                if !is_network_error(&stderr) {
+                    break;
+                } else if attempts < FETCH_ATTEMPTS {
+                    warn!("Network error fetching dependencies, retrying");
+                    thread::sleep(Duration::from_secs(10 * attempts as u64));
+                }
+            }
+            Err(e) => {
+                error = Some(format!("Failed to spawn cargo fetch: {}", e));
+                break;
+            }
+        }
+    }
+    FetchOutcome {
+        success: error.is_none(),
+        attempts,
+        duration_secs: start.elapsed().as_secs_f64(),
+        error,
+    }
+}
+
+/// The cargo-tarpaulin binary given with `tarpaulin_path`, recorded so results can be traced back
+/// to the build that produced them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TarpaulinBinary {
+    pub path: PathBuf,
+    /// Output of `cargo-tarpaulin --version`
+    pub version: String,
+    /// When the binary was last modified in seconds since the unix epoch
+    pub modified: u64,
+}
+
+impl TarpaulinBinary {
+    /// Checks the binary exists and is executable and gets its version
+    pub fn inspect(path: &Path) -> Result<Self, String> {
+        let metadata = std::fs::metadata(path)
+            .map_err(|e| format!("Can't access {}: {}", path.display(), e))?;
+        if !metadata.is_file() {
+            return Err(format!("{} isn't a file", path.display()));
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if metadata.permissions().mode() & 0o111 == 0 {
+                return Err(format!("{} isn't executable", path.display()));
+            }
+        }
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|x| x.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+        let output = new_command(path)
+            .arg("--version")
+            .output()
+            .map_err(|e| format!("Failed to run {}: {}", path.display(), e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} --version failed: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            version: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+            modified,
+        })
+    }
+}
+
+/// Sends a command's stdout and stderr to files in the results directory instead of piping them,
+/// returning the paths of the files
+fn redirect_output(cmd: &mut Command, proj_res: &Path) -> io::Result<(PathBuf, PathBuf)> {
+    let stdout = proj_res.join("stdout.log");
+    let stderr = proj_res.join("stderr.log");
+    cmd.stdout(Stdio::from(File::create(&stdout)?))
+        .stderr(Stdio::from(File::create(&stderr)?));
+    Ok((stdout, stderr))
+}
+
+/// Turns a command into the program followed by its arguments
+pub fn command_line(cmd: &Command) -> Vec<String> {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|x| x.to_string_lossy().to_string())
+        .collect()
+}
+
+/// Resolves the command tarpaulin would be ran with for a project without running anything. If
+/// `clone` is false and the project isn't already cloned CI detection won't find any configs and
+/// the default command is returned
+pub fn resolve_command(
+    context: &Context,
+    proj: &CrateSpec,
+    jobs: Option<usize>,
+    projects: &Path,
+    clone: bool,
+) -> Result<Command, RunError> {
+    detect_project_command(context, proj, jobs, projects, clone).map(|x| x.command)
+}
+
+/// Resolves the command like `resolve_command`, keeping which CI config it came from
+pub fn detect_project_command(
+    context: &Context,
+    proj: &CrateSpec,
+    jobs: Option<usize>,
+    projects: &Path,
+    clone: bool,
+) -> Result<ci::Detection, RunError> {
+    let proj_name = proj.name().unwrap_or("unnamed_project");
+    let span = info_span!("crate", name = proj_name);
+    let _enter = span.enter();
+    let proj_dir = projects.join(proj_name);
+    if clone {
+        git::prepare_project(
+            projects,
+            proj.repository_url.as_str(),
+            proj_name,
+            context.use_system_git,
+        )
+        .map_err(RunError::Git)?;
+    }
+    let overridden = with_override(context, proj)?;
+    let proj = match with_manifest_dir(&proj_dir, overridden.clone()) {
+        Some(x) => x,
+        None => {
+            warn!("No Cargo.toml found, {} isn't a rust project", proj_name);
+            overridden
+        }
+    };
+    let detection = ci::detect_tarpaulin_command(&proj_dir, jobs, context, &proj);
+    info!(
+        "{} would run {:?} in {} ({})",
+        proj_name,
+        command_line(&detection.command),
+        detection
+            .command
+            .get_current_dir()
+            .unwrap_or(&proj_dir)
+            .display(),
+        detection.provider
+    );
+    Ok(detection)
+}
+
+/// How deep into `target` to look for tarpaulin logs, build artefacts are deeper than this
+const LOG_SEARCH_DEPTH: usize = 2;
+/// How far up the process tree to look for tarpaulin when checking if a process descends from it
+const MAX_PROCESS_DEPTH: usize = 64;
+
+/// How often to check if tarpaulin finished or the run is stopping while waiting on it
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How often tarpaulin's CPU usage is checked to see if it's stalled
+const STALL_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What ctrl-c has asked the run to do
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Shutdown {
+    Continue,
+    /// Pressed once, finish the current crate then pause
+    AfterCrate,
+    /// Pressed twice, kill tarpaulin, run the teardown and pause
+    Now,
+}
+
+impl Shutdown {
+    fn from_requests(requests: usize) -> Self {
+        match requests {
+            0 => Self::Continue,
+            1 => Self::AfterCrate,
+            _ => Self::Now,
+        }
+    }
+}
+
+/// Counts the requests to stop a run, clones share the count so one can be given to a ctrl-c
+/// handler while the run checks another
+#[derive(Debug, Default, Clone)]
+pub struct ShutdownSignal(Arc<AtomicUsize>);
+
+impl ShutdownSignal {
+    /// Records a ctrl-c, returning what the run should now do
+    pub fn request(&self) -> Shutdown {
+        Shutdown::from_requests(self.0.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    pub fn requested(&self) -> Shutdown {
+        Shutdown::from_requests(self.0.load(Ordering::SeqCst))
+    }
+
+    /// Forgets any ctrl-c so a resumed run carries on
+    pub fn reset(&self) {
+        self.0.store(0, Ordering::SeqCst);
+    }
+}
+
+/// Contexts are compared by their configuration, the signal is just how a run is stopped
+impl PartialEq for ShutdownSignal {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for ShutdownSignal {}
+
+/// Puts the command in its own process group so everything it spawns can be killed along with it,
+/// and so a ctrl-c in the terminal goes to tater alone rather than straight to its children
+fn own_process_group(cmd: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x200;
+        cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+}
+
+/// Command for any child tater spawns. Children are in their own process group so a ctrl-c only
+/// stops them once tater decides to, letting a graceful shutdown finish the crates in progress
+pub(crate) fn new_command(program: impl AsRef<OsStr>) -> Command {
+    let mut cmd = Command::new(program);
+    own_process_group(&mut cmd);
+    cmd
+}
+
+/// Kills the child along with everything else in its process group
+fn kill_group(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+    }
+    let _ = child.kill();
+}
+
+/// Job object tarpaulin is assigned to on windows, anything tarpaulin spawns joins the job too
+#[cfg(windows)]
+struct JobObject(winapi::um::winnt::HANDLE);
+
+#[cfg(windows)]
+impl JobObject {
+    /// Creates a job for the child that kills everything in it once the job is closed. Anything
+    /// the child spawned before it was assigned isn't in the job, tarpaulin takes long enough to
+    /// start cargo that this doesn't matter
+    fn assign(child: &Child) -> Option<Self> {
+        use std::os::windows::io::AsRawHandle;
+        use winapi::um::jobapi2::{
+            AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject,
+        };
+        use winapi::um::winnt::{
+            JobObjectExtendedLimitInformation, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+            JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        };
+        unsafe {
+            let job = CreateJobObjectW(std::ptr::null_mut(), std::ptr::null());
+            if job.is_null() {
+                return None;
+            }
+            let job = Self(job);
+            let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+            limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            SetInformationJobObject(
+                job.0,
+                JobObjectExtendedLimitInformation,
+                &mut limits as *mut _ as *mut _,
+                std::mem::size_of_val(&limits) as u32,
+            );
+            if AssignProcessToJobObject(job.0, child.as_raw_handle() as _) == 0 {
+                return None;
+            }
+            Some(job)
+        }
+    }
+
+    fn terminate(&self) -> bool {
+        unsafe { winapi::um::jobapi2::TerminateJobObject(self.0, 1) != 0 }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for JobObject {
+    fn drop(&mut self) {
+        unsafe {
+            winapi::um::handleapi::CloseHandle(self.0);
+        }
+    }
+}
+
+/// Tarpaulin along with everything it spawns so they can be killed together. On unix tarpaulin
+/// leads its own process group, on windows it's assigned to a job object
+struct ProcessTree {
+    pid: u32,
+    /// Every process seen below tarpaulin while it ran
+    seen: HashSet<sysinfo::Pid>,
+    #[cfg(windows)]
+    job: Option<JobObject>,
+}
+
+impl ProcessTree {
+    /// The child must have been spawned with `own_process_group`
+    fn new(child: &Child) -> Self {
+        Self {
+            pid: child.id(),
+            seen: HashSet::new(),
+            #[cfg(windows)]
+            job: JobObject::assign(child),
+        }
+    }
+
+    /// Kills every process in the tree, true if there was anything to kill
+    #[cfg(unix)]
+    fn kill(&self) -> bool {
+        // Succeeds if anything is left in the group, including tarpaulin if it hasn't been waited on
+        unsafe { libc::killpg(self.pid as libc::pid_t, libc::SIGKILL) == 0 }
+    }
+
+    #[cfg(windows)]
+    fn kill(&self) -> bool {
+        self.job.as_ref().map(|x| x.terminate()).unwrap_or(false)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn kill(&self) -> bool {
+        false
+    }
+
+    /// CPU usage of tarpaulin and everything below it since they were last checked, `None` if
+    /// sysinfo doesn't support this platform in which case stalls aren't detected. Tarpaulin
+    /// mostly waits on cargo, rustc and the test binaries so its own usage says little
+    fn cpu_usage(&mut self, system: &mut System) -> Option<f32> {
+        if !System::IS_SUPPORTED {
+            return None;
+        }
+        system.refresh_processes();
+        let processes = system.processes();
+        let tarp_pid = self.pid as sysinfo::Pid;
+        let tarpaulin = processes.get(&tarp_pid)?;
+        let mut usage = tarpaulin.cpu_usage();
+        for (pid, process) in processes {
+            if descends_from(processes, *pid, tarp_pid) {
+                self.seen.insert(*pid);
+                usage += process.cpu_usage();
+            }
+        }
+        Some(usage)
+    }
+
+    /// Tarpaulin and everything seen below it
+    fn pids(&self) -> Vec<sysinfo::Pid> {
+        let mut pids = vec![self.pid as sysinfo::Pid];
+        pids.extend(self.seen.iter().copied());
+        pids
+    }
+}
+
+/// Whether the process is somewhere below the ancestor in the process tree
+fn descends_from(
+    processes: &HashMap<sysinfo::Pid, sysinfo::Process>,
+    mut pid: sysinfo::Pid,
+    ancestor: sysinfo::Pid,
+) -> bool {
+    for _ in 0..MAX_PROCESS_DEPTH {
+        match processes.get(&pid).and_then(|x| x.parent()) {
+            Some(parent) if parent == ancestor => return true,
+            Some(parent) => pid = parent,
+            None => break,
+        }
+    }
+    false
+}
+
+/// Kills anything tarpaulin left running after it exited or was killed. Leftover `cargo`,
+/// `rustc` or test binaries can hold the package cache lock and stall the next project. Tarpaulin's
+/// process group is killed first, then anything else still descended from tarpaulin. Processes
+/// outside of tarpaulin's tree are left alone even if they're in the project directory, they could
+/// be a user's shell or editor. Returns what was reaped
+#[cfg(unix)]
+fn reap_orphans(tree: &ProcessTree) -> Vec<String> {
+    let mut reaped = vec![];
+    if tree.kill() {
+        reaped.push(format!("process group {}", tree.pid));
+    }
+    let mut system = System::new();
+    system.refresh_processes();
+    let processes = system.processes();
+    let tarp_pid = tree.pid as sysinfo::Pid;
+    let own_pid = std::process::id();
+    let own_pid = own_pid as sysinfo::Pid;
+    for (pid, process) in processes {
+        if *pid == own_pid || *pid == tarp_pid {
+            continue;
+        }
+        if descends_from(processes, *pid, tarp_pid)
+            && unsafe { libc::kill(*pid, libc::SIGKILL) } == 0
+        {
+            reaped.push(format!("{} ({})", process.name(), pid));
+        }
+    }
+    reaped
+}
+
+#[cfg(not(unix))]
+fn reap_orphans(tree: &ProcessTree) -> Vec<String> {
+    // Terminating a job succeeds even if it's empty so there's nothing worth reporting
+    tree.kill();
+    vec![]
+}
+
+/// Reaps anything tarpaulin left running and logs it
+fn reap_tarpaulin(tree: &ProcessTree) {
+    let reaped = reap_orphans(tree);
+    if !reaped.is_empty() {
+        warn!(
+            "Killed processes left behind by tarpaulin: {}",
+            reaped.join(", ")
+        );
+    }
+}
+
+/// Finds tarpaulin's debug logs in the directory tarpaulin was ran in or its target directory,
+/// newest first
+fn find_tarpaulin_logs(dir: &Path) -> Vec<(PathBuf, SystemTime)> {
+    fn search(dir: &Path, depth: usize, logs: &mut Vec<(PathBuf, SystemTime)>) {
+        let entries = match read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if path.is_dir() {
+                if depth > 0 && (depth < LOG_SEARCH_DEPTH || name == "target") {
+                    search(&path, depth - 1, logs);
+                }
+            } else if name.starts_with("tarpaulin-run") && name.ends_with(".json") {
+                let modified = entry
+                    .metadata()
+                    .and_then(|x| x.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                logs.push((path, modified));
+            }
+        }
+    }
+    let mut logs = vec![];
+    search(dir, LOG_SEARCH_DEPTH, &mut logs);
+    logs.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+    logs
+}
+
+/// Coverage reports tarpaulin writes to the directory it's ran in when asked to with `--out`
+const REPORT_FILES: &[&str] = &[
+    "tarpaulin-report.html",
+    JSON_REPORT,
+    "lcov.info",
+    "cobertura.xml",
+];
+/// Directory in the project results `target/tarpaulin` is copied to
+const TARGET_ARTIFACTS_DIR: &str = "target-tarpaulin";
+
+/// Copies a directory's contents into `dest`, returning how many files were copied
+fn copy_dir(src: &Path, dest: &Path) -> io::Result<usize> {
+    create_dir_all(dest)?;
+    let mut copied = 0;
+    for entry in read_dir(src)?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            copied += copy_dir(&path, &dest.join(entry.file_name()))?;
+        } else {
+            copy(&path, dest.join(entry.file_name()))?;
+            copied += 1;
+        }
+    }
+    Ok(copied)
+}
+
+/// Copies what tarpaulin left in the project into the results before the target directory is
+/// cleaned up. That's every `tarpaulin-run*` log, which are moved so the next run doesn't pick
+/// them up, any coverage reports and everything in `target/tarpaulin`. The working directory is
+/// searched as well as the project root as they differ for projects in a subdir, along with the
+/// shared target directory if there is one. Returns whether any tarpaulin logs were found
+fn collect_artifacts(
+    working_dir: &Path,
+    proj_dir: &Path,
+    shared_target: Option<&Path>,
+    proj_res: &Path,
+) -> bool {
+    let mut dirs = vec![working_dir];
+    if working_dir != proj_dir {
+        dirs.push(proj_dir);
+    }
+    let mut found_log = false;
+    for (log, _) in dirs.iter().flat_map(|x| find_tarpaulin_logs(x)) {
+        if copy(&log, proj_res.join(log.file_name().unwrap_or_default())).is_ok() {
+            let _ = remove_file(&log);
+            found_log = true;
+        } else {
+            warn!(
+                "Failed to copy {}, still in project directory",
+                log.display()
+            );
+        }
+    }
+    for report in dirs
+        .iter()
+        .flat_map(|x| REPORT_FILES.iter().map(move |f| x.join(f)))
+    {
+        if report.is_file()
+            && copy(
+                &report,
+                proj_res.join(report.file_name().unwrap_or_default()),
+            )
+            .is_ok()
+        {
+            let _ = remove_file(&report);
+        }
+    }
+    let targets = dirs
+        .iter()
+        .map(|x| x.join("target"))
+        .chain(shared_target.map(PathBuf::from));
+    for target in targets.map(|x| x.join("tarpaulin")) {
+        if target.is_dir() {
+            match copy_dir(&target, &proj_res.join(TARGET_ARTIFACTS_DIR)) {
+                Ok(copied) => info!("Copied {} files from {}", copied, target.display()),
+                Err(e) => warn!("Failed to copy {}: {}", target.display(), e),
+            }
+        }
+    }
+    found_log
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
-pub struct CrateSpec {
-    #[serde(with = "url_serde")]
-    pub repository_url: Url,
-    #[serde(default)]
-    pub args: Vec<String>,
-    #[serde(default)]
-    pub env: HashMap<String, String>,
-    /// For anything that requires something like another server to be up and running
-    /// This is going to be executed like `sh -c CrateSpec::setup` so not great but :shrug:
-    #[serde(default)]
-    pub setup: Option<String>,
-    /// To tear down any addition things that need running.
-    #[serde(default)]
-    pub teardown: Option<String>,
+/// Runs `cargo test` with the same arguments tarpaulin was ran with, the output is written to
+/// `cargo-test.log` in the project results
+fn run_baseline_test(
+    proj_dir: &Path,
+    proj_res: &Path,
+    jobs: Option<usize>,
+    context: &Context,
+    proj: &CrateSpec,
+) -> BaselineResult {
+    let mut cmd = ci::cargo_test_command(proj_dir, jobs, context, proj);
+    info!("Running baseline test: {:?}", cmd);
+    let res = cmd.spawn().and_then(|child| {
+        wait_with_timeout(child, Some(BASELINE_TEST_TIMEOUT), Some(&context.shutdown))
+    });
+    write_script_log(&proj_res.join("cargo-test.log"), &res);
+    match res {
+        Ok(output) if output.status.success() => {
+            warn!("cargo test passes, failure only happens with tarpaulin");
+            BaselineResult::TarpaulinOnly
+        }
+        _ => {
+            info!("cargo test fails too");
+            BaselineResult::CargoTestToo
+        }
+    }
 }
 
-#[derive(Error, Debug)]
-pub enum RunError {
-    #[error("Issue cloning repo: {0}")]
-    Git(String),
-    #[error("Failed to run setup script: {0}")]
-    Setup(io::Error),
-    #[error("Failed to run tarpaulin: {0}")]
-    Tarpaulin(String),
-    #[error("Tarpaulin seems to have stalled")]
-    Stalled,
-    #[error("Tarpaulin exited with a failure")]
-    Failed,
+/// A project ran by [`run_all`]
+#[derive(Debug)]
+pub struct CrateRun {
+    pub name: String,
+    pub outcome: RunOutcome,
 }
 
-/// This is to make it easier to clean up the project after exiting from running the test with an
-/// error
-struct ProjectCleanupGuard<'a>(&'a Path);
+/// A run of a project [`run_all_with`] is told to do next
+#[derive(Debug, Clone)]
+pub struct PlannedRun<'a> {
+    /// Index of the project in the repos file
+    pub index: usize,
+    /// The project's spec with anything changed for this run applied
+    pub spec: Cow<'a, CrateSpec>,
+    /// Name the run and its events are reported as, a project can be ran more than once in a run
+    pub name: String,
+    /// Where the run's logs and results are kept
+    pub results: PathBuf,
+    /// The project's already been cloned or updated for this run, such as by a prefetcher, so
+    /// it's not done again
+    pub prepared: bool,
+}
 
-impl<'a> Drop for ProjectCleanupGuard<'a> {
-    fn drop(&mut self) {
-        let _ = remove_dir_all(self.0.join("target"));
-    }
+/// Decides what [`run_all_with`] runs and is told how each run went, this is where a wrapper
+/// keeps its own bookkeeping such as resuming, status files or a dashboard
+pub trait RunHooks<'a> {
+    /// The next project to run, the run ends once there's none
+    fn next_run(&mut self) -> Option<PlannedRun<'a>>;
+
+    /// Progress of the project being ran
+    fn event(&self, _event: RunEvent) {}
+
+    fn finished(&mut self, run: PlannedRun<'a>, outcome: RunOutcome);
 }
 
-impl CrateSpec {
-    pub fn name(&self) -> Option<&str> {
-        self.repository_url.path().split('/').next_back()
+/// Runs tarpaulin on the projects given by `hooks` until it runs out, cloning them into
+/// `projects`. Errors before running anything if sccache is wanted but can't be started
+pub fn run_all_with<'a>(
+    context: &Context,
+    jobs: Option<usize>,
+    projects: &Path,
+    hooks: &mut dyn RunHooks<'a>,
+) -> Result<(), String> {
+    let _ = create_dir_all(projects);
+    if context.engine == Engine::Native {
+        install_targets(context);
+    }
+    let _sccache = sccache::Server::start_for(context)?;
+    while let Some(run) = hooks.next_run() {
+        let outcome = {
+            let hooks = &*hooks;
+            run_planned(context, &run, jobs, projects, &|event: RunEvent| {
+                hooks.event(event.renamed(&run.name))
+            })
+        };
+        hooks.finished(run, outcome);
     }
+    Ok(())
 }
 
-fn clone_project(
-    projects: impl AsRef<Path>,
-    repository_url: &str,
-    proj_name: &str,
-) -> Result<(), String> {
-    let git_hnd = Command::new("git")
-        .args(&[
-            "clone",
-            "--recurse-submodules",
-            "--depth",
-            "1",
-            repository_url,
-            proj_name,
-        ])
-        .current_dir(projects)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("Failed to spawn git {}", e))?;
+/// Runs every project in the context that isn't skipped, collecting the outcomes
+struct AllCrates<'a, 'e> {
+    crates: std::iter::Enumerate<std::slice::Iter<'a, CrateSpec>>,
+    results: PathBuf,
+    events: &'e dyn Fn(RunEvent),
+    runs: Vec<CrateRun>,
+}
 
-    let git = git_hnd
-        .wait_with_output()
-        .map_err(|e| format!("Git may not be installed: {}", e))?;
+impl<'a, 'e> RunHooks<'a> for AllCrates<'a, 'e> {
+    fn next_run(&mut self) -> Option<PlannedRun<'a>> {
+        let (index, proj) = self
+            .crates
+            .find(|(_, proj)| !proj.skip && proj.expected != Expectation::Skip)?;
+        let name = proj.name().unwrap_or("unnamed_project").to_string();
+        Some(PlannedRun {
+            index,
+            spec: Cow::Borrowed(proj),
+            results: self.results.join(&name),
+            name,
+            prepared: false,
+        })
+    }
 
-    if !git.status.success() {
-        Err(format!("Git clone of {} failed", repository_url))
-    } else {
-        info!("{} cloned successfully", proj_name);
-        Ok(())
+    fn event(&self, event: RunEvent) {
+        (self.events)(event)
+    }
+
+    fn finished(&mut self, run: PlannedRun<'a>, outcome: RunOutcome) {
+        self.runs.push(CrateRun {
+            name: run.name,
+            outcome,
+        });
+    }
+}
+
+/// Runs tarpaulin on every project in the context that isn't marked skip, cloning them into
+/// `output/projects` and keeping their logs in `output/results`. Unlike the tater binary nothing
+/// else is written, there's no status files, reports or resuming, the outcomes are returned
+/// instead and progress is sent to `events`. Use [`run_all_with`] to choose what's ran
+pub fn run_all(
+    context: &Context,
+    jobs: Option<usize>,
+    output: &Path,
+    events: &dyn Fn(RunEvent),
+) -> Vec<CrateRun> {
+    let mut hooks = AllCrates {
+        crates: context.crates.iter().enumerate(),
+        results: output.join("results"),
+        events,
+        runs: vec![],
+    };
+    if let Err(e) = run_all_with(context, jobs, &output.join("projects"), &mut hooks) {
+        error!("{}", e);
     }
+    hooks.runs
 }
 
-#[instrument(skip(i, context, proj, jobs, projects, results), fields(project=%proj.repository_url))]
+/// Runs tarpaulin on a project
 pub fn run_test(
     i: usize,
     context: &Context,
     proj: &CrateSpec,
-    jobs: Option<&usize>,
+    jobs: Option<usize>,
+    projects: &Path,
+    proj_res: &Path,
+) -> RunOutcome {
+    run_test_with_events(i, context, proj, jobs, projects, proj_res, &|_| {})
+}
+
+/// Runs tarpaulin on a project sending its progress to `events`, everything logged while running
+/// it including from the CI backends is in a span carrying the project's name and index
+pub fn run_test_with_events(
+    i: usize,
+    context: &Context,
+    proj: &CrateSpec,
+    jobs: Option<usize>,
+    projects: &Path,
+    proj_res: &Path,
+    events: &dyn Fn(RunEvent),
+) -> RunOutcome {
+    let run = PlannedRun {
+        index: i,
+        spec: Cow::Borrowed(proj),
+        name: proj.name().unwrap_or("unnamed_project").to_string(),
+        results: proj_res.to_path_buf(),
+        prepared: false,
+    };
+    run_planned(context, &run, jobs, projects, events)
+}
+
+#[instrument(
+    name = "crate",
+    skip(context, run, jobs, projects, events),
+    fields(name = run.spec.name().unwrap_or("unnamed_project"), index = run.index)
+)]
+fn run_planned(
+    context: &Context,
+    run: &PlannedRun,
+    jobs: Option<usize>,
+    projects: &Path,
+    events: &dyn Fn(RunEvent),
+) -> RunOutcome {
+    let i = run.index;
+    let name = run.spec.name().unwrap_or("unnamed_project").to_string();
+    info!("{}. {}/{}", name, i + 1, context.crates.len());
+    events(RunEvent::CrateStarted {
+        index: i,
+        name: name.clone(),
+    });
+    let start = Instant::now();
+    let mut outcome = RunOutcome::default();
+    let res = run_project(context, run, jobs, projects, &mut outcome, events);
+    outcome.timings.total = start.elapsed().as_secs_f64();
+    outcome.error = res.err();
+    let coverage_pct = match outcome.coverage.as_ref() {
+        Some(CoverageReport::Found(c)) => Some(c.percentage),
+        _ => None,
+    };
+    events(RunEvent::Result {
+        name,
+        status: outcome.status(),
+        coverage_pct,
+    });
+    outcome
+}
+
+fn run_project(
+    context: &Context,
+    run: &PlannedRun,
+    jobs: Option<usize>,
     projects: &Path,
-    results: &Path,
+    outcome: &mut RunOutcome,
+    events: &dyn Fn(RunEvent),
 ) -> Result<(), RunError> {
-    let proj_name = proj.name().unwrap_or_else(|| "unnamed_project");
+    let proj = run.spec.as_ref();
+    let proj_res = run.results.as_path();
+    let proj_name = proj.name().unwrap_or("unnamed_project");
     let proj_dir = projects.join(proj_name);
-    info!("{}. {}/{}", proj_name, i + 1, context.crates.len());
-    if proj_dir.join(".git").exists() {
-        warn!("Project already cloned, using existing version");
-    } else {
-        clone_project(&projects, proj.repository_url.as_str(), proj_name)
-            .map_err(|e| RunError::Git(e))?
+    if !run.prepared {
+        let start = Instant::now();
+        let res = git::prepare_project(
+            projects,
+            proj.repository_url.as_str(),
+            proj_name,
+            context.use_system_git,
+        );
+        outcome.timings.clone = start.elapsed().as_secs_f64();
+        res.map_err(RunError::Git)?;
     }
+    events(RunEvent::Cloned {
+        name: proj_name.to_string(),
+    });
+    outcome.commit = git::head_commit(&proj_dir);
+    let overridden = match with_manifest_dir(&proj_dir, with_override(context, proj)?) {
+        Some(x) => x,
+        None => {
+            warn!("No Cargo.toml found, skipping as it isn't a rust project");
+            outcome.not_rust = true;
+            return Ok(());
+        }
+    };
+    let proj = overridden.as_ref();
+
+    let shared_target = context.shared_target_dir();
+    let _guard = ProjectCleanupGuard {
+        proj_dir: &proj_dir,
+        keep: context.keep_target,
+        shared_target: shared_target.as_deref(),
+    };
+    let _ = create_dir_all(proj_res);
 
-    let _guard = ProjectCleanupGuard(&proj_dir);
+    if context.fetches() {
+        let fetch = fetch_dependencies(&proj_dir, context, proj);
+        outcome.timings.fetch = fetch.duration_secs;
+        info!(
+            "Fetch finished in {:.1}s after {} attempt(s)",
+            fetch.duration_secs, fetch.attempts
+        );
+        if let Ok(f) = File::create(proj_res.join("fetch.json")) {
+            let _ = serde_json::to_writer_pretty(f, &fetch);
+        }
+        if let Some(e) = fetch.error {
+            error!("fetch failed for {}", proj_name);
+            return Err(RunError::Fetch(e));
+        }
+    }
 
+    let shell = context.shell_for(proj);
     if let Some(setup) = proj.setup.as_ref() {
-        let res = Command::new("sh")
-            .args(&["-c", setup])
-            .current_dir(&proj_dir)
-            .output();
+        let start = Instant::now();
+        let timeout = proj
+            .setup_timeout_seconds
+            .or(context.script_timeout_seconds)
+            .map(Duration::from_secs);
+        let res = run_script(&shell, setup, &proj_dir, timeout, Some(&context.shutdown));
+        outcome.timings.setup = start.elapsed().as_secs_f64();
+        write_script_log(&proj_res.join("setup.log"), &res);
+        let res = res.and_then(|output| {
+            info!(
+                "setup output:\n{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(io::Error::other(format!("exited with {}", output.status)))
+            }
+        });
         if let Err(res) = res {
-            error!("setup failed for {}", proj_name);
+            if res.kind() == io::ErrorKind::Interrupted {
+                return Err(RunError::Interrupted);
+            }
+            error!("setup failed for {}: {}", proj_name, res);
             return Err(RunError::Setup(res));
         }
     }
+    events(RunEvent::SetupDone {
+        name: proj_name.to_string(),
+    });
+    let teardown_guard = TeardownGuard {
+        shell: &shell,
+        timeout: proj
+            .teardown_timeout_seconds
+            .or(context.script_timeout_seconds)
+            .map(Duration::from_secs),
+        proj_dir: &proj_dir,
+        proj_res,
+        teardown: proj.teardown.as_deref(),
+    };
 
-    let mut tarp =
-        ci::spawn_tarpaulin(&proj_dir, jobs, &context, &proj).expect("Unable to spawn process");
+    let sccache_before = if context.sccache_for(proj) {
+        sccache::stats()
+    } else {
+        None
+    };
+    let tarpaulin_start = Instant::now();
+    let mut cmd = ci::tarpaulin_command(&proj_dir, jobs, context, proj);
+    outcome.command = command_line(&cmd);
+    outcome.env = cmd
+        .get_envs()
+        .filter_map(|(k, v)| {
+            v.map(|v| {
+                (
+                    k.to_string_lossy().to_string(),
+                    v.to_string_lossy().to_string(),
+                )
+            })
+        })
+        .collect();
+    let output_files = if context.output_to_files {
+        let files = redirect_output(&mut cmd, proj_res).map_err(|e| {
+            RunError::Tarpaulin(format!("Failed to create tarpaulin output files: {}", e))
+        })?;
+        Some(files)
+    } else {
+        None
+    };
+    info!("Spawning: {:?}", cmd);
+    let mut tarp = cmd
+        .spawn()
+        .map_err(|e| RunError::Tarpaulin(format!("Failed to spawn tarpaulin: {}", e)))?;
+    let mut tarp_tree = ProcessTree::new(&tarp);
 
-    let system = System::default();
-    // I need to take the stdout and stderr and start writing them now instead...
-    let mut stdout = tarp.stdout.take().unwrap();
-    let mut stderr = tarp.stderr.take().unwrap();
+    let mut system = System::new();
+    // Only set when the output is piped rather than going to files
+    let stdout = tarp.stdout.take();
+    let stderr = tarp.stderr.take();
 
     let stdout_reading = thread::spawn(move || {
         let mut output = vec![];
-        let _ = stdout.read_to_end(&mut output);
+        if let Some(mut stdout) = stdout {
+            let _ = stdout.read_to_end(&mut output);
+        }
         output
     });
 
     let stderr_reading = thread::spawn(move || {
         let mut output = vec![];
-        let _ = stderr.read_to_end(&mut output);
+        if let Some(mut stderr) = stderr {
+            let _ = stderr.read_to_end(&mut output);
+        }
         output
     });
 
     let mut time_doing_nothing = 0;
     let tarp = loop {
-        // We know tarpaulin won't be immediately done so lets just sleep at the start of the loop
-        thread::sleep(Duration::new(10, 0));
+        // We know tarpaulin won't be immediately done so lets just wait at the start of the loop
+        let waiting = Instant::now();
+        while waiting.elapsed() < STALL_CHECK_INTERVAL
+            && context.shutdown.requested() != Shutdown::Now
+            && !matches!(tarp.try_wait(), Ok(Some(_)))
+        {
+            thread::sleep(WAIT_POLL_INTERVAL);
+        }
+        if context.shutdown.requested() == Shutdown::Now && !matches!(tarp.try_wait(), Ok(Some(_)))
+        {
+            warn!("Stopping tarpaulin as ctrl-c was pressed again");
+            if context.in_container(proj) {
+                docker::kill_container(proj_name);
+            }
+            reap_tarpaulin(&tarp_tree);
+            let _ = tarp.kill();
+            let _ = tarp.wait();
+            events(RunEvent::TarpaulinExited {
+                name: proj_name.to_string(),
+                exit_code: None,
+            });
+            outcome.timings.tarpaulin = tarpaulin_start.elapsed().as_secs_f64();
+            // The teardown and cleanup guards run as this returns
+            return Err(RunError::Interrupted);
+        }
         match tarp.try_wait() {
             Ok(Some(status)) => break status,
             Ok(None) => {
-                // Check the CPU level
-                if let Some(proc) = system.process(tarp.id() as _) {
-                    if proc.cpu_usage() < 0.1 {
+                // Check the CPU level, the docker client or cross don't do any of the work so we
+                // have to ask docker how the container is doing
+                let cpu_usage = if context.in_container(proj) {
+                    docker::cpu_usage(proj_name)
+                } else {
+                    tarp_tree.cpu_usage(&mut system)
+                };
+                if let Some(cpu_usage) = cpu_usage {
+                    if cpu_usage < 0.1 {
                         time_doing_nothing += 1;
                     } else {
                         time_doing_nothing = 0;
@@ -175,7 +2488,17 @@ pub fn run_test(
                     // If we've sampled < 0.1% CPU utilisation for a minute we should just give up
                     if time_doing_nothing > 5 {
                         error!("Stalled, killing");
+                        if context.in_container(proj) {
+                            docker::kill_container(proj_name);
+                        }
+                        reap_tarpaulin(&tarp_tree);
                         let _ = tarp.kill();
+                        let _ = tarp.wait();
+                        events(RunEvent::TarpaulinExited {
+                            name: proj_name.to_string(),
+                            exit_code: None,
+                        });
+                        outcome.timings.tarpaulin = tarpaulin_start.elapsed().as_secs_f64();
                         return Err(RunError::Stalled);
                     }
                 }
@@ -189,50 +2512,1121 @@ pub fn run_test(
         };
     };
 
-    if let Some(teardown) = proj.teardown.as_ref() {
-        let res = Command::new("sh")
-            .args(&["-c", teardown])
-            .current_dir(&proj_dir)
-            .output();
-        if let Err(res) = res {
-            warn!("teardown failed for {}: {}", proj_name, res);
-        }
+    outcome.timings.tarpaulin = tarpaulin_start.elapsed().as_secs_f64();
+    outcome.exit_code = tarp.code();
+    outcome.signal = exit_signal(&tarp);
+    if let Some(before) = sccache_before {
+        outcome.sccache = sccache::stats().map(|x| x.since(&before));
     }
-    let _ = remove_dir_all(proj_dir.join("target"));
-    let proj_res = results.join(proj_name);
+    reap_tarpaulin(&tarp_tree);
+    events(RunEvent::TarpaulinExited {
+        name: proj_name.to_string(),
+        exit_code: outcome.exit_code,
+    });
 
-    let stdout = stdout_reading.join().unwrap();
-    let stderr = stderr_reading.join().unwrap();
+    if !tarp.success() && context.baseline_test {
+        outcome.baseline = Some(run_baseline_test(&proj_dir, proj_res, jobs, context, proj));
+    }
 
-    let _ = create_dir(&proj_res);
-    let mut writer =
-        BufWriter::new(File::create(proj_res.join(format!("{}.log", proj_name))).unwrap());
-    writer.write_all(b"stdout:\n").unwrap();
-    writer.write_all(&stdout).unwrap();
-    writer.write_all(b"\n\nstderr:\n").unwrap();
-    writer.write_all(&stderr).unwrap();
+    let teardown_start = Instant::now();
+    if let Err(e) = teardown_guard.run() {
+        error!("{} for {}", e, proj_name);
+        outcome.teardown_error = Some(e);
+    }
+    outcome.timings.teardown = teardown_start.elapsed().as_secs_f64();
 
-    let mut found_log = false;
-    for entry in read_dir(&proj_dir).unwrap() {
-        let entry = entry.unwrap();
-        if let Some(name) = entry.path().file_name() {
-            if name.to_string_lossy().starts_with("tarpaulin-run") {
-                if copy(entry.path(), proj_res.join("tarpaulin-run.json")).is_ok() {
-                    let _ = remove_file(entry.path());
-                    found_log = true;
-                    break;
-                } else {
-                    warn!("Failed to copy log, still in project directory");
-                }
-            }
+    let mut stdout = stdout_reading.join().unwrap();
+    let mut stderr = stderr_reading.join().unwrap();
+    if let Some((stdout_file, stderr_file)) = output_files.as_ref() {
+        stdout = std::fs::read(stdout_file).unwrap_or_default();
+        stderr = std::fs::read(stderr_file).unwrap_or_default();
+    }
+
+    let parsed = parse_coverage(&String::from_utf8_lossy(&stdout));
+    let no_tests = found_no_tests(&String::from_utf8_lossy(&stdout), parsed.as_ref());
+    let coverage = match parsed {
+        Some(coverage) => {
+            info!("{:.2}% coverage", coverage.percentage);
+            CoverageReport::Found(coverage)
+        }
+        None => {
+            warn!("No coverage line found in tarpaulin output");
+            CoverageReport::NoCoverageLineFound
+        }
+    };
+    outcome.coverage = Some(coverage);
+
+    if output_files.is_none() {
+        let mut writer =
+            BufWriter::new(File::create(proj_res.join(format!("{}.log", proj_name))).unwrap());
+        writer.write_all(b"stdout:\n").unwrap();
+        writer.write_all(&stdout).unwrap();
+        writer.write_all(b"\n\nstderr:\n").unwrap();
+        writer.write_all(&stderr).unwrap();
+    }
+
+    let working_dir = proj.working_dir(&proj_dir);
+    let found_log = collect_artifacts(&working_dir, &proj_dir, shared_target.as_deref(), proj_res);
+    if let Ok(report) = std::fs::read_to_string(proj_res.join(JSON_REPORT)) {
+        outcome.report_coverage = parse_json_report(&report);
+        outcome.report_files = json_report_files(&report, &proj_dir);
+        if outcome.report_coverage.is_none() {
+            warn!("Failed to parse {}", JSON_REPORT);
         }
     }
     if !found_log {
-        warn!("Haven't found tarpaulin log file");
+        let files = read_dir(&working_dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|x| x.file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        warn!(
+            "Haven't found tarpaulin log file, {} has: {}",
+            working_dir.display(),
+            files.join(", ")
+        );
     }
-    if tarp.success() {
+    outcome.found_log = found_log;
+    let res = if tarp.success() {
         Ok(())
+    } else if looks_oom_killed(&tarp, &String::from_utf8_lossy(&stderr)) {
+        match kernel_log_oom(&tarp_tree.pids()) {
+            Some(true) => {
+                info!("The kernel log confirms the OOM killer killed tarpaulin or a child")
+            }
+            Some(false) => {}
+            None => debug!("Can't read the kernel log to check for the OOM killer"),
+        }
+        error!("Killed, likely by the OOM killer. Try running with fewer jobs");
+        Err(RunError::OutOfMemory)
     } else {
-        Err(RunError::Failed)
+        let stderr = String::from_utf8_lossy(&stderr);
+        let exit = interpret_exit_status(&tarp);
+        match exit_error(&tarp, &String::from_utf8_lossy(&stdout), &stderr) {
+            RunError::Failed(kind) => {
+                let stdout = String::from_utf8_lossy(&stdout);
+                let kind = match kind {
+                    FailureKind::TestFailure | FailureKind::Unknown
+                        if context.no_network && looks_network_dependent(&stdout, &stderr) =>
+                    {
+                        FailureKind::NetworkDependent
+                    }
+                    kind => kind,
+                };
+                outcome.panic = find_tarpaulin_panic(&stderr);
+                error!(
+                    "Failure looks like {}: {} ({:?})",
+                    kind,
+                    kind.description(),
+                    exit
+                );
+                Err(RunError::Failed(kind))
+            }
+            e => {
+                error!("{}", e);
+                Err(e)
+            }
+        }
+    };
+    // Tarpaulin may or may not fail without tests, either way it says nothing about tarpaulin.
+    // A failure the output explains is kept even if there were no tests
+    let only_no_tests = match &res {
+        Ok(()) => true,
+        Err(RunError::Failed(kind)) => {
+            *kind != FailureKind::NetworkDependent
+                && outcome.panic.is_none()
+                && classify_failure(
+                    &String::from_utf8_lossy(&stdout),
+                    &String::from_utf8_lossy(&stderr),
+                ) == FailureKind::Unknown
+        }
+        Err(_) => false,
+    };
+    if no_tests && only_no_tests {
+        warn!("No tests found, skipping");
+        outcome.no_tests = true;
+        return Ok(());
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::test::{args, test_project, test_spec, TestProject};
+    use std::io::BufReader;
+
+    #[test]
+    fn crate_override() {
+        let root = test_project(
+            "crate-override",
+            &[
+                (
+                    "overrides/tater.toml",
+                    "args = [\"--features\", \"full\"]\nsubdir = \"core\"\n\n[env]\nFOO = \"bar\"\n",
+                ),
+                // Nothing in the project's repository is trusted
+                ("project/overrides/tater.toml", "setup = \"touch pwned\"\n"),
+            ],
+        );
+        let proj_dir = root.join("project");
+        let spec = test_spec();
+        let before = args(&ci::get_command(
+            &proj_dir,
+            None,
+            &Context::default(),
+            &spec,
+        ));
+        assert!(!before.contains(&"full".to_string()));
+        assert!(matches!(
+            with_override(&Context::default(), &spec),
+            Ok(Cow::Borrowed(_))
+        ));
+
+        let overrides = root.join("overrides");
+        let context = Context {
+            overrides_dir: Some(overrides.clone()),
+            ..Default::default()
+        };
+        let spec = with_override(&context, &spec).unwrap();
+        let cmd = ci::get_command(&proj_dir, None, &Context::default(), &spec);
+        assert!(args(&cmd).ends_with(&["--features".to_string(), "full".to_string()]));
+        assert_eq!(cmd.get_current_dir(), Some(proj_dir.join("core").as_path()));
+        assert_eq!(spec.env.get("FOO").map(String::as_str), Some("bar"));
+        assert_eq!(spec.setup, None);
+
+        let file = overrides.join("tater.toml");
+        std::fs::write(&file, "subdir = \"../other\"\n").unwrap();
+        assert!(matches!(
+            with_override(&context, &test_spec()),
+            Err(RunError::Override(_))
+        ));
+        std::fs::write(&file, "subdir = '..\\other'\n").unwrap();
+        assert!(matches!(
+            with_override(&context, &test_spec()),
+            Err(RunError::Override(_))
+        ));
+        std::fs::write(&file, "subdir = 'crates\\core'\n").unwrap();
+        let spec = test_spec();
+        let spec = with_override(&context, &spec).unwrap();
+        assert_eq!(
+            spec.working_dir(&proj_dir),
+            proj_dir.join("crates").join("core")
+        );
+        drop(root);
+        assert!(matches!(
+            with_override(&context, &test_spec()),
+            Ok(Cow::Borrowed(_))
+        ));
+    }
+
+    lazy_static! {
+        /// Held by tests running tarpaulin as a ctrl-c in one would stop the others
+        static ref FULL_RUN: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    }
+
+    /// Sets up a git repo for a crate named foo to clone and a fake tarpaulin running `script`
+    #[cfg(unix)]
+    fn fake_run(name: &str, script: &str) -> (TestProject, Context) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = test_project(
+            name,
+            &[
+                ("upstream/foo/Cargo.toml", "[package]\nname = \"foo\"\n"),
+                ("upstream/foo/src/lib.rs", ""),
+                ("cargo-tarpaulin", &format!("#!/bin/sh\n{}\n", script)),
+            ],
+        );
+        let upstream = root.join("upstream/foo");
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args([
+                    "-c",
+                    "user.name=tater",
+                    "-c",
+                    "user.email=tater@example.com",
+                ])
+                .args(args)
+                .current_dir(&upstream)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "init"]);
+        let tarpaulin = root.join("cargo-tarpaulin");
+        std::fs::set_permissions(&tarpaulin, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let spec: CrateSpec = serde_json::from_value(serde_json::json!({
+            "repository_url": format!("file://{}", upstream.display())
+        }))
+        .unwrap();
+        let context = Context {
+            crates: vec![spec],
+            tarpaulin_path: Some(tarpaulin),
+            ..Default::default()
+        };
+        (root, context)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_events() {
+        use std::cell::RefCell;
+
+        let _lock = FULL_RUN.lock().unwrap_or_else(|e| e.into_inner());
+        let (root, context) = fake_run(
+            "run-events",
+            "echo 'running 1 test'\necho '50.00% coverage, 1/2 lines covered'",
+        );
+        let events = RefCell::new(vec![]);
+        let runs = run_all(&context, None, &root.join("output"), &|e| {
+            events.borrow_mut().push(e)
+        });
+        assert_eq!(runs.len(), 1);
+        assert!(runs[0].outcome.error.is_none(), "{:?}", runs[0].outcome);
+        let name = || "foo".to_string();
+        assert_eq!(
+            events.into_inner(),
+            vec![
+                RunEvent::CrateStarted {
+                    index: 0,
+                    name: name()
+                },
+                RunEvent::Cloned { name: name() },
+                RunEvent::SetupDone { name: name() },
+                RunEvent::TarpaulinExited {
+                    name: name(),
+                    exit_code: Some(0)
+                },
+                RunEvent::Result {
+                    name: name(),
+                    status: Status::Pass,
+                    coverage_pct: Some(50.0)
+                },
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_tests_skipped() {
+        let _lock = FULL_RUN.lock().unwrap_or_else(|e| e.into_inner());
+        let run = |name: &str, script: &str| {
+            let (root, context) = fake_run(name, script);
+            let output = root.join("output");
+            std::fs::create_dir_all(&output).unwrap();
+            let outcome = run_test(
+                0,
+                &context,
+                &context.crates[0],
+                None,
+                &output,
+                &output.join("foo-results"),
+            );
+            drop(root);
+            outcome
+        };
+        let outcome = run("no-tests-pass", "echo 'running 0 tests'");
+        assert_eq!(outcome.skip_reason(), Some(SkipReason::NoTests));
+        // Tarpaulin may exit with an error when there's nothing to cover
+        let outcome = run("no-tests-exit", "echo 'running 0 tests'\nexit 1");
+        assert_eq!(outcome.skip_reason(), Some(SkipReason::NoTests));
+
+        let outcome = run(
+            "no-tests-failed",
+            "echo 'running 0 tests'\necho 'test result: FAILED. 0 passed; 1 failed'\nexit 1",
+        );
+        assert_eq!(outcome.status(), Status::Fail);
+        assert!(!outcome.no_tests);
+        let outcome = run(
+            "no-tests-panic",
+            "echo 'running 0 tests'\necho \"thread 'main' panicked at 'oops', src/cargo_tarpaulin/report.rs:1:1\" >&2\nexit 101",
+        );
+        assert!(
+            matches!(
+                outcome.error,
+                Some(RunError::Failed(FailureKind::TarpaulinPanic))
+            ),
+            "{:?}",
+            outcome.error
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn double_ctrl_c_stops_crate() {
+        let _lock = FULL_RUN.lock().unwrap_or_else(|e| e.into_inner());
+        let pid_dir = test_project("ctrl-c-pid", &[]);
+        std::fs::create_dir_all(&pid_dir).unwrap();
+        let pid_file = pid_dir.join("pid");
+        let (root, mut context) = fake_run(
+            "ctrl-c",
+            &format!(
+                "sleep 600 >/dev/null &\necho $! > {}\nwait",
+                pid_file.display()
+            ),
+        );
+        context.crates[0].teardown = Some("touch torn-down".to_string());
+        let output = root.join("output");
+        std::fs::create_dir_all(&output).unwrap();
+
+        let outcome = thread::scope(|s| {
+            let run = s.spawn(|| {
+                run_test(
+                    0,
+                    &context,
+                    &context.crates[0],
+                    None,
+                    &output,
+                    &output.join("foo-results"),
+                )
+            });
+            // Wait for tarpaulin to start its child before pressing ctrl-c twice
+            while !pid_file.exists() && !run.is_finished() {
+                thread::sleep(Duration::from_millis(50));
+            }
+            assert_eq!(context.shutdown.request(), Shutdown::AfterCrate);
+            assert_eq!(context.shutdown.request(), Shutdown::Now);
+            run.join().unwrap()
+        });
+        assert!(
+            matches!(outcome.error, Some(RunError::Interrupted)),
+            "{:?}",
+            outcome
+        );
+        assert!(output.join("foo/torn-down").exists());
+
+        let child = std::fs::read_to_string(&pid_file).unwrap();
+        let stat = Path::new("/proc").join(child.trim()).join("stat");
+        let dead = (0..50).any(|_| {
+            thread::sleep(Duration::from_millis(100));
+            std::fs::read_to_string(&stat)
+                .map(|x| x.contains(") Z "))
+                .unwrap_or(true)
+        });
+        assert!(dead);
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let repos = r#"{
+            "toolchain": "+nightly",
+            "target": null,
+            "crates": [
+                { "repository_url": "https://github.com/foo/bar" },
+                { "repository_url": "https://gitlab.com/baz/bar" },
+                { "repository_url": "https://github.com/foo/bar/" }
+            ]
+        }"#;
+
+        let err = load_context(repos.as_bytes()).unwrap_err();
+        assert_eq!(err.0.len(), 2);
+        assert!(err.0[0].starts_with("crate 1: same project directory 'bar'"));
+        assert!(err.0[1].starts_with("crate 2: duplicate of crate 0"));
+    }
+
+    #[test]
+    fn coverage_line() {
+        let output = "Jan 01 00:00:00.000  INFO cargo_tarpaulin::report: Coverage Results:
+|| Tested/Total Lines:
+|| src/lib.rs: 10/20
+||
+50.00% coverage, 10/20 lines covered
+|| src/main.rs: 5/5
+||
+60.00% coverage, 15/25 lines covered, +10.00% change in coverage
+";
+        assert_eq!(
+            parse_coverage(output),
+            Some(Coverage {
+                percentage: 60.0,
+                covered: 15,
+                coverable: 25
+            })
+        );
+        assert_eq!(parse_coverage("error: could not compile `foo`"), None);
+    }
+
+    #[test]
+    fn json_report() {
+        let report = r#"{
+            "files": [
+                { "path": ["src", "lib.rs"], "content": "", "traces": [], "covered": 3, "coverable": 4 },
+                { "path": ["src", "main.rs"], "content": "", "traces": [], "covered": 0, "coverable": 4 }
+            ],
+            "coverage": 37.5,
+            "covered": 3,
+            "coverable": 8
+        }"#;
+        let expected = Coverage {
+            percentage: 37.5,
+            covered: 3,
+            coverable: 8,
+        };
+        assert_eq!(parse_json_report(report), Some(expected.clone()));
+        // Older reports only have totals for each file
+        let legacy: serde_json::Value = serde_json::from_str(report).unwrap();
+        let legacy = serde_json::json!({ "files": legacy["files"] });
+        assert_eq!(parse_json_report(&legacy.to_string()), Some(expected));
+        assert_eq!(parse_json_report("<html></html>"), None);
+
+        let root = Path::new("/projects/foo");
+        let absolute = report.replace(r#"["src","#, r#"["/", "projects", "foo", "src","#);
+        assert_ne!(absolute, report);
+        let files = vec!["src/lib.rs".to_string(), "src/main.rs".to_string()];
+        assert_eq!(json_report_files(&absolute, root), Some(files.clone()));
+        // Ran in a container with the project mounted elsewhere
+        let mounted = report.replace(r#"["src","#, r#"["/", "project", "src","#);
+        assert_eq!(json_report_files(&mounted, root), Some(files.clone()));
+        assert_eq!(json_report_files(report, root), Some(files));
+    }
+
+    #[test]
+    fn notes_round_trip() {
+        let repos = r#"{
+            "toolchain": "+nightly",
+            "target": null,
+            "notes": "Crates known to break tarpaulin",
+            "crates": [
+                { "repository_url": "https://github.com/xd009642/tater", "notes": "Flaky upstream" }
+            ]
+        }"#;
+
+        let context = load_context(repos.as_bytes()).unwrap();
+        let json = serde_json::to_string(&context).unwrap();
+        let context = load_context(json.as_bytes()).unwrap();
+        assert_eq!(
+            context.notes.as_deref(),
+            Some("Crates known to break tarpaulin")
+        );
+        assert_eq!(context.crates[0].notes.as_deref(), Some("Flaky upstream"));
+    }
+
+    #[test]
+    fn tarpaulin_panics() {
+        let stderr = "Jan 01 00:00:00.000  INFO cargo_tarpaulin: Running Tarpaulin
+thread 'main' panicked at 'assertion failed: pid > 0', /cargo/registry/src/cargo-tarpaulin-0.18.5/src/statemachine/linux.rs:100:9
+stack backtrace:
+   0: rust_begin_unwind
+             at /rustc/abc/library/std/src/panicking.rs:498:5
+   1: cargo_tarpaulin::statemachine::linux::LinuxData::wait
+   2: main
+note: Some details are omitted, run with `RUST_BACKTRACE=full` for a verbose backtrace.
+";
+        let panic = find_tarpaulin_panic(stderr).unwrap();
+        assert_eq!(panic.message, "assertion failed: pid > 0");
+        assert_eq!(
+            panic.location.as_deref(),
+            Some("/cargo/registry/src/cargo-tarpaulin-0.18.5/src/statemachine/linux.rs:100:9")
+        );
+        assert_eq!(
+            panic.frames,
+            vec![
+                "rust_begin_unwind at /rustc/abc/library/std/src/panicking.rs:498:5",
+                "cargo_tarpaulin::statemachine::linux::LinuxData::wait",
+                "main"
+            ]
+        );
+
+        let stderr = "thread 'main' panicked at src/main.rs:2:5:
+explicit panic
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+thread 'main' panicked at src/statemachine.rs:10:5:
+bad state
+stack backtrace:
+   0: cargo_tarpaulin::launch_tarpaulin
+";
+        let panic = find_tarpaulin_panic(stderr).unwrap();
+        assert_eq!(panic.message, "bad state");
+        assert_eq!(panic.location.as_deref(), Some("src/statemachine.rs:10:5"));
+
+        assert_eq!(find_tarpaulin_panic("test result: FAILED"), None);
+    }
+
+    #[test]
+    fn script_shell() {
+        let mut context = Context::default();
+        let mut spec: CrateSpec =
+            serde_json::from_str(r#"{ "repository_url": "https://github.com/xd009642/tater" }"#)
+                .unwrap();
+        let cmd = script_command(&context.shell_for(&spec), "echo hi").unwrap();
+        if cfg!(windows) {
+            assert_eq!(cmd.get_program(), default_shell()[0].as_str());
+        } else {
+            assert_eq!(cmd.get_program(), "sh");
+            assert_eq!(cmd.get_args().collect::<Vec<_>>(), ["-c", "echo hi"]);
+        }
+
+        context.shell = Some(vec!["bash".to_string(), "-c".to_string()]);
+        let cmd = script_command(&context.shell_for(&spec), "echo hi").unwrap();
+        assert_eq!(cmd.get_program(), "bash");
+
+        spec.shell = Some(vec!["powershell".to_string(), "-Command".to_string()]);
+        let cmd = script_command(&context.shell_for(&spec), "echo hi").unwrap();
+        assert_eq!(cmd.get_program(), "powershell");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), ["-Command", "echo hi"]);
+        assert!(script_command(&[], "echo hi").is_err());
+        assert!(script_command(&["".to_string()], "echo hi").is_err());
+
+        assert_eq!(windows_shell(|_| false), ["cmd", "/C"]);
+        assert_eq!(
+            windows_shell(|x| x == "powershell"),
+            ["powershell", "-NoProfile", "-NonInteractive", "-Command"]
+        );
+        assert_eq!(windows_shell(|_| true)[0], "pwsh");
+    }
+
+    #[test]
+    fn script_timeout() {
+        let dir = std::env::temp_dir();
+        let start = Instant::now();
+        let res = run_script(
+            &default_shell(),
+            "sleep 30",
+            &dir,
+            Some(Duration::from_secs(1)),
+            None,
+        );
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn orphans_reaped() {
+        let dir = test_project("orphans", &[]);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cmd = script_command(&default_shell(), "sleep 600 >/dev/null & echo $!").unwrap();
+        cmd.current_dir(&dir).stdout(Stdio::piped());
+        own_process_group(&mut cmd);
+        let output = cmd.spawn().unwrap();
+        let tree = ProcessTree::new(&output);
+        let output = output.wait_with_output().unwrap();
+        let orphan = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        assert!(Path::new("/proc").join(&orphan).exists());
+
+        // Something else running in the project directory isn't tarpaulin's to kill
+        let mut bystander = Command::new("sleep")
+            .arg("600")
+            .current_dir(&dir)
+            .spawn()
+            .unwrap();
+
+        let reaped = reap_orphans(&tree);
+        assert!(!reaped.is_empty());
+        assert!(process_dies(&orphan));
+        thread::sleep(Duration::from_millis(100));
+        assert!(bystander.try_wait().unwrap().is_none());
+        let _ = bystander.kill();
+        let _ = bystander.wait();
+    }
+
+    /// Once killed a process is either gone or a zombie waiting for its parent to reap it
+    #[cfg(unix)]
+    fn process_dies(pid: &str) -> bool {
+        let stat = Path::new("/proc").join(pid).join("stat");
+        (0..50).any(|_| {
+            thread::sleep(Duration::from_millis(100));
+            std::fs::read_to_string(&stat)
+                .map(|x| x.contains(") Z "))
+                .unwrap_or(true)
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tree_cpu_usage() {
+        // The shell only waits, all the work happens in its child
+        let mut child = script_command(&default_shell(), "(while :; do :; done) & wait")
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let mut tree = ProcessTree::new(&child);
+        let mut system = System::new();
+        // A process's usage is only known from its second sample
+        thread::sleep(Duration::from_millis(200));
+        tree.cpu_usage(&mut system);
+        thread::sleep(Duration::from_millis(500));
+        let usage = tree.cpu_usage(&mut system);
+        tree.kill();
+        let _ = child.wait();
+        assert!(usage.unwrap() > 10.0, "{:?}", usage);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn process_tree_killed() {
+        let mut cmd =
+            script_command(&default_shell(), "sleep 600 >/dev/null & echo $!; wait").unwrap();
+        cmd.stdout(Stdio::piped());
+        own_process_group(&mut cmd);
+        let mut child = cmd.spawn().unwrap();
+        let tree = ProcessTree::new(&child);
+        let mut grandchild = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut grandchild)
+            .unwrap();
+        let grandchild = grandchild.trim();
+        assert!(Path::new("/proc").join(grandchild).exists());
+
+        assert!(tree.kill());
+        let _ = child.wait();
+        assert!(process_dies(grandchild));
+        assert!(process_dies(&tree.pid.to_string()));
+    }
+
+    #[test]
+    fn output_redirected() {
+        let dir = test_project("redirect", &[]);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cmd = script_command(&default_shell(), "echo out && echo err >&2").unwrap();
+        let (stdout, stderr) = redirect_output(&mut cmd, &dir).unwrap();
+        let output = cmd.output().unwrap();
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+        assert_eq!(std::fs::read_to_string(stdout).unwrap().trim(), "out");
+        assert_eq!(std::fs::read_to_string(stderr).unwrap().trim(), "err");
+    }
+
+    #[test]
+    fn target_cleaned_up() {
+        fn run(proj_dir: &Path, keep: bool, fail: bool) -> Result<(), RunError> {
+            let _guard = ProjectCleanupGuard {
+                proj_dir,
+                keep,
+                shared_target: None,
+            };
+            std::fs::create_dir_all(proj_dir.join("target/debug")).unwrap();
+            if fail {
+                return Err(RunError::Stalled);
+            }
+            Ok(())
+        }
+        let dir = test_project("cleanup", &[]);
+
+        assert!(run(&dir, false, false).is_ok());
+        assert!(!dir.join("target").exists());
+        assert!(run(&dir, false, true).is_err());
+        assert!(!dir.join("target").exists());
+        assert!(run(&dir, true, true).is_err());
+        assert!(dir.join("target").exists());
+
+        // Only tarpaulin's directory is removed from a shared target
+        let shared = dir.join("shared");
+        std::fs::create_dir_all(shared.join("debug")).unwrap();
+        std::fs::create_dir_all(shared.join("tarpaulin")).unwrap();
+        drop(ProjectCleanupGuard {
+            proj_dir: &dir,
+            keep: false,
+            shared_target: Some(&shared),
+        });
+        assert!(shared.join("debug").exists());
+        assert!(!shared.join("tarpaulin").exists());
+    }
+
+    #[test]
+    fn newest_log_found() {
+        let dir = test_project("logs", &[]);
+        std::fs::create_dir_all(dir.join("target/tarpaulin")).unwrap();
+        std::fs::create_dir_all(dir.join("src/nested")).unwrap();
+        let now = SystemTime::now();
+        let files = [
+            ("tarpaulin-run-1.json", 30),
+            ("target/tarpaulin/tarpaulin-run-2.json", 10),
+            ("tarpaulin-run-3.json", 20),
+            ("src/nested/tarpaulin-run-4.json", 0),
+            ("tarpaulin-report.json", 0),
+        ];
+        for (file, age) in files.iter() {
+            let f = File::create(dir.join(file)).unwrap();
+            f.set_modified(now - Duration::from_secs(*age)).unwrap();
+        }
+
+        let logs = find_tarpaulin_logs(&dir)
+            .into_iter()
+            .map(|(path, _)| path.strip_prefix(&dir).unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            logs,
+            [
+                Path::new("target/tarpaulin/tarpaulin-run-2.json"),
+                Path::new("tarpaulin-run-3.json"),
+                Path::new("tarpaulin-run-1.json"),
+            ]
+        );
+    }
+
+    #[test]
+    fn artifacts_collected() {
+        let root = test_project(
+            "artifacts",
+            &[
+                ("tarpaulin-run-1.json", "{}"),
+                ("sub/tarpaulin-run-2.json", "{}"),
+                ("sub/lcov.info", "TN:"),
+                ("sub/cobertura.xml", "<coverage/>"),
+                ("target/tarpaulin/profraws/foo.profraw", ""),
+                ("target/debug/foo", ""),
+            ],
+        );
+        let res = root.join("results");
+        std::fs::create_dir_all(&res).unwrap();
+
+        assert!(collect_artifacts(&root.join("sub"), &root, None, &res));
+        for file in &[
+            "tarpaulin-run-1.json",
+            "tarpaulin-run-2.json",
+            "lcov.info",
+            "cobertura.xml",
+            "target-tarpaulin/profraws/foo.profraw",
+        ] {
+            assert!(res.join(file).is_file(), "{} wasn't collected", file);
+        }
+        assert!(!root.join("tarpaulin-run-1.json").exists());
+        assert!(!res.join("target-tarpaulin/debug").exists());
+        // The logs were moved so they aren't found again
+        assert!(!collect_artifacts(&root.join("sub"), &root, None, &res));
+    }
+
+    #[test]
+    fn teardown_failure() {
+        let dir = test_project("teardown", &[]);
+        let _ = std::fs::create_dir_all(&dir);
+        let shell = default_shell();
+        let guard = TeardownGuard {
+            shell: &shell,
+            timeout: None,
+            proj_dir: &dir,
+            proj_res: &dir,
+            teardown: Some("echo stopping; exit 3"),
+        };
+
+        assert!(matches!(guard.run(), Err(RunError::Teardown(_))));
+        let log = std::fs::read_to_string(dir.join("teardown.log")).unwrap();
+        assert!(log.contains("stopping"));
+    }
+
+    #[test]
+    fn no_tests_detected() {
+        let stdout = "Jan 01 00:00:00.000  INFO cargo_tarpaulin: Running Tarpaulin
+Jan 01 00:00:00.000  INFO cargo_tarpaulin::process_handling: running /proj/target/debug/deps/foo-1234
+
+running 0 tests
+
+test result: ok. 0 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; finished in 0.00s
+
+Jan 01 00:00:00.000  INFO cargo_tarpaulin::report: Coverage Results:
+|| Tested/Total Lines:
+|| src/main.rs: 0/12
+||
+0.00% coverage, 0/12 lines covered
+";
+        let coverage = parse_coverage(stdout);
+        assert!(found_no_tests(stdout, coverage.as_ref()));
+        let empty = Coverage {
+            percentage: 0.0,
+            covered: 0,
+            coverable: 0,
+        };
+        assert!(found_no_tests("", Some(&empty)));
+
+        let some_tests = stdout.replacen("running 0 tests", "running 0 tests\n\nrunning 1 test", 1);
+        assert!(!found_no_tests(&some_tests, coverage.as_ref()));
+        assert!(!found_no_tests("error[E0425]: cannot find value `x`", None));
+
+        let outcome = RunOutcome {
+            no_tests: true,
+            ..Default::default()
+        };
+        assert_eq!(outcome.status(), Status::Skipped);
+        assert_eq!(outcome.skip_reason(), Some(SkipReason::NoTests));
+    }
+
+    #[test]
+    fn manifest_located() {
+        let root = test_project("manifest", &[]);
+        let manifest = |dir: &str, contents: &str| {
+            create_dir_all(root.join(dir)).unwrap();
+            std::fs::write(root.join(dir).join("Cargo.toml"), contents).unwrap();
+        };
+        let spec: CrateSpec =
+            serde_json::from_str(r#"{ "repository_url": "https://github.com/foo/bar" }"#).unwrap();
+        let subdir = |spec: Option<Cow<'_, CrateSpec>>| spec.map(|x| x.subdir.clone());
+
+        // Nothing to run tarpaulin on
+        create_dir_all(root.join("docs")).unwrap();
+        assert_eq!(find_manifest_dir(&root), None);
+        assert_eq!(subdir(with_manifest_dir(&root, Cow::Borrowed(&spec))), None);
+        let outcome = RunOutcome {
+            not_rust: true,
+            ..Default::default()
+        };
+        assert_eq!(outcome.status(), Status::Skipped);
+        assert_eq!(outcome.skip_reason(), Some(SkipReason::NotRust));
+
+        // Only a workspace is looked for below the root
+        manifest("bindings", "[package]\nname = \"bindings\"\n");
+        assert_eq!(find_manifest_dir(&root), None);
+        manifest("rust", "[workspace]\nmembers = [\"core\"]\n");
+        assert_eq!(
+            subdir(with_manifest_dir(&root, Cow::Borrowed(&spec))),
+            Some(Some("rust".to_string()))
+        );
+
+        manifest("", "[package]\nname = \"bar\"\n");
+        assert_eq!(
+            subdir(with_manifest_dir(&root, Cow::Borrowed(&spec))),
+            Some(None)
+        );
+    }
+
+    #[test]
+    fn failure_kinds() {
+        assert_eq!(
+            classify_failure("", "error[E0425]: cannot find value `x` in this scope"),
+            FailureKind::CompileError
+        );
+        assert_eq!(
+            classify_failure("test result: FAILED. 1 passed; 1 failed", ""),
+            FailureKind::TestFailure
+        );
+        assert_eq!(
+            classify_failure(
+                "test result: FAILED. 1 passed; 1 failed",
+                "thread 'main' panicked at 'oops', /home/.cargo/registry/src/cargo-tarpaulin-0.18.5/src/lib.rs:10:5"
+            ),
+            FailureKind::TarpaulinPanic
+        );
+        assert_eq!(
+            classify_failure("", "error: linking with `cc` failed: exit status: 1"),
+            FailureKind::Infra
+        );
+        assert_eq!(
+            classify_failure("", "error: internal compiler error: unexpected panic"),
+            FailureKind::Infra
+        );
+        assert_eq!(classify_failure("", ""), FailureKind::Unknown);
+        assert_eq!(
+            classify_failure(
+                "test result: ok. 2 passed; 0 failed",
+                "Error: \"Coverage is below the failure threshold 50.00% < 80.00%\""
+            ),
+            FailureKind::CoverageError
+        );
+        assert!(looks_network_dependent(
+            "---- client::fetches_page stdout ----\nthread 'client::fetches_page' panicked at \
+             'called `Result::unwrap()` on an `Err` value: Os { code: 111, kind: \
+             ConnectionRefused, message: \"Connection refused\" }'",
+            ""
+        ));
+        assert!(looks_network_dependent(
+            "",
+            "error sending request for url (https://example.com/): error trying to connect: dns \
+             error: failed to lookup address information: Temporary failure in name resolution"
+        ));
+        assert!(!looks_network_dependent(
+            "test result: FAILED. 1 passed; 1 failed",
+            ""
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn oom_kills_detected() {
+        use std::os::unix::process::ExitStatusExt;
+
+        assert!(looks_oom_killed(&ExitStatus::from_raw(9), ""));
+        let failed = ExitStatus::from_raw(101 << 8);
+        assert!(looks_oom_killed(
+            &failed,
+            "error: test failed, to rerun pass `--lib`\n\nCaused by:\n  process didn't exit \
+             successfully: `target/debug/deps/foo-1234` (signal: 9, SIGKILL: kill)"
+        ));
+        assert!(looks_oom_killed(&failed, "Killed\n"));
+        assert!(!looks_oom_killed(&failed, "test killed_process ... FAILED"));
+        assert!(!looks_oom_killed(&ExitStatus::from_raw(15), ""));
+        assert_eq!(RunError::OutOfMemory.label(), "out_of_memory");
+        assert!(!RunError::OutOfMemory.retryable());
+    }
+
+    #[test]
+    fn kernel_log_oom_kills() {
+        let log = "[Mon Oct  5 10:00:00 2026] Out of memory: Killed process 4321 (foo-1234) \
+                   total-vm:1234kB, anon-rss:1234kB\n\
+                   [Mon Oct  5 10:00:01 2026] Killed process 43 (bar) total-vm:1kB\n";
+        assert!(oom_killed_any(log, &[1, 4321]));
+        assert!(oom_killed_any(log, &[43]));
+        assert!(!oom_killed_any(log, &[432, 1234]));
+        assert!(!oom_killed_any(log, &[] as &[u32]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exit_codes_interpreted() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let status = |code: i32| ExitStatus::from_raw(code << 8);
+        assert_eq!(
+            interpret_exit_status(&status(0)),
+            TarpaulinExitCode::Success
+        );
+        assert_eq!(
+            interpret_exit_status(&status(1)),
+            TarpaulinExitCode::TestFailure
+        );
+        assert_eq!(
+            interpret_exit_status(&status(2)),
+            TarpaulinExitCode::BuildFailure
+        );
+        assert_eq!(
+            interpret_exit_status(&status(101)),
+            TarpaulinExitCode::Panic
+        );
+        assert_eq!(
+            interpret_exit_status(&status(3)),
+            TarpaulinExitCode::Other(3)
+        );
+        // SIGKILL
+        assert_eq!(
+            interpret_exit_status(&ExitStatus::from_raw(9)),
+            TarpaulinExitCode::Signal(Some(9))
+        );
+
+        // The output is more specific than the exit code
+        assert_eq!(
+            failure_kind(
+                TarpaulinExitCode::BuildFailure,
+                "",
+                "error: linking with `cc` failed"
+            ),
+            FailureKind::Infra
+        );
+        assert_eq!(
+            failure_kind(TarpaulinExitCode::BuildFailure, "", ""),
+            FailureKind::CompileError
+        );
+        assert_eq!(
+            failure_kind(TarpaulinExitCode::Other(3), "", ""),
+            FailureKind::Unknown
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn crashes_kept_apart() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let failed = ExitStatus::from_raw(1 << 8);
+        assert!(matches!(
+            exit_error(&failed, "test result: FAILED", ""),
+            RunError::Failed(FailureKind::TestFailure)
+        ));
+        let panicked = ExitStatus::from_raw(101 << 8);
+        assert!(matches!(
+            exit_error(&panicked, "", ""),
+            RunError::Failed(FailureKind::TarpaulinPanic)
+        ));
+        let other = ExitStatus::from_raw(3 << 8);
+        assert!(matches!(
+            exit_error(&other, "", ""),
+            RunError::Failed(FailureKind::Unknown)
+        ));
+        assert_eq!(exit_signal(&other), None);
+
+        let segfault = ExitStatus::from_raw(libc::SIGSEGV);
+        assert_eq!(exit_signal(&segfault), Some(libc::SIGSEGV));
+        let crashed = exit_error(&segfault, "", "");
+        assert!(matches!(crashed, RunError::Crashed(Some(libc::SIGSEGV))));
+        assert_eq!(crashed.label(), "crashed");
+        assert_eq!(
+            crashed.to_string(),
+            format!("Tarpaulin crashed, killed by signal {}", libc::SIGSEGV)
+        );
+        assert!(!crashed.retryable());
+    }
+
+    #[test]
+    fn retryable_errors() {
+        assert!(RunError::Stalled.retryable());
+        assert!(RunError::Git(GitError::System("clone failed".to_string())).retryable());
+        assert!(RunError::Setup(io::Error::new(io::ErrorKind::TimedOut, "timeout")).retryable());
+        assert!(!RunError::Setup(io::Error::other("exited with 1")).retryable());
+        assert!(!RunError::Failed(FailureKind::CompileError).retryable());
+    }
+
+    #[test]
+    fn outcome_status() {
+        let mut outcome = RunOutcome::default();
+        assert_eq!(outcome.status(), Status::Pass);
+        outcome.error = Some(RunError::Stalled);
+        assert_eq!(outcome.status(), Status::Stalled);
+        outcome.error = Some(RunError::Setup(io::ErrorKind::TimedOut.into()));
+        assert_eq!(outcome.status(), Status::Timeout);
+        outcome.error = Some(RunError::Setup(io::ErrorKind::NotFound.into()));
+        assert_eq!(outcome.status(), Status::Fail);
+        outcome.error = Some(RunError::Failed(FailureKind::Unknown));
+        assert_eq!(outcome.status(), Status::Fail);
+    }
+
+    #[test]
+    fn empty_crates() {
+        let repos = r#"{ "toolchain": "+nightly", "target": null, "crates": [] }"#;
+
+        let err = load_context(repos.as_bytes()).unwrap_err();
+        assert_eq!(err.0, vec!["No crates listed".to_string()]);
+    }
+
+    #[test]
+    fn empty_shells() {
+        let repos = r#"{
+            "toolchain": "+nightly",
+            "shell": [],
+            "crates": [
+                { "repository_url": "https://github.com/foo/bar", "shell": [""] },
+                { "repository_url": "https://github.com/foo/baz", "shell": ["bash", "-c"] }
+            ]
+        }"#;
+
+        let err = load_context(repos.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.0,
+            vec!["shell can't be empty", "crate 0: shell can't be empty"]
+        );
+    }
+
+    #[test]
+    fn reports_all_bad_crates() {
+        let repos = r#"{
+            "toolchain": "+nightly",
+            "target": null,
+            "crates": [
+                { "repository_url": "not a url" },
+                { "repository_url": "https://github.com/foo/bar", "setup": "echo \"hi" },
+                { "args": [] }
+            ]
+        }"#;
+
+        let err = load_context(repos.as_bytes()).unwrap_err();
+        assert_eq!(err.0.len(), 3);
+        assert!(err.0[0].starts_with("crate 0:"));
+        assert_eq!(err.0[1], "crate 1: setup script has an unclosed quote");
+        assert!(err.0[2].starts_with("crate 2:"));
+
+        let repos = r#"{
+            "toolchain": 1,
+            "crates": [
+                { "repository_url": "https://github.com/foo/bar" },
+                { "repository_url": "https://github.com/foo/bar" }
+            ]
+        }"#;
+        let err = load_context(repos.as_bytes()).unwrap_err();
+        assert_eq!(err.0.len(), 2);
+        assert!(err.0[0].starts_with("invalid type"));
+        assert!(err.0[1].starts_with("crate 1: duplicate of crate 0"));
     }
 }
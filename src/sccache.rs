@@ -0,0 +1,160 @@
+use crate::runner::{new_command, Context, Engine};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use tracing::{debug, info, warn};
+
+const SCCACHE: &str = "sccache";
+
+/// sccache's counters, taken before and after a project to see how much of its build was cached
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SccacheStats {
+    pub compile_requests: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+impl SccacheStats {
+    /// What changed since `before`, the counters only go down if the server restarted
+    pub fn since(&self, before: &Self) -> Self {
+        Self {
+            compile_requests: self
+                .compile_requests
+                .saturating_sub(before.compile_requests),
+            cache_hits: self.cache_hits.saturating_sub(before.cache_hits),
+            cache_misses: self.cache_misses.saturating_sub(before.cache_misses),
+        }
+    }
+}
+
+/// Gets the totals from the output of `sccache --show-stats`, the per language breakdowns newer
+/// versions print are ignored
+pub fn parse_stats(output: &str) -> Option<SccacheStats> {
+    lazy_static! {
+        static ref STAT: Regex =
+            Regex::new(r"(?m)^(Compile requests|Cache hits|Cache misses)\s+(\d+)\s*$").unwrap();
+    }
+    let mut stats = SccacheStats::default();
+    let mut found = false;
+    for cap in STAT.captures_iter(output) {
+        let value = cap[2].parse().ok()?;
+        match &cap[1] {
+            "Compile requests" => stats.compile_requests = value,
+            "Cache hits" => stats.cache_hits = value,
+            _ => stats.cache_misses = value,
+        }
+        found = true;
+    }
+    if found {
+        Some(stats)
+    } else {
+        None
+    }
+}
+
+/// The current counters of the sccache server, `None` if they can't be read
+pub fn stats() -> Option<SccacheStats> {
+    let output = new_command(SCCACHE)
+        .arg("--show-stats")
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|x| x.status.success())?;
+    parse_stats(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The sccache server for a run, it's stopped when this is dropped if tater started it
+pub struct Server {
+    started: bool,
+}
+
+impl Server {
+    /// Starts the server if the context uses sccache. An error if sccache isn't installed as every
+    /// project would fail to build
+    pub fn start_for(context: &Context) -> Result<Option<Self>, String> {
+        if !context.sccache || context.engine != Engine::Native {
+            return Ok(None);
+        }
+        let version = new_command(SCCACHE)
+            .arg("--version")
+            .output()
+            .ok()
+            .filter(|x| x.status.success())
+            .ok_or_else(|| {
+                "sccache isn't installed, install it with `cargo install sccache`".to_string()
+            })?;
+        info!("Using {}", String::from_utf8_lossy(&version.stdout).trim());
+        // Fails if a server is already running, that one's left running at the end
+        let started = new_command(SCCACHE)
+            .arg("--start-server")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|x| x.success())
+            .unwrap_or(false);
+        if !started {
+            debug!("sccache server already running");
+        }
+        Ok(Some(Self { started }))
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if !self.started {
+            return;
+        }
+        let stopped = new_command(SCCACHE)
+            .arg("--stop-server")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        if !matches!(stopped, Ok(x) if x.success()) {
+            warn!("Failed to stop the sccache server");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_parsed() {
+        let before = "\
+Compile requests                     12
+Compile requests executed             8
+Cache hits                            5
+Cache hits (Rust)                     5
+Cache misses                          3
+Cache misses (Rust)                   3
+Cache timeouts                        0
+";
+        let before = parse_stats(before).unwrap();
+        assert_eq!(
+            before,
+            SccacheStats {
+                compile_requests: 12,
+                cache_hits: 5,
+                cache_misses: 3,
+            }
+        );
+        let after = SccacheStats {
+            compile_requests: 40,
+            cache_hits: 30,
+            cache_misses: 5,
+        };
+        assert_eq!(
+            after.since(&before),
+            SccacheStats {
+                compile_requests: 28,
+                cache_hits: 25,
+                cache_misses: 2,
+            }
+        );
+        // The server was restarted part way through
+        assert_eq!(before.since(&after), SccacheStats::default());
+        assert_eq!(parse_stats("error: couldn't connect to server"), None);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::report::*;
+use crate::runner::*;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, read_dir};
+use std::path::Path;
+
+/// Summary of an output directory, read without touching anything so it's safe to use while
+/// tater is running
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StatusSummary {
+    pub pass: usize,
+    pub fail: usize,
+    /// Projects which ran but have no tests
+    pub skipped: usize,
+    /// Projects in the repos file which haven't been ran, `None` without a repos file
+    pub remaining: Option<usize>,
+    /// The project with the most recent result
+    pub last: Option<String>,
+    /// Coverage percentages of the projects tarpaulin reported coverage for
+    pub coverage: Vec<f64>,
+}
+
+/// Names in a pass, fail or skipped file
+fn read_names(path: &Path) -> Vec<String> {
+    read_status_file(path, "")
+        .into_iter()
+        .map(|x| x.name)
+        .collect()
+}
+
+fn newest_result(results: &Path) -> Option<String> {
+    read_dir(results)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| {
+            let modified = fs::metadata(entry.path().join(RESULT_FILE))
+                .and_then(|x| x.modified())
+                .ok()?;
+            Some((modified, entry.file_name().to_string_lossy().to_string()))
+        })
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, name)| name)
+}
+
+impl StatusSummary {
+    /// Reads the summary from an output directory, if the context is given it's used to work out
+    /// how many projects are left to run
+    pub fn read(output: &Path, context: Option<&Context>) -> Self {
+        let run = RunDirs::latest(output);
+        let passed = read_names(&run.status.join("pass"));
+        let failed = read_names(&run.status.join("fail"));
+        let skipped = read_names(&run.status.join("skipped"));
+        let results = run.results;
+        let remaining = context.map(|context| {
+            let ran = passed
+                .iter()
+                .chain(&failed)
+                .chain(&skipped)
+                .collect::<HashSet<_>>();
+            context
+                .crates
+                .iter()
+                .filter(|x| !x.skip && x.expected != Expectation::Skip)
+                .filter_map(|x| x.name())
+                .filter(|x| !ran.contains(&x.to_string()))
+                .count()
+        });
+        let coverage = read_all_results(&results)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|x| match x.coverage {
+                Some(CoverageReport::Found(c)) => Some(c.percentage),
+                _ => None,
+            })
+            .collect();
+        Self {
+            pass: passed.len(),
+            fail: failed.len(),
+            skipped: skipped.len(),
+            remaining,
+            last: newest_result(&results),
+            coverage,
+        }
+    }
+}
+
+impl fmt::Display for StatusSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pass: {}, Fail: {}", self.pass, self.fail)?;
+        if self.skipped > 0 {
+            write!(f, ", Skipped: {}", self.skipped)?;
+        }
+        if let Some(remaining) = self.remaining {
+            write!(f, ", Remaining: {}", remaining)?;
+        }
+        if let Some(last) = self.last.as_ref() {
+            write!(f, ", Last: {}", last)?;
+        }
+        if !self.coverage.is_empty() {
+            let mean = self.coverage.iter().sum::<f64>() / self.coverage.len() as f64;
+            let min = self.coverage.iter().cloned().fold(f64::INFINITY, f64::min);
+            let max = self
+                .coverage
+                .iter()
+                .cloned()
+                .fold(f64::NEG_INFINITY, f64::max);
+            write!(
+                f,
+                "\nCoverage: mean {:.2}%, min {:.2}%, max {:.2}%",
+                mean, min, max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the summary of the output directory to stdout
+pub fn print_status(output: &Path, context: Option<&Context>) {
+    println!("{}", StatusSummary::read(output, context));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_project;
+
+    #[test]
+    fn status_line() {
+        let output = test_project("status-line", &[]);
+        for (name, coverage) in [("a", 50.0), ("b", 100.0)] {
+            let dir = output.join("results").join(name);
+            fs::create_dir_all(&dir).unwrap();
+            let result = format!(
+                r#"{{"status":"pass","coverage":{{"found":{{"percentage":{},"covered":1,"coverable":2}}}}}}"#,
+                coverage
+            );
+            fs::write(dir.join(RESULT_FILE), result).unwrap();
+        }
+        fs::write(output.join("pass"), "a\nb\n").unwrap();
+        fs::write(output.join("fail"), "c\tcompile_error\n").unwrap();
+        let context: Context = serde_json::from_str(
+            r#"{
+                "toolchain": "stable",
+                "crates": [
+                    { "repository_url": "https://github.com/foo/a" },
+                    { "repository_url": "https://github.com/foo/b" },
+                    { "repository_url": "https://github.com/foo/c" },
+                    { "repository_url": "https://github.com/foo/d" },
+                    { "repository_url": "https://github.com/foo/e", "expected": "skip" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let summary = StatusSummary::read(&output, Some(&context));
+        assert_eq!(summary.pass, 2);
+        assert_eq!(summary.fail, 1);
+        assert_eq!(summary.remaining, Some(1));
+        assert!(summary.last.is_some());
+        let last = summary.last.clone().unwrap();
+        assert_eq!(
+            summary.to_string(),
+            format!(
+                "Pass: 2, Fail: 1, Remaining: 1, Last: {}\nCoverage: mean 75.00%, min 50.00%, max 100.00%",
+                last
+            )
+        );
+        assert_eq!(
+            StatusSummary::read(&output, None)
+                .to_string()
+                .lines()
+                .next(),
+            Some(format!("Pass: 2, Fail: 1, Last: {}", last).as_str())
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//! Helpers shared by the unit tests in the library and the binary
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A directory made by [`test_project`], removed when dropped so it's cleaned up even when a test
+/// fails
+pub(crate) struct TestProject(PathBuf);
+
+impl Deref for TestProject {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestProject {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestProject {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Creates a project directory in the temp dir containing the given files. With no files the
+/// directory itself isn't created
+pub(crate) fn test_project(name: &str, files: &[(&str, &str)]) -> TestProject {
+    let root = std::env::temp_dir().join(format!("tater-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    for (path, contents) in files {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    TestProject(root)
+}
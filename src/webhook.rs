@@ -0,0 +1,59 @@
+use serde::Serialize;
+use tracing::warn;
+
+/// Header containing the HMAC-SHA256 signature of the payload when a secret is given
+#[cfg(feature = "remote")]
+const SIGNATURE_HEADER: &str = "X-Tater-Signature";
+
+/// Signs the payload with HMAC-SHA256 giving the hex encoded signature prefixed with `sha256=`
+#[cfg(feature = "remote")]
+pub fn sign(secret: &str, payload: &[u8]) -> String {
+    use hmac::{Hmac, Mac};
+    let mut mac =
+        Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key size");
+    mac.update(payload);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Posts the payload as JSON to the webhook. Failures are only warned about so they don't affect
+/// the result of the run
+#[cfg(feature = "remote")]
+pub fn post(url: &str, secret: Option<&str>, payload: &impl Serialize) {
+    let body = match serde_json::to_vec(payload) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Unable to serialize webhook payload: {}", e);
+            return;
+        }
+    };
+    let mut request = ureq::post(url).set("Content-Type", "application/json");
+    if let Some(secret) = secret {
+        request = request.set(SIGNATURE_HEADER, &sign(secret, &body));
+    }
+    match request.send_bytes(&body) {
+        Ok(_) => tracing::info!("Posted summary to {}", url),
+        Err(e) => warn!("Failed to post summary to {}: {}", url, e),
+    }
+}
+
+#[cfg(not(feature = "remote"))]
+pub fn post(url: &str, _secret: Option<&str>, _payload: &impl Serialize) {
+    warn!(
+        "Can't post summary to {} as tater was built without the `remote` feature",
+        url
+    );
+}
+
+#[cfg(all(test, feature = "remote"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature() {
+        // Test case 2 from RFC 4231
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
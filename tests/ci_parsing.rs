@@ -0,0 +1,92 @@
+//! Runs the CI parsers over configs taken from real projects, found in `tests/fixtures`, and
+//! checks the tarpaulin command made from them
+mod common;
+
+use common::fixture;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tater::ci::{github, gitlab, travis, ProviderInput};
+use tater::{Context, CrateSpec};
+
+type Provider = fn(&ProviderInput) -> io::Result<Command>;
+
+fn args(cmd: &Command) -> Vec<String> {
+    cmd.get_args()
+        .map(|x| x.to_string_lossy().to_string())
+        .collect()
+}
+
+/// What a provider made from the fixture
+struct Parsed {
+    /// Arguments on top of the ones every command starts with
+    args: Vec<String>,
+    dir: Option<PathBuf>,
+}
+
+fn parse(provider: Provider, root: &Path) -> Parsed {
+    let context = Context::default();
+    let spec: CrateSpec =
+        serde_json::from_str(r#"{ "repository_url": "https://github.com/xd009642/tater" }"#)
+            .unwrap();
+    let input = ProviderInput {
+        root,
+        jobs: None,
+        context: &context,
+        spec: &spec,
+    };
+    let base = args(&input.command());
+    let cmd = provider(&input).unwrap();
+    let args = args(&cmd);
+    assert!(
+        args.starts_with(&base),
+        "{:?} doesn't start with {:?}",
+        args,
+        base
+    );
+    Parsed {
+        args: args[base.len()..].to_vec(),
+        dir: cmd.get_current_dir().map(Path::to_path_buf),
+    }
+}
+
+#[test]
+fn github_matrix_include() {
+    // hyper passes its features to `actions-rs/cargo` from the matrix's `include`
+    let root = fixture("hyper");
+    let parsed = parse(github::get_command, &root);
+    assert_eq!(parsed.args, ["--features", "full"]);
+    assert_eq!(parsed.dir.as_deref(), Some(&*root));
+}
+
+#[test]
+fn github_matrix_run() {
+    // indicatif runs `cargo test ${{ matrix.features }}`, next to jobs for cross, msrv and lints
+    let root = fixture("indicatif");
+    let parsed = parse(github::get_command, &root);
+    assert_eq!(parsed.args, ["--all-features"]);
+}
+
+#[test]
+fn github_default_working_directory() {
+    let root = fixture("openmls");
+    let parsed = parse(github::get_command, &root);
+    assert_eq!(parsed.args, ["--verbose"]);
+    assert_eq!(parsed.dir, Some(root.join("openmls")));
+}
+
+#[test]
+fn travis_script() {
+    // The coverage job in `jobs.include` isn't used, only the top level script is
+    let root = fixture("clap");
+    let parsed = parse(travis::get_command, &root);
+    assert_eq!(parsed.args, ["--no-default-features"]);
+}
+
+#[test]
+fn gitlab_anchors() {
+    // All of vcpkg's jobs share one script, written once as a YAML anchor
+    let root = fixture("vcpkg");
+    let parsed = parse(gitlab::get_command, &root);
+    assert_eq!(parsed.args, ["--all", "--features=extras"]);
+}
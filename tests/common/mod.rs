@@ -0,0 +1,10 @@
+//! Helpers shared by the integration tests and the benchmarks
+use std::path::{Path, PathBuf};
+
+/// Path to a fixture project in `tests/fixtures`. The CI parsers only read the project so the
+/// fixtures are used in place
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
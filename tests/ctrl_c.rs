@@ -0,0 +1,151 @@
+//! Runs the tater binary and presses ctrl-c while a crate is being set up
+#![cfg(unix)]
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args([
+            "-c",
+            "user.name=tater",
+            "-c",
+            "user.email=tater@example.com",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// A git repo with an empty crate and a fake tarpaulin that passes it
+fn setup_crate(root: &Path) -> (PathBuf, PathBuf) {
+    let _ = fs::remove_dir_all(root);
+    let upstream = root.join("upstream/foo");
+    fs::create_dir_all(upstream.join("src")).unwrap();
+    fs::write(upstream.join("Cargo.toml"), "[package]\nname = \"foo\"\n").unwrap();
+    fs::write(upstream.join("src/lib.rs"), "").unwrap();
+    git(&upstream, &["init", "-q"]);
+    git(&upstream, &["add", "."]);
+    git(&upstream, &["commit", "-q", "-m", "init"]);
+
+    let tarpaulin = root.join("cargo-tarpaulin");
+    fs::write(
+        &tarpaulin,
+        "#!/bin/sh\n\
+         if [ \"$1\" = \"--version\" ]; then echo 'cargo-tarpaulin 0.27.0'; exit 0; fi\n\
+         echo 'running 1 test'\n\
+         echo '50.00% coverage, 1/2 lines covered'\n",
+    )
+    .unwrap();
+    fs::set_permissions(&tarpaulin, fs::Permissions::from_mode(0o755)).unwrap();
+    (upstream, tarpaulin)
+}
+
+/// Starts tater in its own process group so the test can press ctrl-c in it
+fn spawn_tater(root: &Path, tarpaulin: &Path, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_tater"))
+        .arg("--input")
+        .arg(root.join("repos.json"))
+        .arg("--output")
+        .arg(root.join("output"))
+        .arg("--tarpaulin-path")
+        .arg(tarpaulin)
+        .arg("--flat-results")
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .unwrap()
+}
+
+fn wait_for(path: &Path) {
+    let start = Instant::now();
+    while !path.exists() {
+        assert!(start.elapsed() < Duration::from_secs(60), "setup never ran");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn ctrl_c(tater: &Child) {
+    unsafe {
+        libc::killpg(tater.id() as libc::pid_t, libc::SIGINT);
+    }
+}
+
+/// A ctrl-c in the terminal goes to the whole foreground process group. Tater should finish the
+/// crate it's on before pausing, so the setup script mustn't get the signal too
+#[test]
+fn ctrl_c_during_setup() {
+    let root = std::env::temp_dir().join(format!("tater-ctrl-c-{}", std::process::id()));
+    let (upstream, tarpaulin) = setup_crate(&root);
+    let started = root.join("setup-started");
+    let finished = root.join("setup-finished");
+    let repos = serde_json::json!({
+        "toolchain": "",
+        "crates": [{
+            "repository_url": format!("file://{}", upstream.display()),
+            "setup": format!("touch {} && sleep 2 && touch {}", started.display(), finished.display()),
+        }]
+    });
+    fs::write(root.join("repos.json"), repos.to_string()).unwrap();
+
+    let mut tater = spawn_tater(&root, &tarpaulin, &[]);
+    wait_for(&started);
+    ctrl_c(&tater);
+    tater.wait().unwrap();
+
+    assert!(finished.exists(), "setup was killed by the ctrl-c");
+    let pass = fs::read_to_string(root.join("output/pass")).unwrap_or_default();
+    assert!(pass.lines().any(|x| x.contains("foo")), "{}", pass);
+    let _ = fs::remove_dir_all(&root);
+}
+
+/// Pressing ctrl-c twice while a crate is being retried stops it, it's paused to be ran again
+/// when resuming rather than failing
+#[test]
+fn ctrl_c_during_retry() {
+    let root = std::env::temp_dir().join(format!("tater-ctrl-c-retry-{}", std::process::id()));
+    let (upstream, tarpaulin) = setup_crate(&root);
+    // The first setup times out so it's retried, the retry hangs until it's stopped
+    let attempted = root.join("setup-attempted");
+    let retrying = root.join("setup-retrying");
+    let setup = format!(
+        "if [ -e {0} ]; then touch {1} && sleep 30; else touch {0} && sleep 30; fi",
+        attempted.display(),
+        retrying.display()
+    );
+    let repos = serde_json::json!({
+        "toolchain": "",
+        "crates": [{
+            "repository_url": format!("file://{}", upstream.display()),
+            "setup": setup,
+            "setup_timeout_seconds": 1,
+        }]
+    });
+    fs::write(root.join("repos.json"), repos.to_string()).unwrap();
+
+    let mut tater = spawn_tater(&root, &tarpaulin, &["--retry-failures-at-end"]);
+    wait_for(&retrying);
+    ctrl_c(&tater);
+    thread::sleep(Duration::from_millis(500));
+    ctrl_c(&tater);
+    let status = tater.wait().unwrap();
+
+    assert_eq!(status.code(), Some(130));
+    let output = root.join("output");
+    let fail = fs::read_to_string(output.join("fail")).unwrap_or_default();
+    assert!(!fail.lines().any(|x| x.contains("foo")), "{}", fail);
+    let paused = fs::read_to_string(output.join("paused")).unwrap();
+    assert!(paused.lines().any(|x| x.contains("foo")), "{}", paused);
+    let progress: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(output.join("progress")).unwrap()).unwrap();
+    assert_eq!(progress["next"], 0);
+    let _ = fs::remove_dir_all(&root);
+}